@@ -3,8 +3,10 @@ use std::env;
 use std::net::IpAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use clap::ArgMatches;
+use web3::types::Address;
 
 /// The default IP address that the OME will listen on
 pub const DEFAULT_IP: &str = "0.0.0.0";
@@ -15,6 +17,21 @@ pub const DEFAULT_PORT: &str = "8989";
 /// The default file path for reading and writing state dumps
 pub const DEFAULT_DUMPFILE: &str = ".omedump.json";
 
+/// The default number of seconds between state dumps
+pub const DEFAULT_SNAPSHOT_INTERVAL: &str = "60";
+
+/// The default number of previous state dumps to keep
+pub const DEFAULT_SNAPSHOT_GENERATIONS: &str = "3";
+
+/// The default number of seconds between sweeps for expired orders
+pub const DEFAULT_EXPIRY_INTERVAL: &str = "1";
+
+/// The default file path for the write-ahead journal
+pub const DEFAULT_JOURNAL: &str = ".omejournal";
+
+/// The default file path for the outbox of matches awaiting the executioner
+pub const DEFAULT_OUTBOX: &str = ".omeoutbox";
+
 pub const DEFAULT_EXECUTIONER: &str = "http://localhost:3000";
 
 pub const DEFAULT_CERTFILE: &str = "cert.pem";
@@ -28,9 +45,15 @@ pub struct Arguments {
     pub listen_port: u16,
     pub executioner_address: String,
     pub dumpfile_path: PathBuf,
+    pub snapshot_interval: Duration,
+    pub snapshot_generations: usize,
+    pub journal_path: PathBuf,
+    pub outbox_path: PathBuf,
+    pub expiry_interval: Duration,
     pub certificate_path: PathBuf,
     pub private_key_path: PathBuf,
     pub force_no_tls: bool,
+    pub operator: Option<Address>, /* may set markets' domain separators */
 }
 
 impl TryFrom<ArgMatches<'_>> for Arguments {
@@ -42,9 +65,20 @@ impl TryFrom<ArgMatches<'_>> for Arguments {
         let mut listen_port: u16 = DEFAULT_PORT.parse::<u16>().unwrap();
         let mut executioner_address: String = DEFAULT_EXECUTIONER.to_string();
         let mut dumpfile_path: PathBuf = DEFAULT_DUMPFILE.into();
+        let mut snapshot_interval: Duration = Duration::from_secs(
+            DEFAULT_SNAPSHOT_INTERVAL.parse::<u64>().unwrap(),
+        );
+        let mut snapshot_generations: usize =
+            DEFAULT_SNAPSHOT_GENERATIONS.parse::<usize>().unwrap();
+        let mut journal_path: PathBuf = DEFAULT_JOURNAL.into();
+        let mut outbox_path: PathBuf = DEFAULT_OUTBOX.into();
+        let mut expiry_interval: Duration = Duration::from_secs(
+            DEFAULT_EXPIRY_INTERVAL.parse::<u64>().unwrap(),
+        );
         let mut certificate_path: PathBuf = DEFAULT_CERTFILE.into();
         let mut private_key_path: PathBuf = DEFAULT_KEYFILE.into();
         let mut force_no_tls: bool = DEFAULT_TLS_TOGGLE;
+        let mut operator: Option<Address> = None;
 
         /* handle listening address */
         if let Some(t) = value.value_of("listen") {
@@ -91,9 +125,86 @@ impl TryFrom<ArgMatches<'_>> for Arguments {
         }
 
         /* handle dumpfile path */
-        if let Some(t) = value.value_of("dumpfile_path") {
+        if let Some(t) = value.value_of("dumpfile") {
             dumpfile_path = t.into();
-        };
+        } else {
+            match env::var("OME_DUMPFILE_PATH") {
+                Ok(t) => dumpfile_path = t.into(),
+                Err(_e) => {}
+            }
+        }
+
+        /* handle snapshot interval */
+        if let Some(t) = value.value_of("snapshot_interval") {
+            snapshot_interval = match t.parse::<u64>() {
+                Ok(p) if p > 0 => Duration::from_secs(p),
+                _ => return Err("Invalid snapshot interval"),
+            };
+        } else {
+            match env::var("OME_SNAPSHOT_INTERVAL") {
+                Ok(t) => match t.parse::<u64>() {
+                    Ok(p) if p > 0 => {
+                        snapshot_interval = Duration::from_secs(p)
+                    }
+                    _ => return Err("Invalid snapshot interval"),
+                },
+                Err(_e) => {}
+            }
+        }
+
+        /* handle snapshot generations */
+        if let Some(t) = value.value_of("snapshot_generations") {
+            snapshot_generations = match t.parse::<usize>() {
+                Ok(p) => p,
+                Err(_e) => return Err("Invalid snapshot generation count"),
+            };
+        } else {
+            match env::var("OME_SNAPSHOT_GENERATIONS") {
+                Ok(t) => match t.parse::<usize>() {
+                    Ok(p) => snapshot_generations = p,
+                    Err(_err) => {
+                        return Err("Invalid snapshot generation count")
+                    }
+                },
+                Err(_e) => {}
+            }
+        }
+
+        /* handle journal path */
+        if let Some(t) = value.value_of("journal") {
+            journal_path = t.into();
+        } else {
+            match env::var("OME_JOURNAL_PATH") {
+                Ok(t) => journal_path = t.into(),
+                Err(_e) => {}
+            }
+        }
+
+        /* handle outbox path */
+        if let Some(t) = value.value_of("outbox") {
+            outbox_path = t.into();
+        } else {
+            match env::var("OME_OUTBOX_PATH") {
+                Ok(t) => outbox_path = t.into(),
+                Err(_e) => {}
+            }
+        }
+
+        /* handle expiry interval */
+        if let Some(t) = value.value_of("expiry_interval") {
+            expiry_interval = match t.parse::<u64>() {
+                Ok(p) if p > 0 => Duration::from_secs(p),
+                _ => return Err("Invalid expiry interval"),
+            };
+        } else {
+            match env::var("OME_EXPIRY_INTERVAL") {
+                Ok(t) => match t.parse::<u64>() {
+                    Ok(p) if p > 0 => expiry_interval = Duration::from_secs(p),
+                    _ => return Err("Invalid expiry interval"),
+                },
+                Err(_e) => {}
+            }
+        }
 
         /* handle TLS certificate path */
         if let Some(t) = value.value_of("certificate_path") {
@@ -125,14 +236,41 @@ impl TryFrom<ArgMatches<'_>> for Arguments {
             }
         }
 
+        /* handle operator address */
+        if let Some(t) = value.value_of("operator") {
+            operator = match Address::from_str(t.trim_start_matches("0x")) {
+                Ok(p) => Some(p),
+                Err(_e) => return Err("Invalid operator address"),
+            };
+        } else {
+            match env::var("OME_OPERATOR_ADDRESS") {
+                Ok(t) => {
+                    operator =
+                        match Address::from_str(t.trim_start_matches("0x")) {
+                            Ok(p) => Some(p),
+                            Err(_err) => {
+                                return Err("Invalid operator address")
+                            }
+                        }
+                }
+                Err(_e) => {}
+            }
+        }
+
         Ok(Self {
             listen_address,
             listen_port,
             executioner_address,
             dumpfile_path,
+            snapshot_interval,
+            snapshot_generations,
+            journal_path,
+            outbox_path,
+            expiry_interval,
             certificate_path,
             private_key_path,
             force_no_tls,
+            operator,
         })
     }
 }
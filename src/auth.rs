@@ -0,0 +1,100 @@
+//! Contains logic for authenticating requests made on behalf of a trader
+//!
+//! Requests that act on behalf of a single trader (e.g., subscribing to their
+//! order events, cancelling their orders, sending their heartbeats, or seeing
+//! the hidden reserve of their iceberg orders) are authenticated by the
+//! trader signing, as an EIP-191 personal message, a challenge naming the
+//! action, their address and the current time (see `challenge`). Signatures
+//! are only accepted for a short while after the time they name, so that a
+//! leaked signature soon becomes useless. As each challenge names its action,
+//! a signature for one action never authenticates another.
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use web3::types::{Address, H256};
+
+use crate::signature::{self, SignatureError};
+
+/// The number of seconds either side of its timestamp that a signed
+/// challenge is accepted for
+pub const AUTH_WINDOW: i64 = 60;
+
+#[derive(Clone, Copy, Debug, Display, Error, PartialEq, Eq)]
+pub enum AuthError {
+    StaleTimestamp,
+    InvalidSignature,
+}
+
+impl From<SignatureError> for AuthError {
+    fn from(_error: SignatureError) -> Self {
+        AuthError::InvalidSignature
+    }
+}
+
+/// Represents an action a trader may authenticate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Subscribe, /* to their order events */
+    Cancel,    /* many of their orders at once */
+    Heartbeat {
+        timeout: u64,
+    }, /* naming its timeout, e.g. zero to disarm */
+    Inspect,   /* their orders, hidden reserve included */
+    Configure {
+        market: Address,
+        domain_separator: H256,
+    }, /* a market's domain separator, by the operator alone */
+}
+
+/// Returns the challenge `trader` must sign to take `action` at `timestamp`
+pub fn challenge(
+    action: Action,
+    trader: Address,
+    timestamp: DateTime<Utc>,
+) -> String {
+    let request: String = match action {
+        Action::Subscribe => {
+            format!("Subscribe to order events of {:#x}", trader)
+        }
+        Action::Cancel => format!("Cancel orders of {:#x}", trader),
+        Action::Inspect => format!("Inspect orders of {:#x}", trader),
+        Action::Configure {
+            market,
+            domain_separator,
+        } => format!(
+            "Set domain separator of {:#x} to {:#x} as {:#x}",
+            market, domain_separator, trader
+        ),
+        Action::Heartbeat { timeout } => {
+            format!("Heartbeat of {:#x} with timeout {}", trader, timeout)
+        }
+    };
+
+    format!("{}\nTimestamp: {}", request, timestamp.timestamp())
+}
+
+/// Checks that `signature` is the signature by `trader` of their challenge
+/// to take `action` at `timestamp`, and that `timestamp` is recent as of
+/// `now`
+pub fn authenticate(
+    action: Action,
+    trader: Address,
+    timestamp: DateTime<Utc>,
+    signature: &[u8],
+    now: DateTime<Utc>,
+) -> Result<(), AuthError> {
+    if (now - timestamp).num_seconds().abs() > AUTH_WINDOW {
+        return Err(AuthError::StaleTimestamp);
+    }
+
+    let digest: H256 = signature::personal_digest(
+        challenge(action, trader, timestamp).as_bytes(),
+    );
+
+    if signature::recover_signer(digest, signature)? == trader {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidSignature)
+    }
+}
@@ -0,0 +1,320 @@
+//! A stand-in for the executioner, so that the OME can be developed and tested
+//! offline
+//!
+//! Implements the `/check` and `/submit` endpoints called by the OME (see
+//! `rpc`), accepting every order and settling every matched pair with a fake
+//! transaction hash unless configured otherwise. Every matched pair received
+//! is logged, and the log can be retrieved via `GET /submissions`.
+//!
+//! Like the real executioner, pairs resent with the same idempotency key are
+//! answered as they were the first time.
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::{App, Arg, ArgMatches};
+use serde::Serialize;
+use tokio::sync::{Mutex, MutexGuard};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+use web3::types::{Address, H160, H256};
+
+use tracer_ome::order::{ExternalOrder, OrderId};
+use tracer_ome::rpc::{
+    CheckRequest, MatchRequest, RejectionResponse, IDEMPOTENCY_KEY_HEADER,
+};
+
+#[macro_use]
+extern crate log;
+extern crate pretty_env_logger;
+
+pub const DEFAULT_IP: &str = "127.0.0.1";
+pub const DEFAULT_PORT: &str = "3000";
+
+/// Represents how the mock executioner treats the requests it receives
+#[derive(Clone, Debug, Default)]
+struct Behaviour {
+    rejected_traders: HashSet<Address>, /* whose orders are always refused */
+    max_latency: Duration, /* longest random delay before each response */
+    failure_rate: f64,     /* probability of failing a request outright */
+}
+
+impl Behaviour {
+    /// Returns whether `order` belongs to a trader whose orders are refused
+    fn refuses(&self, order: &ExternalOrder) -> bool {
+        match Address::from_str(order.user.trim_start_matches("0x")) {
+            Ok(trader) => self.rejected_traders.contains(&trader),
+            Err(_e) => false,
+        }
+    }
+
+    /// Waits for a random time of up to `max_latency`
+    async fn delay(&self) {
+        let latency: Duration = self.max_latency.mul_f64(random());
+
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+    }
+
+    /// Returns whether to fail the current request (with a server error)
+    fn fails(&self) -> bool {
+        random() < self.failure_rate
+    }
+}
+
+/// Represents a single matched pair received, along with the answer given
+#[derive(Clone, Debug, Serialize)]
+struct Submission {
+    key: Option<String>, /* idempotency key */
+    received: DateTime<Utc>,
+    request: MatchRequest,
+    status: u16,
+    response: String,
+}
+
+/// Represents every matched pair received, and the answer given to each
+/// idempotency key
+#[derive(Clone, Debug, Default)]
+struct Ledger {
+    submissions: Vec<Submission>,
+    answers: HashMap<String, (StatusCode, String)>,
+}
+
+/// Returns a random number in [0, 1)
+fn random() -> f64 {
+    (H256::random().to_low_u64_be() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Route handler for checking the validity of a single order
+async fn check_handler(
+    request: CheckRequest,
+    behaviour: Arc<Behaviour>,
+) -> Result<impl Reply, Infallible> {
+    behaviour.delay().await;
+
+    let status: StatusCode = if behaviour.fails() {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else if behaviour.refuses(&request.order) {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+
+    info!("Checked order {}: {}", request.order.id, status);
+
+    Ok(warp::reply::with_status(warp::reply(), status))
+}
+
+/// Route handler for settling a matched pair of orders
+async fn submit_handler(
+    key: Option<String>,
+    request: MatchRequest,
+    behaviour: Arc<Behaviour>,
+    ledger: Arc<Mutex<Ledger>>,
+) -> Result<Response, Infallible> {
+    behaviour.delay().await;
+
+    let mut ledger: MutexGuard<Ledger> = ledger.lock().await;
+    let answer: Option<(StatusCode, String)> = key
+        .as_ref()
+        .and_then(|key| ledger.answers.get(key))
+        .cloned();
+
+    let (status, response): (StatusCode, String) = match answer {
+        Some(t) => {
+            info!("Resent pair with key {}", key.clone().unwrap_or_default());
+            t
+        }
+        None if behaviour.fails() => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Simulated failure".to_string(),
+        ),
+        None => {
+            /* blame the first order of a refused trader */
+            let offender: Option<OrderId> = [&request.maker, &request.taker]
+                .iter()
+                .find(|order| behaviour.refuses(order))
+                .and_then(|order| {
+                    OrderId::from_str(order.id.trim_start_matches("0x")).ok()
+                });
+
+            let answer: (StatusCode, String) = match offender {
+                Some(offender) => (
+                    StatusCode::BAD_REQUEST,
+                    serde_json::to_string(&RejectionResponse {
+                        offender: Some(offender),
+                    })
+                    .unwrap(),
+                ),
+                None => (StatusCode::OK, format!("{:x}", H160::random())),
+            };
+
+            if let Some(key) = &key {
+                ledger.answers.insert(key.clone(), answer.clone());
+            }
+
+            answer
+        }
+    };
+
+    info!(
+        "Received pair ({}, {}): {} {}",
+        request.maker.id, request.taker.id, status, response
+    );
+
+    ledger.submissions.push(Submission {
+        key,
+        received: Utc::now(),
+        request,
+        status: status.as_u16(),
+        response: response.clone(),
+    });
+
+    Ok(warp::reply::with_status(response, status).into_response())
+}
+
+/// Route handler for listing every matched pair received so far
+async fn submissions_handler(
+    ledger: Arc<Mutex<Ledger>>,
+) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&ledger.lock().await.submissions))
+}
+
+/// Builds the behaviour of the mock executioner from the command line
+fn behaviour(matches: &ArgMatches) -> Result<Behaviour, &'static str> {
+    let mut behaviour: Behaviour = Behaviour::default();
+
+    if let Some(traders) = matches.values_of("reject_trader") {
+        for trader in traders {
+            match Address::from_str(trader.trim_start_matches("0x")) {
+                Ok(t) => behaviour.rejected_traders.insert(t),
+                Err(_e) => return Err("Invalid trader address"),
+            };
+        }
+    }
+
+    if let Some(t) = matches.value_of("max_latency") {
+        behaviour.max_latency = match t.parse::<u64>() {
+            Ok(p) => Duration::from_millis(p),
+            Err(_e) => return Err("Invalid maximum latency"),
+        };
+    }
+
+    if let Some(t) = matches.value_of("failure_rate") {
+        behaviour.failure_rate = match t.parse::<f64>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => p,
+            _ => return Err("Invalid failure rate"),
+        };
+    }
+
+    Ok(behaviour)
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+
+    let matches = App::new("Tracer mock executioner")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("address")
+                .short("l")
+                .long("listen")
+                .value_name("address")
+                .help("The address to listen on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .value_name("port")
+                .help("The TCP port to listen on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reject_trader")
+                .long("reject_trader")
+                .value_name("reject_trader")
+                .help("A trader whose orders and matches are always refused")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("max_latency")
+                .long("max_latency")
+                .value_name("max_latency")
+                .help("The longest random delay, in ms, before each response")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("failure_rate")
+                .long("failure_rate")
+                .value_name("failure_rate")
+                .help("The probability (0 to 1) of failing a request outright")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let address: IpAddr = match IpAddr::from_str(
+        matches.value_of("address").unwrap_or(DEFAULT_IP),
+    ) {
+        Ok(t) => t,
+        Err(_e) => {
+            eprintln!("Invalid listening address");
+            process::exit(1);
+        }
+    };
+    let port: u16 =
+        match matches.value_of("port").unwrap_or(DEFAULT_PORT).parse() {
+            Ok(t) => t,
+            Err(_e) => {
+                eprintln!("Invalid listening port");
+                process::exit(1);
+            }
+        };
+    let behaviour: Arc<Behaviour> = match behaviour(&matches) {
+        Ok(t) => Arc::new(t),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let ledger: Arc<Mutex<Ledger>> = Arc::new(Mutex::new(Ledger::default()));
+
+    info!("Behaving as {:?}", behaviour);
+
+    let check_behaviour: Arc<Behaviour> = behaviour.clone();
+    let submit_behaviour: Arc<Behaviour> = behaviour.clone();
+    let submit_ledger: Arc<Mutex<Ledger>> = ledger.clone();
+    let submissions_ledger: Arc<Mutex<Ledger>> = ledger.clone();
+
+    let check_route = warp::path!("check")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || check_behaviour.clone()))
+        .and_then(check_handler);
+    let submit_route = warp::path!("submit")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
+        .and(warp::body::json())
+        .and(warp::any().map(move || submit_behaviour.clone()))
+        .and(warp::any().map(move || submit_ledger.clone()))
+        .and_then(submit_handler);
+    let submissions_route = warp::path!("submissions")
+        .and(warp::get())
+        .and(warp::any().map(move || submissions_ledger.clone()))
+        .and_then(submissions_handler);
+
+    let routes = check_route.or(submit_route).or(submissions_route);
+
+    warp::serve(routes).run((address, port)).await;
+}
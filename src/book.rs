@@ -3,26 +3,34 @@
 use std::{
     cmp::Ordering,
     collections::{BTreeMap, VecDeque},
+    convert::TryFrom,
     fmt::Display,
 };
 
 use chrono::{DateTime, Utc};
-use ethereum_types::U256;
+use ethabi::Token;
+use ethereum_types::{H256, U256, U512};
 use itertools::Either;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use web3::types::Address;
 
-use crate::order::{ExternalOrder, Order, OrderId, OrderSide};
-use crate::rpc;
+use crate::index::{Location, OrderIndex};
+use crate::level::Level;
+use crate::order::{
+    ExternalOrder, Order, OrderId, OrderSide, OrderType, PostOnly,
+    SelfTradePrevention, TimeInForce,
+};
+use crate::registry::{TerminalOrders, Termination};
+use crate::trigger::TriggerBook;
 use crate::util::{from_hex_de, from_hex_se};
 
 /// Represents an order book for a particular Tracer market
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Book {
     pub market: Address, /* the address of the Tracer market */
-    pub bids: BTreeMap<U256, VecDeque<Order>>, /* buy-side */
-    pub asks: BTreeMap<U256, VecDeque<Order>>, /* sell-side */
+    pub bids: BTreeMap<U256, Level>, /* buy-side */
+    pub asks: BTreeMap<U256, Level>, /* sell-side */
     #[serde(
         serialize_with = "from_hex_se",
         deserialize_with = "from_hex_de",
@@ -33,6 +41,20 @@ pub struct Book {
     pub crossed: bool,   /* is book crossed? */
     #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
     pub spread: U256, /* bid-ask spread */
+    #[serde(default)]
+    pub stops: TriggerBook, /* stop orders awaiting activation */
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>, /* default mode */
+    #[serde(default)]
+    pub domain_separator: Option<H256>, /* orders are signed for, if set */
+    #[serde(skip)]
+    pub index: OrderIndex, /* locations of resting orders, see `reindex` */
+    #[serde(default)]
+    pub trades: TradeHistory, /* most recent trades, see `rollback` */
+    #[serde(default)]
+    pub terminal: TerminalOrders, /* orders filled or cancelled */
+    #[serde(default)]
+    pub matches: u64, /* number of matches ever made, see `Match::number` */
 }
 
 #[derive(
@@ -40,6 +62,7 @@ pub struct Book {
 )]
 pub enum BookError {
     Web3Error,
+    InvalidAmendment,
 }
 
 impl From<web3::Error> for BookError {
@@ -61,8 +84,164 @@ pub enum OrderStatus {
     Add,
     PartialMatch,
     FullMatch,
+    PartialMatchCancelled, /* remainder cancelled rather than stored */
+    Cancelled,             /* nothing matched and nothing stored */
+    Rejected,              /* post-only order would have taken liquidity */
+    Repriced,              /* post-only order stored one tick behind instead */
+    Pending,               /* stop order stored until triggered */
+    Amended,               /* quantity reduced in place, keeping priority */
+}
+
+/// Represents the action taken to prevent a trade between two orders of the
+/// same trader
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SelfTrade {
+    pub mode: SelfTradePrevention,
+    pub resting_id: OrderId, /* the resting order of the same trader */
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub resting_cancelled: U256, /* quantity of it cancelled */
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub incoming_cancelled: U256, /* quantity of the incoming order cancelled */
+}
+
+/// Represents the outcome of submitting an order to the matching engine
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Execution {
+    pub status: OrderStatus,
+    pub filled: U256, /* quantity of the order that was matched */
+    pub average_price: Option<U256>, /* quantity-weighted, if filled */
+    pub self_trades: Vec<SelfTrade>, /* in the order they were prevented */
+}
+
+impl Execution {
+    /// Constructs an execution in which nothing was matched
+    pub fn unfilled(status: OrderStatus) -> Self {
+        Self {
+            status,
+            filled: U256::zero(),
+            average_price: None,
+            self_trades: vec![],
+        }
+    }
+}
+
+pub type MatchId = H256;
+
+/// Represents a single match between an incoming (taker) order and a resting
+/// (maker) order, with each as it stood immediately after the match
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Match {
+    pub maker: Order,
+    pub taker: Order,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub amount: U256,
+    #[serde(default)]
+    pub number: u64, /* of the match among those made by its book */
 }
 
+impl Match {
+    /// Returns the ID of the match, which doubles as the idempotency key of
+    /// its delivery to the executioner
+    ///
+    /// The ID depends only on the orders matched, how much of each remained
+    /// afterwards and the number of the match within its book, so replaying
+    /// the journal derives the same IDs, while orders matched again after a
+    /// rollback never repeat one. Matches made before books numbered them
+    /// (i.e., numbered zero) keep the IDs they were made with.
+    pub fn id(&self) -> MatchId {
+        let mut components: Vec<Token> = vec![
+            Token::FixedBytes(self.maker.id.as_bytes().to_vec()),
+            Token::FixedBytes(self.taker.id.as_bytes().to_vec()),
+            Token::Uint(self.maker.remaining),
+            Token::Uint(self.taker.remaining),
+        ];
+
+        if self.number != 0 {
+            components.push(Token::Uint(self.number.into()));
+        }
+
+        web3::signing::keccak256(&ethabi::encode(&components)).into()
+    }
+}
+
+/// The number of most recent trades each book remembers
+pub const RECENT_TRADES: usize = 1024;
+
+/// Represents a single trade remembered by a book
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Trade {
+    pub id: MatchId,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub prior: U256, /* last traded price before this trade */
+    #[serde(default)]
+    pub maker: OrderId,
+    #[serde(default)]
+    pub taker: OrderId,
+}
+
+/// Represents the most recent trades of a book, oldest first, so that the
+/// last traded price can be recomputed should a trade be rolled back, and so
+/// that orders already filled are not filled again
+#[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TradeHistory {
+    trades: VecDeque<Trade>,
+}
+
+impl TradeHistory {
+    /// Remembers `trade`, forgetting the oldest trade if need be
+    pub fn push(&mut self, trade: Trade) {
+        self.trades.push_back(trade);
+
+        if self.trades.len() > RECENT_TRADES {
+            self.trades.pop_front();
+        }
+    }
+
+    /// Forgets the trade with ID `id`, carrying its prior price over to the
+    /// trade that followed it
+    ///
+    /// Returns the forgotten trade and whether it was the latest trade.
+    pub fn remove(&mut self, id: MatchId) -> Option<(Trade, bool)> {
+        let position: usize =
+            self.trades.iter().position(|trade| trade.id == id)?;
+        let trade: Trade = self.trades.remove(position)?;
+
+        match self.trades.get_mut(position) {
+            Some(next) => {
+                next.prior = trade.prior;
+                Some((trade, false))
+            }
+            None => Some((trade, true)),
+        }
+    }
+
+    /// Returns whether the order with ID `id` took part in any of the trades
+    /// remembered
+    pub fn involves(&self, id: OrderId) -> bool {
+        self.trades
+            .iter()
+            .any(|trade| trade.maker == id || trade.taker == id)
+    }
+}
+
+/// Represents the reconciliation of a book with the executioner's refusal of
+/// one of its matches
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reconciliation {
+    pub matched: Match,
+    pub restored: Vec<OrderId>, /* resting orders given back their quantity */
+    pub cancelled: Option<Order>, /* the offending order, if it was resting */
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub ltp: U256, /* last traded price afterwards */
+}
+
+/// Basis points in 100%
+pub const BPS_DENOMINATOR: u32 = 10_000;
+
 impl Book {
     /// Constructor for the `Book` type
     ///
@@ -77,6 +256,13 @@ impl Book {
             depth: (0, 0),
             crossed: false,
             spread: Default::default(),
+            stops: TriggerBook::new(),
+            self_trade_prevention: None,
+            domain_separator: None,
+            index: OrderIndex::new(),
+            trades: TradeHistory::default(),
+            terminal: TerminalOrders::new(),
+            matches: 0,
         }
     }
 
@@ -85,50 +271,81 @@ impl Book {
         &self.market
     }
 
-    /// Returns a reference to the order matching the provided order ID
-    pub fn order(&self, id: OrderId) -> Option<&Order> {
-        /* search bids */
-        for (_, curr_level) in self.bids.iter() {
-            for curr_order in curr_level.iter() {
-                if curr_order.id == id {
-                    return Some(curr_order);
-                }
-            }
+    /// Returns one side of the order book
+    fn levels(&self, side: OrderSide) -> &BTreeMap<U256, Level> {
+        match side {
+            OrderSide::Bid => &self.bids,
+            OrderSide::Ask => &self.asks,
         }
+    }
 
-        /* search asks */
-        for (_, curr_level) in self.asks.iter() {
-            for curr_order in curr_level.iter() {
-                if curr_order.id == id {
-                    return Some(curr_order);
-                }
-            }
+    /// Returns one side of the order book, mutably
+    fn levels_mut(&mut self, side: OrderSide) -> &mut BTreeMap<U256, Level> {
+        match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
         }
+    }
+
+    /// Returns a reference to the order matching the provided order ID
+    pub fn order(&self, id: OrderId) -> Option<&Order> {
+        let location: Location = self.index.location(id)?;
 
-        None
+        self.levels(location.side)
+            .get(&location.price)?
+            .get(location.position)
     }
 
     /// Returns a mutable reference to the order matching the provided order ID
     pub fn order_mut(&mut self, id: OrderId) -> Option<&mut Order> {
-        /* search bids */
-        for (_, curr_level) in self.bids.iter_mut() {
-            for curr_order in curr_level.iter_mut() {
-                if curr_order.id == id {
-                    return Some(curr_order);
-                }
-            }
-        }
+        let location: Location = self.index.location(id)?;
 
-        /* search asks */
-        for (_, curr_level) in self.asks.iter_mut() {
-            for curr_order in curr_level.iter_mut() {
-                if curr_order.id == id {
-                    return Some(curr_order);
-                }
+        self.levels_mut(location.side)
+            .get_mut(&location.price)?
+            .get_mut(location.position)
+    }
+
+    /// Returns every order of `trader` currently in the order book, bids
+    /// first, each side in ascending order of price and then by priority
+    pub fn orders_of(&self, trader: Address) -> Vec<&Order> {
+        let ids = match self.index.orders_of(trader) {
+            Some(t) => t,
+            None => return vec![],
+        };
+        let mut locations: Vec<Location> = ids
+            .iter()
+            .filter_map(|id| self.index.location(*id))
+            .collect();
+        locations.sort_by_key(|location| {
+            (
+                location.side == OrderSide::Ask,
+                location.price,
+                location.position,
+            )
+        });
+
+        locations
+            .into_iter()
+            .filter_map(|location| {
+                self.levels(location.side)
+                    .get(&location.price)?
+                    .get(location.position)
+            })
+            .collect()
+    }
+
+    /// Rebuilds the indices of the order book from its price levels
+    ///
+    /// Must be called after mutating `bids` or `asks` directly, rather than
+    /// via the methods of the book.
+    pub fn reindex(&mut self) {
+        self.index.clear();
+
+        for level in self.bids.values().chain(self.asks.values()) {
+            for (position, order) in level.positions() {
+                self.index.insert(order, position);
             }
         }
-
-        None
     }
 
     /// Returns the last traded price of the order book
@@ -137,17 +354,19 @@ impl Book {
     }
 
     /// Returns a pair (2-tuple) containing the depths of each side of the book
+    ///
+    /// Only orders with some visible quantity are counted.
     pub fn depth(&self) -> (usize, usize) {
         (
             self.bids
                 .values()
                 .flatten()
-                .filter(|order| !order.remaining.is_zero())
+                .filter(|order| !order.visible().is_zero())
                 .count(),
             self.asks
                 .values()
                 .flatten()
-                .filter(|order| !order.remaining.is_zero())
+                .filter(|order| !order.visible().is_zero())
                 .count(),
         )
     }
@@ -180,34 +399,135 @@ impl Book {
         }
     }
 
+    /// Returns the worst price at which `order` may be matched
+    ///
+    /// For limit orders this is simply their price. Market orders may
+    /// additionally bound their price by a maximum slippage from the top of
+    /// the opposite side of the book.
+    fn limit_price(order: &Order, opposing_top: Option<U256>) -> U256 {
+        let (top, slippage): (U256, u32) =
+            match (order.order_type, opposing_top, order.max_slippage) {
+                (OrderType::Market, Some(top), Some(slippage)) => {
+                    (top, slippage)
+                }
+                _ => return order.price,
+            };
+
+        let delta: U256 = U256::try_from(
+            top.full_mul(U256::from(slippage)) / U512::from(BPS_DENOMINATOR),
+        )
+        .unwrap_or(U256::MAX);
+
+        match order.side {
+            OrderSide::Bid => order.price.min(top.saturating_add(delta)),
+            OrderSide::Ask => order.price.max(top.saturating_sub(delta)),
+        }
+    }
+
+    /// Returns the price one tick behind `opposing_top` on `side`, if any
+    fn behind(opposing_top: U256, side: OrderSide) -> Option<U256> {
+        match side {
+            OrderSide::Bid => opposing_top.checked_sub(U256::one()),
+            OrderSide::Ask => opposing_top.checked_add(U256::one()),
+        }
+    }
+
+    /// Returns the quantity on the opposite side of the book that `order`
+    /// could be matched against at `limit` or better, up to `order.remaining`
+    fn available(
+        opposing_side: &BTreeMap<U256, Level>,
+        order: &Order,
+        limit: U256,
+    ) -> U256 {
+        let levels = match order.side {
+            OrderSide::Bid => Either::Left(opposing_side.iter()),
+            OrderSide::Ask => Either::Right(opposing_side.iter().rev()),
+        };
+        let mut total: U256 = U256::zero();
+
+        for (price, opposites) in levels {
+            if total >= order.remaining
+                || !Book::price_viable(*price, limit, order.side)
+            {
+                break;
+            }
+
+            total = opposites
+                .iter()
+                .filter(|opposite| opposite.trader != order.trader)
+                .fold(total, |acc, opposite| {
+                    acc.saturating_add(opposite.remaining)
+                });
+        }
+
+        total.min(order.remaining)
+    }
+
     #[allow(unused_must_use)]
     async fn r#match(
         &mut self,
         mut order: Order,
-        executioner_address: String,
+        matches: &mut Vec<Match>,
         opposing_top: Option<U256>,
-    ) -> Result<OrderStatus, BookError> {
+    ) -> Result<Execution, BookError> {
         info!("Matching {}...", order);
 
-        let opposing_side: &mut BTreeMap<U256, VecDeque<Order>> =
-            match order.side {
-                OrderSide::Bid => &mut self.asks,
-                OrderSide::Ask => &mut self.bids,
-            };
+        let limit: U256 = Book::limit_price(&order, opposing_top);
+        let self_trade_prevention: Option<SelfTradePrevention> =
+            order.self_trade_prevention.or(self.self_trade_prevention);
+        let opposing_side: &mut BTreeMap<U256, Level> = match order.side {
+            OrderSide::Bid => &mut self.asks,
+            OrderSide::Ask => &mut self.bids,
+        };
+        let initial: U256 = order.remaining;
         let mut running_total: U256 = order.remaining;
+        let mut notional: U512 = U512::zero();
+        let mut cancelled: U256 = U256::zero(); /* by self-trade prevention */
+        let mut self_trades: Vec<SelfTrade> = vec![];
         let mut done: bool = false;
 
         /* if we haven't crossed the spread, we're not going to match */
         if opposing_top.is_none()
-            || !Book::price_viable(
-                opposing_top.unwrap(),
-                order.price,
-                order.side,
-            )
+            || !Book::price_viable(opposing_top.unwrap(), limit, order.side)
         {
+            if !order.rests() {
+                info!("{} does not cross, cancelling...", order);
+                return Ok(Execution::unfilled(OrderStatus::Cancelled));
+            }
+
             info!("{} does not cross, adding...", order);
             self.add_order(order);
-            return Ok(OrderStatus::Add);
+            return Ok(Execution::unfilled(OrderStatus::Add));
+        }
+
+        /* post-only orders must never take liquidity */
+        if let Some(post_only) = order.post_only {
+            let repriced: Option<U256> = match post_only {
+                PostOnly::Reject => None,
+                PostOnly::Reprice => {
+                    Book::behind(opposing_top.unwrap(), order.side)
+                }
+            };
+
+            return Ok(match repriced {
+                Some(price) => {
+                    info!("{} would take liquidity, repricing...", order);
+                    self.add_order(Order { price, ..order });
+                    Execution::unfilled(OrderStatus::Repriced)
+                }
+                None => {
+                    info!("{} would take liquidity, rejecting...", order);
+                    Execution::unfilled(OrderStatus::Rejected)
+                }
+            });
+        }
+
+        /* fill-or-kill orders must not touch the book unless fully matched */
+        if order.time_in_force == TimeInForce::FillOrKill
+            && Book::available(opposing_side, &order, limit) < order.remaining
+        {
+            info!("{} cannot be filled in full, killing...", order);
+            return Ok(Execution::unfilled(OrderStatus::Cancelled));
         }
 
         let opposing_side_iterator = match order.side {
@@ -217,22 +537,83 @@ impl Book {
 
         for (price, opposites) in opposing_side_iterator {
             /* if we've run out of viable prices or we're done, halt */
-            if done || !Book::price_viable(*price, order.price, order.side) {
+            if done || !Book::price_viable(*price, limit, order.side) {
                 break;
             }
 
-            for opposite in opposites {
+            let mut position: u64 = opposites.start();
+
+            while position < opposites.end() {
+                let opposite: &mut Order = match opposites.get_mut(position) {
+                    Some(t) => t,
+                    None => {
+                        position += 1;
+                        continue;
+                    }
+                };
+
                 /* no self-trading allowed */
                 if opposite.trader == order.trader {
-                    info!("Self-trade, skipping...");
+                    let mode: SelfTradePrevention = match self_trade_prevention
+                    {
+                        Some(t) => t,
+                        None => {
+                            info!("Self-trade, skipping...");
+                            position += 1;
+                            continue;
+                        }
+                    };
+                    info!("Self-trade, applying {}...", mode);
+
+                    let (resting_cancelled, incoming_cancelled): (U256, U256) =
+                        match mode {
+                            SelfTradePrevention::CancelNewest => {
+                                (U256::zero(), order.remaining)
+                            }
+                            SelfTradePrevention::CancelOldest => {
+                                (opposite.remaining, U256::zero())
+                            }
+                            SelfTradePrevention::CancelBoth => {
+                                (opposite.remaining, order.remaining)
+                            }
+                            SelfTradePrevention::DecrementAndCancel => {
+                                let decrement: U256 =
+                                    opposite.remaining.min(order.remaining);
+                                (decrement, decrement)
+                            }
+                        };
+
+                    order = Book::fill(order, incoming_cancelled);
+                    *opposite = Book::fill(opposite.clone(), resting_cancelled);
+                    if opposite.remaining.is_zero() {
+                        self.terminal
+                            .record(opposite.id, Termination::Cancelled);
+                    }
+                    self_trades.push(SelfTrade {
+                        mode,
+                        resting_id: opposite.id,
+                        resting_cancelled,
+                        incoming_cancelled,
+                    });
+
+                    running_total -= incoming_cancelled;
+                    cancelled += incoming_cancelled;
+                    position =
+                        Book::refresh(opposites, &mut self.index, position);
+
+                    if running_total.is_zero() {
+                        info!("Self-trade prevention cancelled {}", order);
+                        done = true;
+                        break;
+                    }
                     continue;
                 }
 
-                /* determine how much to match */
+                /* determine how much to match (icebergs only show a slice) */
                 let amount: U256 =
-                    match opposite.remaining.cmp(&order.remaining) {
+                    match opposite.visible().cmp(&order.remaining) {
                         Ordering::Greater => order.remaining,
-                        _ => opposite.remaining,
+                        _ => opposite.visible(),
                     };
                 info!("Matching with amount of {}...", amount);
 
@@ -240,18 +621,30 @@ impl Book {
                 order = Book::fill(order, amount);
                 *opposite = Book::fill(opposite.clone(), amount);
 
+                self.matches += 1;
+                let matched: Match = Match {
+                    maker: opposite.clone(),
+                    taker: order.clone(),
+                    price: *price,
+                    amount,
+                    number: self.matches,
+                };
+                self.trades.push(Trade {
+                    id: matched.id(),
+                    price: *price,
+                    prior: self.ltp,
+                    maker: matched.maker.id,
+                    taker: matched.taker.id,
+                });
+                matches.push(matched);
+
                 self.ltp = *price;
                 info!("LTP updated, is now {}", self.ltp);
 
-                info!("Forwarding {} and {}...", order, opposite);
-                rpc::send_matched_orders(
-                    order.clone(),
-                    opposite.clone(),
-                    executioner_address.clone(),
-                )
-                .await;
-
                 running_total -= amount;
+                notional += price.full_mul(amount);
+
+                position = Book::refresh(opposites, &mut self.index, position);
 
                 /* check if we've totally matched our incoming order */
                 if running_total.is_zero() {
@@ -262,13 +655,67 @@ impl Book {
             }
         }
 
+        let filled: U256 = initial - running_total - cancelled;
+        let average_price: Option<U256> = if filled.is_zero() {
+            None
+        } else {
+            U256::try_from(notional / U512::from(filled)).ok()
+        };
+
         /* if our incoming order has any volume left, add it to the book */
-        if running_total > U256::zero() {
-            self.add_order(order);
-            Ok(OrderStatus::PartialMatch)
+        let status: OrderStatus = if running_total.is_zero() {
+            if cancelled.is_zero() {
+                OrderStatus::FullMatch
+            } else if filled.is_zero() {
+                OrderStatus::Cancelled
+            } else {
+                OrderStatus::PartialMatchCancelled
+            }
+        } else if !order.rests() {
+            info!("Cancelling unmatched remainder of {}", order);
+            if filled.is_zero() {
+                OrderStatus::Cancelled
+            } else {
+                OrderStatus::PartialMatchCancelled
+            }
         } else {
-            Ok(OrderStatus::FullMatch)
+            self.add_order(order);
+            OrderStatus::PartialMatch
+        };
+
+        Ok(Execution {
+            status,
+            filled,
+            average_price,
+            self_trades,
+        })
+    }
+
+    /// Refreshes the order at `position` of `level` if it is an iceberg order
+    /// whose visible slice has been exhausted, moving it to the back of the
+    /// level (and updating `index` to match)
+    ///
+    /// Returns the position of the next order in the level to match against.
+    fn refresh(
+        level: &mut Level,
+        index: &mut OrderIndex,
+        position: u64,
+    ) -> u64 {
+        if let Some(order) = level.get_mut(position) {
+            if order.visible().is_zero() && !order.remaining.is_zero() {
+                order.replenish();
+                info!("Replenished {}", order);
+
+                if let Some(replenished) = level.remove(position) {
+                    let back: u64 = level.push_back(replenished);
+                    if let Some(order) = level.get(back) {
+                        index.insert(order, back);
+                    }
+                }
+            }
         }
+
+        position + 1
     }
 
     fn fill(order: Order, amount: U256) -> Order {
@@ -276,27 +723,19 @@ impl Book {
         match amount.cmp(&order.remaining) {
             Ordering::Greater => order,
             _ => Order {
-                id: order.id,
-                trader: order.trader,
-                market: order.market,
-                side: order.side,
-                price: order.price,
-                quantity: order.quantity,
                 remaining: order.remaining - amount,
-                expiration: order.expiration,
-                created: order.created,
-                signed_data: order.signed_data,
+                displayed: order.displayed.saturating_sub(amount),
+                ..order
             },
         }
     }
 
     fn prune(&mut self) {
-        for (_price, orders) in self.bids.iter_mut() {
-            orders.retain(|order| !order.remaining.is_zero());
-        }
-
-        for (_price, orders) in self.asks.iter_mut() {
-            orders.retain(|order| !order.remaining.is_zero());
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            for (_, order) in orders.remove_where(|o| o.remaining.is_zero()) {
+                self.index.remove(&order);
+                self.terminal.record(order.id, Termination::Filled);
+            }
         }
 
         self.bids.retain(|_price, orders| !orders.is_empty());
@@ -310,49 +749,188 @@ impl Book {
     pub async fn submit(
         &mut self,
         order: Order,
-        executioner_address: String,
     ) -> Result<OrderStatus, BookError> {
+        Ok(self.execute(order).await?.status)
+    }
+
+    /// Submits an order to the matching engine, reporting how much of it was
+    /// filled and at what average price
+    pub async fn execute(
+        &mut self,
+        order: Order,
+    ) -> Result<Execution, BookError> {
+        self.execute_at(order, Utc::now()).await
+    }
+
+    /// Identical to `execute`, except that order expiry is evaluated as of
+    /// `now` rather than the current time
+    ///
+    /// Callers that have just swept the book of expired orders (see
+    /// `expire`) should pass the same `now`, so that no further orders expire
+    /// unreported in between.
+    pub async fn execute_at(
+        &mut self,
+        order: Order,
+        now: DateTime<Utc>,
+    ) -> Result<Execution, BookError> {
         info!("Submitting {}...", order);
+        self.process(order, &mut vec![], now).await
+    }
 
-        let match_result: Result<OrderStatus, BookError> = match order.side {
-            OrderSide::Bid => {
-                self.r#match(order, executioner_address, self.top().1).await
+    /// Resubmits a previously journalled order to the matching engine
+    ///
+    /// Identical to `submit`, except that order expiry is evaluated as of
+    /// `now` (i.e., the time of original submission) rather than the current
+    /// time.
+    pub async fn replay(
+        &mut self,
+        order: Order,
+        now: DateTime<Utc>,
+    ) -> Result<OrderStatus, BookError> {
+        info!("Replaying {}...", order);
+        Ok(self.process(order, &mut vec![], now).await?.status)
+    }
+
+    /// Submits an order to the matching engine as of `now`, appending each
+    /// resulting match to `matches`
+    ///
+    /// The book never forwards matches to the executioner itself; that is
+    /// left to the caller (see `outbox`).
+    pub async fn process(
+        &mut self,
+        mut order: Order,
+        matches: &mut Vec<Match>,
+        now: DateTime<Utc>,
+    ) -> Result<Execution, BookError> {
+        /* an order that has already expired can never be matched */
+        if order.is_expired(now) {
+            info!("{} has already expired, cancelling...", order);
+            self.terminal.record(order.id, Termination::Cancelled);
+            return Ok(Execution::unfilled(OrderStatus::Cancelled));
+        }
+
+        /* resting orders that have expired must never be matched */
+        self.expire(now);
+
+        /* stop orders wait in the trigger book until the market reaches them */
+        if order.stop_price.is_some() {
+            if !TriggerBook::is_triggered(&order, self.ltp) {
+                self.stops.add_order(order);
+                return Ok(Execution::unfilled(OrderStatus::Pending));
             }
-            OrderSide::Ask => {
-                self.r#match(order, executioner_address, self.top().0).await
+
+            info!("{} is already triggered, activating...", order);
+            order.stop_price = None;
+        }
+
+        let match_result: Result<Execution, BookError> =
+            self.activate(order, matches).await;
+
+        /* matching may have moved the LTP past the stops of other orders */
+        while let Some(triggered) = self.stops.next(self.ltp) {
+            info!("LTP of {} triggered {}", self.ltp, triggered);
+
+            if let Err(e) = self.activate(triggered, matches).await {
+                warn!("Failed to match triggered order: {}", e);
             }
+        }
+
+        match_result
+    }
+
+    /// Matches `order` against the book, as of the current top of book
+    async fn activate(
+        &mut self,
+        order: Order,
+        matches: &mut Vec<Match>,
+    ) -> Result<Execution, BookError> {
+        let opposing_top: Option<U256> = match order.side {
+            OrderSide::Bid => self.top().1,
+            OrderSide::Ask => self.top().0,
         };
+        let (id, initial): (OrderId, U256) = (order.id, order.remaining);
+        let match_result: Result<Execution, BookError> =
+            self.r#match(order, matches, opposing_top).await;
+
+        /* whatever of the order does not rest has left the book for good */
+        if let Ok(execution) = &match_result {
+            if self.order(id).is_none() {
+                self.terminal.record(
+                    id,
+                    if execution.filled == initial {
+                        Termination::Filled
+                    } else {
+                        Termination::Cancelled
+                    },
+                );
+            }
+        }
 
         self.update();
 
         match_result
     }
 
+    /// Removes every order that has expired as of `now` from the book
+    ///
+    /// Returns the orders removed.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<Order> {
+        let mut expired: Vec<Order> = vec![];
+
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            for (_, order) in orders.remove_where(|o| o.is_expired(now)) {
+                self.index.remove(&order);
+                expired.push(order);
+            }
+        }
+
+        expired.extend(self.stops.expire(now));
+
+        for order in expired.iter() {
+            info!("Expired {}", order);
+            self.terminal.record(order.id, Termination::Cancelled);
+        }
+
+        if !expired.is_empty() {
+            self.update();
+        }
+
+        expired
+    }
+
     #[allow(clippy::unnecessary_wraps)]
-    fn add_order(&mut self, order: Order) -> Result<(), BookError> {
+    fn add_order(&mut self, mut order: Order) -> Result<(), BookError> {
         info!("Adding {}...", order);
 
+        /* icebergs rest with only their first slice visible */
+        order.replenish();
+
         let tmp_order: Order = order.clone();
         let order_side = order.side;
         let order_price = order.price;
-        let orders = VecDeque::new();
+        let orders = Level::new();
 
-        match order_side {
+        let position: u64 = match order_side {
             OrderSide::Bid => {
-                self.bids
+                let position: u64 = self
+                    .bids
                     .entry(order_price)
                     .or_insert(orders)
                     .push_back(order);
                 info!("Added to bid-side");
+                position
             }
             OrderSide::Ask => {
-                self.asks
+                let position: u64 = self
+                    .asks
                     .entry(order_price)
                     .or_insert(orders)
                     .push_back(order);
                 info!("Added to ask-side");
+                position
             }
-        }
+        };
+        self.index.insert(&tmp_order, position);
 
         info!("Added {}", tmp_order);
 
@@ -371,32 +949,276 @@ impl Book {
     /// Returns `Ok(None)` if there is no such order currently in the book.
     ///
     /// Returns a `BookError` if there is an error condition
-    #[allow(unused_variables)] /* TODO: remove when cancel is implemented */
     pub fn cancel(
         &mut self,
         order_id: OrderId,
     ) -> Result<Option<DateTime<Utc>>, BookError> {
-        for (_, orders) in self.bids.iter_mut() {
-            for (index, order) in orders.iter_mut().enumerate() {
-                if order.id == order_id {
-                    info!("Cancelled {}", order.clone());
-                    orders.remove(index);
-                    return Ok(Some(Utc::now()));
+        Ok(self.remove(order_id).map(|order| {
+            info!("Cancelled {}", order);
+            Utc::now()
+        }))
+    }
+
+    /// Removes the order matching the provided order ID from the order book,
+    /// returning it
+    fn remove(&mut self, order_id: OrderId) -> Option<Order> {
+        let location: Location = self.index.location(order_id)?;
+        let levels: &mut BTreeMap<U256, Level> = self.levels_mut(location.side);
+        let orders: &mut Level = levels.get_mut(&location.price)?;
+        let order: Option<Order> = orders.remove(location.position);
+
+        if orders.is_empty() {
+            levels.remove(&location.price);
+        }
+
+        if let Some(order) = &order {
+            self.index.remove(order);
+            self.terminal.record(order.id, Termination::Cancelled);
+        }
+
+        order
+    }
+
+    /// Cancels every open order in the order book (and trigger book) of
+    /// `trader` on `side`, where `None` matches any trader or either side
+    /// respectively
+    ///
+    /// Returns the orders cancelled.
+    pub fn mass_cancel(
+        &mut self,
+        trader: Option<Address>,
+        side: Option<OrderSide>,
+    ) -> Vec<Order> {
+        let matches = |order: &Order| {
+            (trader.is_none() || trader == Some(order.trader))
+                && (side.is_none() || side == Some(order.side))
+        };
+        let mut cancelled: Vec<Order> = vec![];
+
+        match trader {
+            /* only visit the trader's own orders */
+            Some(trader) => {
+                let ids: Vec<OrderId> = self
+                    .orders_of(trader)
+                    .into_iter()
+                    .filter(|order| matches(order))
+                    .map(|order| order.id)
+                    .collect();
+                cancelled
+                    .extend(ids.into_iter().filter_map(|id| self.remove(id)));
+            }
+            None => {
+                for orders in
+                    self.bids.values_mut().chain(self.asks.values_mut())
+                {
+                    for (_, order) in orders.remove_where(|o| matches(o)) {
+                        self.index.remove(&order);
+                        cancelled.push(order);
+                    }
                 }
             }
         }
 
-        for (_, orders) in self.asks.iter_mut() {
-            for (index, order) in orders.iter_mut().enumerate() {
-                if order.id == order_id {
-                    info!("Cancelled {}", order.clone());
-                    orders.remove(index);
-                    return Ok(Some(Utc::now()));
-                }
+        cancelled.extend(self.stops.cancel_where(matches));
+
+        for order in cancelled.iter() {
+            info!("Cancelled {}", order);
+            self.terminal.record(order.id, Termination::Cancelled);
+        }
+
+        if !cancelled.is_empty() {
+            self.update();
+        }
+
+        cancelled
+    }
+
+    /// Returns whether `amended` may replace the resting order `original`
+    ///
+    /// An amendment must be a distinct resting limit order of the same
+    /// trader, market and side, for more than the quantity of the original
+    /// order that has already been filled.
+    pub fn is_amendment(original: &Order, amended: &Order) -> bool {
+        amended.id != original.id
+            && amended.trader == original.trader
+            && amended.market == original.market
+            && amended.side == original.side
+            && amended.quantity > original.quantity - original.remaining
+            && amended.rests()
+            && amended.stop_price.is_none()
+    }
+
+    /// Replaces the open order currently in the order book with the matching
+    /// ID with `amended`
+    ///
+    /// `amended.quantity` is the new total quantity of the order, so whatever
+    /// was filled of the original order counts towards it. If the price is
+    /// unchanged and the quantity is only reduced, the amended order keeps
+    /// the original's place in the queue. Otherwise, the original order is
+    /// removed and the amended order is matched afresh as of `now`, losing
+    /// priority. Resulting matches are appended to `matches`.
+    ///
+    /// Returns `Ok(None)` if there is no such order currently in the book.
+    pub async fn amend(
+        &mut self,
+        order_id: OrderId,
+        amended: Order,
+        matches: &mut Vec<Match>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Execution>, BookError> {
+        let original: Order = match self.order(order_id) {
+            Some(t) => t.clone(),
+            None => return Ok(None),
+        };
+
+        if !Book::is_amendment(&original, &amended) {
+            return Err(BookError::InvalidAmendment);
+        }
+
+        info!("Amending {} to {}...", original, amended);
+
+        let remaining: U256 =
+            amended.quantity - (original.quantity - original.remaining);
+
+        /* reducing quantity in place keeps priority */
+        if amended.price == original.price
+            && amended.quantity <= original.quantity
+            && amended.display_quantity == original.display_quantity
+        {
+            if let Some(order) = self.order_mut(order_id) {
+                *order = Order {
+                    remaining,
+                    displayed: order.displayed.min(remaining),
+                    ..amended.clone()
+                };
+            }
+            if let Some(location) = self.index.location(order_id) {
+                self.index.remove(&original);
+                self.index.insert(&amended, location.position);
             }
+            self.terminal.record(original.id, Termination::Cancelled);
+
+            self.update();
+            return Ok(Some(Execution::unfilled(OrderStatus::Amended)));
         }
 
-        Ok(None)
+        self.cancel(order_id)?;
+        self.update();
+
+        self.process(
+            Order {
+                remaining,
+                ..amended
+            },
+            matches,
+            now,
+        )
+        .await
+        .map(Some)
+    }
+
+    /// Undoes `matched`, which the executioner has refused for good
+    ///
+    /// The quantity of the match is restored to each order of the pair still
+    /// resting in the book, in place. Orders the match exhausted have left
+    /// the book and are not reinstated, as they may since have come to cross
+    /// it. Should the executioner hold one of the orders (e.g., its signature
+    /// or margin) to blame, that order is cancelled instead. If the match was
+    /// the latest trade, the last traded price reverts to its prior value.
+    pub fn rollback(
+        &mut self,
+        matched: &Match,
+        offender: Option<OrderId>,
+    ) -> Reconciliation {
+        info!(
+            "Rolling back match of {} with {}...",
+            matched.maker, matched.taker
+        );
+
+        let cancelled: Option<Order> = offender.and_then(|id| {
+            if id == matched.maker.id || id == matched.taker.id {
+                self.remove(id)
+            } else {
+                None
+            }
+        });
+        let mut restored: Vec<OrderId> = vec![];
+
+        for party in [&matched.maker, &matched.taker].iter() {
+            if let Some(order) = self.order_mut(party.id) {
+                order.remaining = order
+                    .remaining
+                    .saturating_add(matched.amount)
+                    .min(order.quantity);
+                info!("Restored {} to {}", matched.amount, order);
+                restored.push(party.id);
+            }
+        }
+
+        if let Some((trade, true)) = self.trades.remove(matched.id()) {
+            self.ltp = trade.prior;
+            info!("LTP reverted, is now {}", self.ltp);
+        }
+
+        /* an order exhausted by this match alone was never really filled */
+        for party in [&matched.maker, &matched.taker].iter() {
+            if Some(party.id) != offender
+                && self.termination(party.id) == Some(Termination::Filled)
+                && !self.trades.involves(party.id)
+            {
+                self.terminal.forget(party.id);
+            }
+        }
+
+        self.update();
+
+        Reconciliation {
+            matched: matched.clone(),
+            restored,
+            cancelled,
+            ltp: self.ltp,
+        }
+    }
+
+    /// Returns a reference to the stop order matching the provided order ID
+    pub fn stop(&self, id: OrderId) -> Option<&Order> {
+        self.stops.order(id)
+    }
+
+    /// Returns whether an order with ID `id` has already been submitted to
+    /// this book, i.e., whether it is resting in the book or trigger book, has
+    /// recently been (at least partially) filled, or has since left the book
+    ///
+    /// Only the last `RECENT_TRADES` trades and `TERMINAL_ORDERS` orders to
+    /// leave the book are remembered.
+    pub fn is_duplicate(&self, id: OrderId) -> bool {
+        self.order(id).is_some()
+            || self.stop(id).is_some()
+            || self.trades.involves(id)
+            || self.terminal.get(id).is_some()
+    }
+
+    /// Returns how the order with ID `id` left this book, if it has and is
+    /// still remembered
+    pub fn termination(&self, id: OrderId) -> Option<Termination> {
+        self.terminal.get(id)
+    }
+
+    /// Cancels the stop order currently in the trigger book with the matching
+    /// ID
+    ///
+    /// Returns the time of cancellation, or `None` if there is no such order
+    /// currently in the trigger book.
+    pub fn cancel_stop(&mut self, order_id: OrderId) -> Option<DateTime<Utc>> {
+        self.remove_stop(order_id).map(|_order| Utc::now())
+    }
+
+    /// Removes the stop order matching the provided order ID from the trigger
+    /// book, returning it
+    pub fn remove_stop(&mut self, order_id: OrderId) -> Option<Order> {
+        let order: Order = self.stops.cancel(order_id)?;
+        self.terminal.record(order.id, Termination::Cancelled);
+        Some(order)
     }
 
     /// Updates internal metadata of the order book
@@ -433,7 +1255,7 @@ impl From<Book> for ExternalBook {
                         price.to_string(),
                         orders
                             .iter()
-                            .map(|order| ExternalOrder::from(order.clone()))
+                            .map(|order| ExternalOrder::from(order.public()))
                             .collect(),
                     )
                 })
@@ -446,7 +1268,7 @@ impl From<Book> for ExternalBook {
                         price.to_string(),
                         orders
                             .iter()
-                            .map(|order| ExternalOrder::from(order.clone()))
+                            .map(|order| ExternalOrder::from(order.public()))
                             .collect(),
                     )
                 })
@@ -1,12 +1,20 @@
 use std::collections::{BTreeMap, VecDeque};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use ethereum_types::{Address, U256};
 
-use crate::book::{Book, BookError, OrderStatus};
-use crate::order::{Order, OrderSide};
-
-pub const TEST_RPC_ADDRESS: &str = "http://localhost:3000";
+use crate::book::{
+    Book, BookError, Execution, ExternalBook, Match, OrderStatus,
+    Reconciliation, SelfTrade, Trade, TradeHistory,
+};
+use crate::index::OrderIndex;
+use crate::level::Level;
+use crate::order::{
+    ExternalOrder, Order, OrderSide, OrderType, PostOnly, SelfTradePrevention,
+    TimeInForce,
+};
+use crate::registry::{TerminalOrders, Termination};
+use crate::trigger::TriggerBook;
 
 async fn submit_orders(
     market: Address,
@@ -22,7 +30,7 @@ async fn submit_orders(
                 *side,
                 (*price).into(),
                 (*qty).into(),
-                Utc::now(),
+                Utc::now() + Duration::days(1),
                 Utc::now(),
                 vec![],
             )
@@ -33,7 +41,7 @@ async fn submit_orders(
 
     /* apply each order to the book (sadly we can't `map` here due to our blocking requirement) */
     for order in orders {
-        book.submit(order.clone(), TEST_RPC_ADDRESS.to_string())
+        book.submit(order.clone())
             .await
             .expect("Failed to submit order to book");
     }
@@ -98,13 +106,12 @@ pub async fn test_simple_buy() {
         OrderSide::Bid,
         U256::from_dec_str(&"96").unwrap(),
         U256::from_dec_str(&"5").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -127,13 +134,12 @@ pub async fn test_simple_buy_partially_filled() {
         OrderSide::Bid,
         U256::from_dec_str(&"96").unwrap(),
         U256::from_dec_str(&"3").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -155,13 +161,12 @@ pub async fn test_simple_sell() {
         OrderSide::Ask,
         U256::from_dec_str(&"95").unwrap(),
         U256::from_dec_str(&"10").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(ask, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(ask).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -184,13 +189,12 @@ pub async fn test_simple_sell_partially_filled() {
         OrderSide::Ask,
         U256::from_dec_str(&"95").unwrap(),
         U256::from_dec_str(&"1").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -213,13 +217,12 @@ pub async fn test_deep_buy() {
         OrderSide::Bid,
         U256::from_dec_str(&"99").unwrap(),
         U256::from_dec_str(&"42").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -243,13 +246,12 @@ pub async fn test_no_self_matching() {
         OrderSide::Bid,
         97.into(),
         15.into(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let actual_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_depth, ask_depth) = book.depth();
 
@@ -269,7 +271,7 @@ pub async fn test_no_self_matching_when_last_order() {
         OrderSide::Ask,
         97.into(),
         15.into(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
@@ -280,17 +282,14 @@ pub async fn test_no_self_matching_when_last_order() {
         OrderSide::Bid,
         97.into(),
         15.into(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    book.submit(ask, TEST_RPC_ADDRESS.to_string())
-        .await
-        .unwrap();
+    book.submit(ask).await.unwrap();
 
-    let actual_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_depth, ask_depth) = book.depth();
 
@@ -309,13 +308,12 @@ pub async fn test_deep_buy_with_limit() {
         OrderSide::Bid,
         U256::from_dec_str(&"97").unwrap(),
         U256::from_dec_str(&"42").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(bid, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(bid).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -337,13 +335,12 @@ pub async fn test_deep_sell() {
         OrderSide::Ask,
         U256::from_dec_str(&"94").unwrap(),
         U256::from_dec_str(&"20").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(ask, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(ask).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -366,13 +363,12 @@ pub async fn test_deep_sell_with_limit() {
         OrderSide::Ask,
         U256::from_dec_str(&"94").unwrap(),
         U256::from_dec_str(&"35").unwrap(),
-        Utc::now(),
+        Utc::now() + Duration::days(1),
         Utc::now(),
         vec![],
     );
 
-    let submit_res: Result<OrderStatus, BookError> =
-        book.submit(ask, TEST_RPC_ADDRESS.to_string()).await;
+    let submit_res: Result<OrderStatus, BookError> = book.submit(ask).await;
 
     let (bid_length, ask_length) = book.depth();
 
@@ -390,7 +386,7 @@ pub async fn test_partial_matching_mutability() {
     let traders: Vec<Address> =
         vec![Address::random(), Address::random(), Address::random()];
     let the_far_future: DateTime<Utc> = DateTime::<Utc>::from_utc(
-        NaiveDateTime::from_timestamp(1699025703, 0),
+        NaiveDateTime::from_timestamp(4102444800, 0),
         Utc,
     );
     let market: Address = Address::zero();
@@ -434,17 +430,53 @@ pub async fn test_partial_matching_mutability() {
     let mut actual_book: Book = Book::new(market);
 
     for order in orders.iter() {
-        actual_book
-            .submit(order.clone(), TEST_RPC_ADDRESS.to_string())
-            .await
-            .unwrap();
+        actual_book.submit(order.clone()).await.unwrap();
     }
 
+    /* the short is filled by the first long, then by part of the second */
+    let remaining = |order: &Order, remaining: &str| Order {
+        remaining: U256::from_dec_str(remaining).unwrap(),
+        ..order.clone()
+    };
+    let matches: Vec<Match> = vec![
+        Match {
+            maker: remaining(&orders[0], "200000000000000000"),
+            taker: remaining(&orders[1], "0"),
+            price: orders[0].price,
+            amount: orders[1].quantity,
+            number: 1,
+        },
+        Match {
+            maker: remaining(&orders[0], "0"),
+            taker: remaining(&orders[2], "800000000000000000"),
+            price: orders[0].price,
+            amount: U256::from_dec_str("200000000000000000").unwrap(),
+            number: 2,
+        },
+    ];
+    let mut trades: TradeHistory = TradeHistory::default();
+    for (matched, prior) in
+        matches.iter().zip(vec![U256::zero(), orders[0].price])
+    {
+        trades.push(Trade {
+            id: matched.id(),
+            price: matched.price,
+            prior,
+            maker: matched.maker.id,
+            taker: matched.taker.id,
+        });
+    }
+
+    /* the first long, then the short, left the book filled */
+    let mut terminal: TerminalOrders = TerminalOrders::new();
+    terminal.record(orders[1].id, Termination::Filled);
+    terminal.record(orders[0].id, Termination::Filled);
+
     let expected_book: Book = Book {
         market,
         bids: {
-            let mut side: BTreeMap<U256, VecDeque<Order>> = BTreeMap::new();
-            let mut level: VecDeque<Order> = VecDeque::new();
+            let mut side: BTreeMap<U256, Level> = BTreeMap::new();
+            let mut level: Level = Level::new();
             let mut order: Order = orders[2].clone();
             order.remaining =
                 U256::from_dec_str("0800000000000000000").unwrap();
@@ -457,7 +489,1312 @@ pub async fn test_partial_matching_mutability() {
         depth: (1, 0),
         crossed: false,
         spread: U256::from_dec_str("0").unwrap(), // todo check how this is calculated
+        stops: TriggerBook::new(),
+        self_trade_prevention: None,
+        domain_separator: None,
+        index: OrderIndex::new(),
+        trades,
+        terminal,
+        matches: 2,
     };
 
     assert_eq!(actual_book, expected_book);
 }
+
+fn market_order(
+    side: OrderSide,
+    worst_price: u64,
+    qty: u64,
+    max_slippage: Option<u32>,
+) -> Order {
+    let mut order: Order = Order::new(
+        Address::from_low_u64_be(10),
+        Address::zero(),
+        side,
+        worst_price.into(),
+        qty.into(),
+        Utc::now() + Duration::days(1),
+        Utc::now(),
+        vec![],
+    );
+    order.order_type = OrderType::Market;
+    order.max_slippage = max_slippage;
+    order
+}
+
+#[tokio::test]
+pub async fn test_market_buy_sweeps_levels() {
+    let mut book = setup().await;
+    let bid: Order = market_order(OrderSide::Bid, 100, 25, None);
+
+    let actual_res: Result<Execution, BookError> = book.execute(bid).await;
+
+    /* 5 @ 96, 15 @ 97, 5 @ 98 */
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::FullMatch,
+            filled: 25.into(),
+            average_price: Some(97.into()),
+            self_trades: vec![],
+        })
+    );
+    assert_eq!(book.depth(), (5, 3));
+    assert_eq!(book.ltp(), 98.into());
+}
+
+#[tokio::test]
+pub async fn test_market_buy_remainder_is_cancelled() {
+    let mut book = setup().await;
+    let bid: Order = market_order(OrderSide::Bid, 97, 30, None);
+
+    let actual_res: Result<Execution, BookError> = book.execute(bid).await;
+
+    /* 5 @ 96, 15 @ 97, then the worst price is hit */
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::PartialMatchCancelled,
+            filled: 20.into(),
+            average_price: Some(96.into()),
+            self_trades: vec![],
+        })
+    );
+    assert_eq!(book.depth(), (5, 3));
+}
+
+#[tokio::test]
+pub async fn test_market_sell_with_max_slippage() {
+    let mut book = setup().await;
+
+    /* 2% below the best bid of 95 is 93.1, so only 95 and 94 are viable */
+    let ask: Order = market_order(OrderSide::Ask, 0, 100, Some(200));
+
+    let actual_res: Result<Execution, BookError> = book.execute(ask).await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::PartialMatchCancelled,
+            filled: 30.into(),
+            average_price: Some(94.into()),
+            self_trades: vec![],
+        })
+    );
+    assert_eq!(book.depth(), (3, 5));
+}
+
+#[tokio::test]
+pub async fn test_market_order_on_empty_side_is_cancelled() {
+    let mut book = Book::new(Address::zero());
+    let bid: Order = market_order(OrderSide::Bid, 100, 10, None);
+
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::Cancelled));
+    assert_eq!(book.depth(), (0, 0));
+}
+
+fn timed_order(
+    trader: u64,
+    side: OrderSide,
+    price: u64,
+    qty: u64,
+    time_in_force: TimeInForce,
+    expiration: DateTime<Utc>,
+) -> Order {
+    let mut order: Order = Order::new(
+        Address::from_low_u64_be(trader),
+        Address::zero(),
+        side,
+        price.into(),
+        qty.into(),
+        expiration,
+        Utc::now(),
+        vec![],
+    );
+    order.time_in_force = time_in_force;
+    order
+}
+
+#[tokio::test]
+pub async fn test_immediate_or_cancel_remainder_is_cancelled() {
+    let mut book = setup().await;
+    let bid: Order = timed_order(
+        10,
+        OrderSide::Bid,
+        97,
+        30,
+        TimeInForce::ImmediateOrCancel,
+        Utc::now() + Duration::days(1),
+    );
+
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::PartialMatchCancelled));
+    assert_eq!(book.depth(), (5, 3));
+    assert!(!book.bids.contains_key(&97.into()));
+}
+
+#[tokio::test]
+pub async fn test_fill_or_kill_without_liquidity_leaves_book_untouched() {
+    let mut book = setup().await;
+    let mut expected_book: Book = book.clone();
+
+    /* only 20 available at 97 or better */
+    let bid: Order = timed_order(
+        10,
+        OrderSide::Bid,
+        97,
+        21,
+        TimeInForce::FillOrKill,
+        Utc::now() + Duration::days(1),
+    );
+
+    expected_book
+        .terminal
+        .record(bid.id, Termination::Cancelled);
+
+    let actual_res: Result<Execution, BookError> = book.execute(bid).await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::Cancelled,
+            filled: U256::zero(),
+            average_price: None,
+            self_trades: vec![],
+        })
+    );
+    /* the price levels are untouched, and the order is never accepted again */
+    assert_eq!(book, expected_book);
+}
+
+#[tokio::test]
+pub async fn test_fill_or_kill_with_liquidity_fills() {
+    let mut book = setup().await;
+    let bid: Order = timed_order(
+        10,
+        OrderSide::Bid,
+        97,
+        20,
+        TimeInForce::FillOrKill,
+        Utc::now() + Duration::days(1),
+    );
+
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::FullMatch));
+    assert_eq!(book.depth(), (5, 3));
+}
+
+#[tokio::test]
+pub async fn test_expired_good_til_date_orders_are_not_matched() {
+    let mut book = Book::new(Address::zero());
+    let ask: Order = timed_order(
+        1,
+        OrderSide::Ask,
+        100,
+        10,
+        TimeInForce::GoodTilDate,
+        Utc::now() + Duration::seconds(1),
+    );
+    let bid: Order = timed_order(
+        2,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+
+    book.submit(ask.clone()).await.unwrap();
+
+    /* replay the bid as though it arrived after the ask expired */
+    let actual_res: Result<OrderStatus, BookError> =
+        book.replay(bid, ask.expiration).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::Add));
+    assert!(book.order(ask.id).is_none());
+    assert_eq!(book.depth(), (1, 0));
+}
+
+#[tokio::test]
+pub async fn test_expired_good_til_date_order_is_not_added() {
+    let mut book = Book::new(Address::zero());
+    let bid: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilDate,
+        Utc::now() - Duration::seconds(1),
+    );
+
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::Cancelled));
+    assert_eq!(book.depth(), (0, 0));
+}
+
+#[tokio::test]
+pub async fn test_expired_orders_are_not_matched_whatever_time_in_force() {
+    let mut book = Book::new(Address::zero());
+    let ask: Order = timed_order(
+        1,
+        OrderSide::Ask,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::seconds(1),
+    );
+    let bid: Order = timed_order(
+        2,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::ImmediateOrCancel,
+        Utc::now() + Duration::days(1),
+    );
+
+    book.submit(ask.clone()).await.unwrap();
+
+    /* replay the bid as though it arrived after the ask expired */
+    let actual_res: Result<OrderStatus, BookError> =
+        book.replay(bid.clone(), ask.expiration).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::Cancelled));
+    assert!(book.order(ask.id).is_none());
+    assert_eq!(book.depth(), (0, 0));
+
+    /* nor is an order that has itself expired */
+    let stale: Order = timed_order(
+        3,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() - Duration::seconds(1),
+    );
+    assert_eq!(book.submit(stale).await, Ok(OrderStatus::Cancelled));
+    assert_eq!(book.depth(), (0, 0));
+}
+
+fn post_only_order(
+    side: OrderSide,
+    price: u64,
+    qty: u64,
+    post_only: PostOnly,
+) -> Order {
+    let mut order: Order = Order::new(
+        Address::from_low_u64_be(10),
+        Address::zero(),
+        side,
+        price.into(),
+        qty.into(),
+        Utc::now() + Duration::days(1),
+        Utc::now(),
+        vec![],
+    );
+    order.post_only = Some(post_only);
+    order
+}
+
+#[tokio::test]
+pub async fn test_post_only_crossing_order_is_rejected() {
+    let mut book = setup().await;
+    let mut expected_book: Book = book.clone();
+    let bid: Order = post_only_order(OrderSide::Bid, 97, 10, PostOnly::Reject);
+    expected_book
+        .terminal
+        .record(bid.id, Termination::Cancelled);
+
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::Rejected));
+    /* the price levels are untouched, and the order is never accepted again */
+    assert_eq!(book, expected_book);
+}
+
+#[tokio::test]
+pub async fn test_post_only_crossing_bid_is_repriced() {
+    let mut book = setup().await;
+    let bid: Order = post_only_order(OrderSide::Bid, 97, 10, PostOnly::Reprice);
+
+    let actual_res: Result<OrderStatus, BookError> =
+        book.submit(bid.clone()).await;
+
+    /* one tick behind the best ask of 96 */
+    assert_eq!(actual_res, Ok(OrderStatus::Repriced));
+    assert_eq!(book.order(bid.id).unwrap().price, 95.into());
+    assert_eq!(book.depth(), (6, 5));
+    assert_eq!(book.ltp(), U256::zero());
+}
+
+#[tokio::test]
+pub async fn test_post_only_crossing_ask_is_repriced() {
+    let mut book = setup().await;
+    let ask: Order = post_only_order(OrderSide::Ask, 90, 10, PostOnly::Reprice);
+
+    let actual_res: Result<OrderStatus, BookError> =
+        book.submit(ask.clone()).await;
+
+    /* one tick behind the best bid of 95 */
+    assert_eq!(actual_res, Ok(OrderStatus::Repriced));
+    assert_eq!(book.order(ask.id).unwrap().price, 96.into());
+    assert_eq!(book.asks.get(&96.into()).unwrap().len(), 2);
+    assert_eq!(book.depth(), (5, 6));
+}
+
+#[tokio::test]
+pub async fn test_post_only_resting_order_is_added() {
+    let mut book = setup().await;
+    let bid: Order = post_only_order(OrderSide::Bid, 95, 10, PostOnly::Reject);
+
+    let actual_res: Result<OrderStatus, BookError> =
+        book.submit(bid.clone()).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::Add));
+    assert_eq!(book.order(bid.id).unwrap().price, 95.into());
+    assert_eq!(book.depth(), (6, 5));
+}
+
+fn stop_order(
+    trader: u64,
+    side: OrderSide,
+    stop_price: u64,
+    price: u64,
+    qty: u64,
+) -> Order {
+    let mut order: Order = Order::new(
+        Address::from_low_u64_be(trader),
+        Address::zero(),
+        side,
+        price.into(),
+        qty.into(),
+        Utc::now() + Duration::days(1),
+        Utc::now(),
+        vec![],
+    );
+    order.stop_price = Some(stop_price.into());
+    order
+}
+
+#[tokio::test]
+pub async fn test_stop_order_waits_for_trigger() {
+    let mut book = setup().await;
+    let bid: Order = stop_order(10, OrderSide::Bid, 97, 99, 5);
+
+    let actual_res: Result<OrderStatus, BookError> =
+        book.submit(bid.clone()).await;
+
+    /* nothing has traded yet, so the stop cannot have been reached */
+    assert_eq!(actual_res, Ok(OrderStatus::Pending));
+    assert_eq!(book.stop(bid.id), Some(&bid));
+    assert!(book.order(bid.id).is_none());
+    assert_eq!(book.depth(), (5, 5));
+
+    assert!(book.cancel_stop(bid.id).is_some());
+    assert!(book.stop(bid.id).is_none());
+    assert!(book.cancel_stop(bid.id).is_none());
+}
+
+#[tokio::test]
+pub async fn test_stop_limit_order_triggered_by_ltp() {
+    let mut book = setup().await;
+    let stop: Order = stop_order(10, OrderSide::Bid, 97, 99, 5);
+    let bid: Order = timed_order(
+        11,
+        OrderSide::Bid,
+        97,
+        20,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+
+    book.submit(stop.clone()).await.unwrap();
+
+    /* 5 @ 96 and 15 @ 97 take the LTP to the stop price */
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::FullMatch));
+    assert!(book.stop(stop.id).is_none());
+    assert_eq!(
+        book.asks
+            .get(&98.into())
+            .unwrap()
+            .front()
+            .unwrap()
+            .remaining,
+        30.into()
+    );
+    assert_eq!(book.ltp(), 98.into());
+    assert_eq!(book.depth(), (5, 3));
+}
+
+#[tokio::test]
+pub async fn test_triggered_stop_orders_are_activated_in_order() {
+    let mut book = setup().await;
+    let nearer: Order = stop_order(10, OrderSide::Ask, 95, 92, 10);
+    let further: Order = stop_order(11, OrderSide::Ask, 94, 92, 10);
+    let ask: Order = timed_order(
+        12,
+        OrderSide::Ask,
+        93,
+        35,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+
+    book.submit(further.clone()).await.unwrap();
+    book.submit(nearer.clone()).await.unwrap();
+
+    /* sweeps 95, 94 and 93, triggering both sell stops */
+    book.submit(ask).await.unwrap();
+
+    /* the stop nearest the prior price takes what is left at 92 */
+    assert!(book.stops.orders().next().is_none());
+    assert!(book.order(nearer.id).is_none());
+    assert_eq!(book.order(further.id).unwrap().remaining, 10.into());
+    assert!(!book.bids.contains_key(&92.into()));
+    assert_eq!(book.ltp(), 92.into());
+    assert_eq!(book.depth(), (1, 6));
+}
+
+#[tokio::test]
+pub async fn test_already_triggered_stop_order_is_activated() {
+    let mut book = setup().await;
+    let bid: Order = timed_order(
+        11,
+        OrderSide::Bid,
+        96,
+        5,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    book.submit(bid).await.unwrap();
+
+    /* the market has already traded at 96, beyond this sell stop */
+    let stop: Order = stop_order(10, OrderSide::Ask, 97, 95, 5);
+    let actual_res: Result<OrderStatus, BookError> =
+        book.submit(stop.clone()).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::FullMatch));
+    assert!(book.stop(stop.id).is_none());
+    assert_eq!(book.ltp(), 95.into());
+}
+
+fn iceberg_order(
+    trader: u64,
+    side: OrderSide,
+    price: u64,
+    qty: u64,
+    display_quantity: u64,
+) -> Order {
+    let mut order: Order = Order::new(
+        Address::from_low_u64_be(trader),
+        Address::zero(),
+        side,
+        price.into(),
+        qty.into(),
+        Utc::now() + Duration::days(1),
+        Utc::now(),
+        vec![],
+    );
+    order.display_quantity = Some(display_quantity.into());
+    order
+}
+
+#[tokio::test]
+pub async fn test_iceberg_order_only_shows_slice() {
+    let mut book = Book::new(Address::zero());
+    let ask: Order = iceberg_order(1, OrderSide::Ask, 100, 50, 10);
+
+    book.submit(ask.clone()).await.unwrap();
+
+    let external: ExternalBook = ExternalBook::from(book.clone());
+    let level: &VecDeque<ExternalOrder> = external.asks.get("100").unwrap();
+    assert_eq!(level[0].amount, "10");
+    assert_eq!(level[0].amount_left, "10");
+    assert_eq!(book.order(ask.id).unwrap().remaining, 50.into());
+    assert_eq!(book.depth(), (0, 1));
+}
+
+#[tokio::test]
+pub async fn test_iceberg_order_loses_priority_when_replenished() {
+    let mut book = Book::new(Address::zero());
+    let iceberg: Order = iceberg_order(1, OrderSide::Ask, 100, 30, 10);
+    let plain: Order = timed_order(
+        2,
+        OrderSide::Ask,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let bid: Order = timed_order(
+        3,
+        OrderSide::Bid,
+        100,
+        15,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+
+    for order in [iceberg.clone(), plain.clone(), bid].iter().cloned() {
+        book.submit(order).await.unwrap();
+    }
+
+    /* the first slice of 10 is taken, then 5 from the order behind it */
+    let level: Vec<&Order> =
+        book.asks.get(&100.into()).unwrap().iter().collect();
+    assert_eq!(level.len(), 2);
+    assert_eq!(level[0].id, plain.id);
+    assert_eq!(level[0].remaining, 5.into());
+    assert_eq!(level[1].id, iceberg.id);
+    assert_eq!(level[1].remaining, 20.into());
+    assert_eq!(level[1].visible(), 10.into());
+}
+
+#[tokio::test]
+pub async fn test_iceberg_hidden_quantity_is_matched() {
+    let mut book = Book::new(Address::zero());
+    let iceberg: Order = iceberg_order(1, OrderSide::Ask, 100, 30, 10);
+    let plain: Order = timed_order(
+        2,
+        OrderSide::Ask,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let bid: Order = timed_order(
+        3,
+        OrderSide::Bid,
+        100,
+        40,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+
+    book.submit(iceberg).await.unwrap();
+    book.submit(plain).await.unwrap();
+
+    let actual_res: Result<OrderStatus, BookError> = book.submit(bid).await;
+
+    assert_eq!(actual_res, Ok(OrderStatus::FullMatch));
+    assert!(book.asks.is_empty());
+    assert_eq!(book.depth(), (0, 0));
+}
+
+/// Returns a book with a resting ask from trader 1 ahead of one from trader
+/// 2 (both for 10 @ 100), alongside a bid from trader 1 crossing both
+fn self_trade_setup(
+    mode: Option<SelfTradePrevention>,
+) -> (Book, Order, Order, Order) {
+    let mut book: Book = Book::new(Address::zero());
+    let own: Order = timed_order(
+        1,
+        OrderSide::Ask,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let other: Order = timed_order(
+        2,
+        OrderSide::Ask,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let mut bid: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        100,
+        15,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    bid.self_trade_prevention = mode;
+
+    let level: &mut Level = book.asks.entry(100.into()).or_default();
+    level.push_back(own.clone());
+    level.push_back(other.clone());
+    book.reindex();
+
+    (book, own, other, bid)
+}
+
+#[tokio::test]
+pub async fn test_self_trade_cancel_newest() {
+    let (mut book, own, _other, bid) =
+        self_trade_setup(Some(SelfTradePrevention::CancelNewest));
+    let expected_asks: BTreeMap<U256, Level> = book.asks.clone();
+
+    let actual_res: Result<Execution, BookError> = book.execute(bid).await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::Cancelled,
+            filled: U256::zero(),
+            average_price: None,
+            self_trades: vec![SelfTrade {
+                mode: SelfTradePrevention::CancelNewest,
+                resting_id: own.id,
+                resting_cancelled: U256::zero(),
+                incoming_cancelled: 15.into(),
+            }],
+        })
+    );
+    assert_eq!(book.asks, expected_asks);
+    assert!(book.bids.is_empty());
+}
+
+#[tokio::test]
+pub async fn test_self_trade_cancel_oldest() {
+    let (mut book, own, other, bid) =
+        self_trade_setup(Some(SelfTradePrevention::CancelOldest));
+
+    let actual_res: Result<Execution, BookError> =
+        book.execute(bid.clone()).await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::PartialMatch,
+            filled: 10.into(),
+            average_price: Some(100.into()),
+            self_trades: vec![SelfTrade {
+                mode: SelfTradePrevention::CancelOldest,
+                resting_id: own.id,
+                resting_cancelled: 10.into(),
+                incoming_cancelled: U256::zero(),
+            }],
+        })
+    );
+    assert!(book.order(own.id).is_none());
+    assert!(book.order(other.id).is_none());
+    assert_eq!(book.order(bid.id).unwrap().remaining, 5.into());
+}
+
+#[tokio::test]
+pub async fn test_self_trade_cancel_both() {
+    let (mut book, own, other, bid) =
+        self_trade_setup(Some(SelfTradePrevention::CancelBoth));
+
+    let actual_res: Result<Execution, BookError> =
+        book.execute(bid.clone()).await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::Cancelled,
+            filled: U256::zero(),
+            average_price: None,
+            self_trades: vec![SelfTrade {
+                mode: SelfTradePrevention::CancelBoth,
+                resting_id: own.id,
+                resting_cancelled: 10.into(),
+                incoming_cancelled: 15.into(),
+            }],
+        })
+    );
+    assert!(book.order(own.id).is_none());
+    assert_eq!(book.order(other.id).unwrap().remaining, 10.into());
+    assert!(book.order(bid.id).is_none());
+}
+
+#[tokio::test]
+pub async fn test_self_trade_decrement_and_cancel() {
+    let (mut book, own, other, bid) =
+        self_trade_setup(Some(SelfTradePrevention::DecrementAndCancel));
+
+    let actual_res: Result<Execution, BookError> = book.execute(bid).await;
+
+    /* 10 is decremented from both, then the remaining 5 trades */
+    assert_eq!(
+        actual_res,
+        Ok(Execution {
+            status: OrderStatus::PartialMatchCancelled,
+            filled: 5.into(),
+            average_price: Some(100.into()),
+            self_trades: vec![SelfTrade {
+                mode: SelfTradePrevention::DecrementAndCancel,
+                resting_id: own.id,
+                resting_cancelled: 10.into(),
+                incoming_cancelled: 10.into(),
+            }],
+        })
+    );
+    assert!(book.order(own.id).is_none());
+    assert_eq!(book.order(other.id).unwrap().remaining, 5.into());
+    assert_eq!(book.depth(), (0, 1));
+}
+
+#[tokio::test]
+pub async fn test_self_trade_prevention_defaults_to_market_mode() {
+    let (mut book, own, _other, bid) = self_trade_setup(None);
+    book.self_trade_prevention = Some(SelfTradePrevention::CancelOldest);
+
+    let actual_res: Result<Execution, BookError> = book.execute(bid).await;
+
+    assert_eq!(actual_res.unwrap().self_trades[0].resting_id, own.id);
+    assert!(book.order(own.id).is_none());
+}
+
+async fn submit_all(book: &mut Book, orders: Vec<Order>) {
+    for order in orders {
+        book.submit(order)
+            .await
+            .expect("Failed to submit order to book");
+    }
+}
+
+fn amended_order(original: &Order, price: u64, qty: u64) -> Order {
+    Order::new(
+        original.trader,
+        original.market,
+        original.side,
+        price.into(),
+        qty.into(),
+        original.expiration,
+        original.created,
+        vec![0x01],
+    )
+}
+
+#[tokio::test]
+pub async fn test_amend_reducing_quantity_keeps_priority() {
+    let mut book: Book = Book::new(Address::zero());
+    let first: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let second: Order = timed_order(
+        2,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    submit_all(&mut book, vec![first.clone(), second.clone()]).await;
+
+    let amended: Order = amended_order(&first, 100, 6);
+    let actual_res: Result<Option<Execution>, BookError> = book
+        .amend(first.id, amended.clone(), &mut vec![], Utc::now())
+        .await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Some(Execution::unfilled(OrderStatus::Amended)))
+    );
+    assert!(book.order(first.id).is_none());
+
+    let level: Vec<&Order> =
+        book.bids.get(&100.into()).unwrap().iter().collect();
+    assert_eq!(level[0].id, amended.id);
+    assert_eq!(level[0].remaining, 6.into());
+    assert_eq!(level[1].id, second.id);
+}
+
+#[tokio::test]
+pub async fn test_amend_increasing_quantity_loses_priority() {
+    let mut book: Book = Book::new(Address::zero());
+    let first: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let second: Order = timed_order(
+        2,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    submit_all(&mut book, vec![first.clone(), second.clone()]).await;
+
+    let amended: Order = amended_order(&first, 100, 20);
+    let actual_res: Result<Option<Execution>, BookError> = book
+        .amend(first.id, amended.clone(), &mut vec![], Utc::now())
+        .await;
+
+    assert_eq!(actual_res, Ok(Some(Execution::unfilled(OrderStatus::Add))));
+
+    let level: Vec<&Order> =
+        book.bids.get(&100.into()).unwrap().iter().collect();
+    assert_eq!(level.len(), 2);
+    assert_eq!(level[0].id, second.id);
+    assert_eq!(level[1].id, amended.id);
+    assert_eq!(level[1].remaining, 20.into());
+}
+
+#[tokio::test]
+pub async fn test_amend_price_rematches_and_keeps_fills() {
+    let mut book: Book = Book::new(Address::zero());
+    let bid: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let ask: Order = timed_order(
+        2,
+        OrderSide::Ask,
+        100,
+        4,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let higher_ask: Order = timed_order(
+        3,
+        OrderSide::Ask,
+        105,
+        5,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    submit_all(&mut book, vec![bid.clone(), ask, higher_ask.clone()]).await;
+    assert_eq!(book.order(bid.id).unwrap().remaining, 6.into());
+
+    /* 4 of the bid has already been filled, so 8 remain to be matched */
+    let amended: Order = amended_order(&bid, 105, 12);
+    let actual_res: Result<Option<Execution>, BookError> = book
+        .amend(bid.id, amended.clone(), &mut vec![], Utc::now())
+        .await;
+
+    assert_eq!(
+        actual_res,
+        Ok(Some(Execution {
+            status: OrderStatus::PartialMatch,
+            filled: 5.into(),
+            average_price: Some(105.into()),
+            self_trades: vec![],
+        }))
+    );
+    assert!(book.order(bid.id).is_none());
+    assert!(book.order(higher_ask.id).is_none());
+    assert_eq!(book.order(amended.id).unwrap().remaining, 3.into());
+    assert_eq!(book.ltp, 105.into());
+}
+
+#[tokio::test]
+pub async fn test_invalid_amendments() {
+    let mut book: Book = Book::new(Address::zero());
+    let bid: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    submit_all(&mut book, vec![bid.clone()]).await;
+    let expected_book: Book = book.clone();
+
+    /* another trader's order */
+    let foreign: Order = Order {
+        trader: Address::from_low_u64_be(2),
+        ..amended_order(&bid, 100, 5)
+    };
+    assert_eq!(
+        book.amend(bid.id, foreign, &mut vec![], Utc::now()).await,
+        Err(BookError::InvalidAmendment)
+    );
+
+    /* an order that may not rest in the book */
+    let immediate: Order = Order {
+        time_in_force: TimeInForce::ImmediateOrCancel,
+        ..amended_order(&bid, 100, 5)
+    };
+    assert_eq!(
+        book.amend(bid.id, immediate, &mut vec![], Utc::now()).await,
+        Err(BookError::InvalidAmendment)
+    );
+
+    /* an order that isn't in the book */
+    let missing: Order = amended_order(&bid, 101, 5);
+    assert_eq!(
+        book.amend(missing.id, missing.clone(), &mut vec![], Utc::now())
+            .await,
+        Ok(None)
+    );
+
+    assert_eq!(book, expected_book);
+}
+
+#[tokio::test]
+pub async fn test_mass_cancel_by_trader_and_side() {
+    let mut book: Book = Book::new(Address::zero());
+    let own_bid: Order = timed_order(
+        1,
+        OrderSide::Bid,
+        90,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let own_ask: Order = timed_order(
+        1,
+        OrderSide::Ask,
+        110,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let other_bid: Order = timed_order(
+        2,
+        OrderSide::Bid,
+        90,
+        10,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    let own_stop: Order = stop_order(1, OrderSide::Bid, 120, 125, 5);
+    submit_all(
+        &mut book,
+        vec![
+            own_bid.clone(),
+            own_ask.clone(),
+            other_bid.clone(),
+            own_stop.clone(),
+        ],
+    )
+    .await;
+
+    let cancelled: Vec<Order> = book
+        .mass_cancel(Some(Address::from_low_u64_be(1)), Some(OrderSide::Bid));
+
+    assert_eq!(
+        cancelled.iter().map(|order| order.id).collect::<Vec<_>>(),
+        vec![own_bid.id, own_stop.id]
+    );
+    assert!(book.order(own_bid.id).is_none());
+    assert!(book.stop(own_stop.id).is_none());
+    assert!(book.order(own_ask.id).is_some());
+    assert!(book.order(other_bid.id).is_some());
+    assert_eq!(book.depth, (1, 1));
+}
+
+#[tokio::test]
+pub async fn test_mass_cancel_everything() {
+    let mut book = setup().await;
+    let (bids, asks): (usize, usize) = book.depth();
+
+    let cancelled: Vec<Order> = book.mass_cancel(None, None);
+
+    assert_eq!(cancelled.len(), bids + asks);
+    assert!(book.bids.is_empty());
+    assert!(book.asks.is_empty());
+    assert_eq!(book.depth, (0, 0));
+}
+
+/// Asserts that every resting order of `book` (and nothing else) is indexed
+fn assert_indexed(book: &Book) {
+    let resting: Vec<&Order> = book
+        .bids
+        .values()
+        .chain(book.asks.values())
+        .flatten()
+        .collect();
+
+    assert_eq!(book.index.len(), resting.len());
+    for order in resting {
+        assert_eq!(book.order(order.id), Some(order));
+    }
+}
+
+#[tokio::test]
+pub async fn test_index_tracks_fills_and_cancels() {
+    let mut book = setup().await;
+    assert_indexed(&book);
+
+    /* fully fills the asks at 96 and 97, partially fills that at 98 */
+    let bid: Order = timed_order(
+        10,
+        OrderSide::Bid,
+        98,
+        30,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    book.submit(bid).await.unwrap();
+    assert_indexed(&book);
+
+    let resting: Order =
+        book.asks.get(&98.into()).unwrap().front().unwrap().clone();
+    assert!(book.cancel(resting.id).unwrap().is_some());
+    assert!(book.order(resting.id).is_none());
+    assert!(!book.asks.contains_key(&98.into()));
+    assert!(book.cancel(resting.id).unwrap().is_none());
+    assert_indexed(&book);
+}
+
+#[tokio::test]
+pub async fn test_orders_keep_their_positions_as_others_leave() {
+    let mut book: Book = Book::new(Address::zero());
+    let orders: Vec<Order> = (1..=3)
+        .map(|trader| {
+            timed_order(
+                trader,
+                OrderSide::Ask,
+                100,
+                10,
+                TimeInForce::GoodTilCancelled,
+                Utc::now() + Duration::days(1),
+            )
+        })
+        .collect();
+    let iceberg: Order = iceberg_order(4, OrderSide::Ask, 100, 30, 10);
+    for order in orders.iter().chain(vec![&iceberg]) {
+        book.submit(order.clone()).await.unwrap();
+    }
+    let position = |book: &Book, order: &Order| {
+        book.index.location(order.id).unwrap().position
+    };
+    let last: u64 = position(&book, &orders[2]);
+
+    /* leaving from the middle moves no other order */
+    assert!(book.cancel(orders[1].id).unwrap().is_some());
+    assert_eq!(position(&book, &orders[2]), last);
+    assert_eq!(book.order(orders[2].id).map(|o| o.id), Some(orders[2].id));
+    assert_indexed(&book);
+
+    /* a replenished iceberg moves to the back, and is found there */
+    let bid: Order = timed_order(
+        5,
+        OrderSide::Bid,
+        100,
+        30,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    );
+    book.submit(bid).await.unwrap();
+    assert!(position(&book, &iceberg) > last);
+    assert_eq!(book.order(iceberg.id).unwrap().remaining, 20.into());
+    assert_eq!(book.asks.get(&100.into()).unwrap().len(), 1);
+    assert_indexed(&book);
+
+    /* the book reads the same once reindexed from scratch */
+    let mut reloaded: Book =
+        serde_json::from_str(&serde_json::to_string(&book).unwrap()).unwrap();
+    reloaded.reindex();
+    assert_eq!(reloaded, book);
+    assert_indexed(&reloaded);
+}
+
+#[tokio::test]
+pub async fn test_orders_of_trader() {
+    let mut book: Book = Book::new(Address::zero());
+    let orders: Vec<Order> = vec![
+        timed_order(
+            1,
+            OrderSide::Ask,
+            110,
+            10,
+            TimeInForce::GoodTilCancelled,
+            Utc::now() + Duration::days(1),
+        ),
+        timed_order(
+            1,
+            OrderSide::Bid,
+            90,
+            10,
+            TimeInForce::GoodTilCancelled,
+            Utc::now() + Duration::days(1),
+        ),
+        timed_order(
+            2,
+            OrderSide::Bid,
+            90,
+            10,
+            TimeInForce::GoodTilCancelled,
+            Utc::now() + Duration::days(1),
+        ),
+        timed_order(
+            1,
+            OrderSide::Bid,
+            90,
+            5,
+            TimeInForce::GoodTilCancelled,
+            Utc::now() + Duration::days(1),
+        ),
+    ];
+    submit_all(&mut book, orders.clone()).await;
+
+    /* bids first, then by price and priority */
+    assert_eq!(
+        book.orders_of(Address::from_low_u64_be(1)),
+        vec![&orders[1], &orders[3], &orders[0]]
+    );
+    assert!(book.orders_of(Address::from_low_u64_be(3)).is_empty());
+
+    book.mass_cancel(Some(Address::from_low_u64_be(1)), None);
+    assert!(book.orders_of(Address::from_low_u64_be(1)).is_empty());
+    assert_eq!(
+        book.orders_of(Address::from_low_u64_be(2)),
+        vec![&orders[2]]
+    );
+    assert_indexed(&book);
+}
+
+fn gtc_order(trader: u64, side: OrderSide, price: u64, qty: u64) -> Order {
+    timed_order(
+        trader,
+        side,
+        price,
+        qty,
+        TimeInForce::GoodTilCancelled,
+        Utc::now() + Duration::days(1),
+    )
+}
+
+async fn process_all(book: &mut Book, orders: Vec<Order>) -> Vec<Match> {
+    let mut matches: Vec<Match> = vec![];
+
+    for order in orders {
+        book.process(order, &mut matches, Utc::now())
+            .await
+            .expect("Failed to submit order to book");
+    }
+
+    matches
+}
+
+#[tokio::test]
+pub async fn test_rollback_restores_resting_orders_and_ltp() {
+    let mut book: Book = Book::new(Address::zero());
+    let ask: Order = gtc_order(1, OrderSide::Ask, 100, 10);
+    let bid: Order = gtc_order(2, OrderSide::Bid, 100, 4);
+    let matches: Vec<Match> =
+        process_all(&mut book, vec![ask.clone(), bid.clone()]).await;
+    assert_eq!(matches.len(), 1);
+    assert_eq!(book.ltp(), 100.into());
+
+    let reconciliation: Reconciliation = book.rollback(&matches[0], None);
+
+    /* the bid was exhausted by the match, so only the ask is restored */
+    assert_eq!(reconciliation.restored, vec![ask.id]);
+    assert_eq!(reconciliation.cancelled, None);
+    assert_eq!(reconciliation.ltp, U256::zero());
+    assert_eq!(book.order(ask.id).unwrap().remaining, 10.into());
+    assert!(book.order(bid.id).is_none());
+    assert_eq!(book.ltp(), U256::zero());
+    assert_indexed(&book);
+}
+
+#[tokio::test]
+pub async fn test_rollback_reverts_ltp_only_for_latest_trade() {
+    let mut book: Book = Book::new(Address::zero());
+    let orders: Vec<Order> = vec![
+        gtc_order(1, OrderSide::Ask, 100, 10),
+        gtc_order(1, OrderSide::Ask, 105, 10),
+        gtc_order(2, OrderSide::Bid, 100, 4),
+        gtc_order(3, OrderSide::Bid, 105, 10),
+    ];
+    let matches: Vec<Match> = process_all(&mut book, orders.clone()).await;
+    assert_eq!(matches.len(), 3);
+    assert_eq!(book.ltp(), 105.into());
+
+    /* later trades stand, so the LTP is unaffected */
+    assert_eq!(book.rollback(&matches[0], None).ltp, 105.into());
+
+    assert_eq!(book.rollback(&matches[2], None).ltp, 100.into());
+    assert_eq!(book.order(orders[1].id).unwrap().remaining, 10.into());
+
+    /* the first trade was rolled back, so nothing was traded before this */
+    assert_eq!(book.rollback(&matches[1], None).ltp, U256::zero());
+    assert_eq!(book.ltp(), U256::zero());
+    assert_indexed(&book);
+}
+
+#[tokio::test]
+pub async fn test_rollback_cancels_offending_order() {
+    let mut book: Book = Book::new(Address::zero());
+    let ask: Order = gtc_order(1, OrderSide::Ask, 100, 10);
+    let bid: Order = gtc_order(2, OrderSide::Bid, 100, 4);
+    let matches: Vec<Match> =
+        process_all(&mut book, vec![ask.clone(), bid.clone()]).await;
+
+    let reconciliation: Reconciliation =
+        book.rollback(&matches[0], Some(ask.id));
+
+    assert!(reconciliation.restored.is_empty());
+    assert_eq!(reconciliation.cancelled.map(|order| order.id), Some(ask.id));
+    assert!(book.order(ask.id).is_none());
+    assert_eq!(book.depth(), (0, 0));
+    assert_indexed(&book);
+}
+
+#[tokio::test]
+pub async fn test_submitted_orders_are_duplicates() {
+    let mut book: Book = Book::new(Address::zero());
+    let ask: Order = gtc_order(1, OrderSide::Ask, 100, 10);
+    let bid: Order = gtc_order(2, OrderSide::Bid, 100, 4);
+    let unseen: Order = gtc_order(3, OrderSide::Bid, 90, 4);
+    let matches: Vec<Match> =
+        process_all(&mut book, vec![ask.clone(), bid.clone()]).await;
+
+    /* the ask still rests, whereas the bid was filled in full */
+    assert!(book.is_duplicate(ask.id));
+    assert!(book.is_duplicate(bid.id));
+    assert!(book.order(bid.id).is_none());
+    assert!(!book.is_duplicate(unseen.id));
+
+    /* an order whose only fill was undone may be submitted again */
+    book.rollback(&matches[0], None);
+    assert!(!book.is_duplicate(bid.id));
+}
+
+#[tokio::test]
+pub async fn test_orders_leaving_book_are_terminal() {
+    let mut book: Book = Book::new(Address::zero());
+    let ask: Order = gtc_order(1, OrderSide::Ask, 100, 10);
+    let bid: Order = gtc_order(2, OrderSide::Bid, 100, 10);
+    let cancelled: Order = gtc_order(3, OrderSide::Bid, 90, 10);
+    let killed: Order = timed_order(
+        4,
+        OrderSide::Bid,
+        100,
+        10,
+        TimeInForce::FillOrKill,
+        Utc::now() + Duration::days(1),
+    );
+    let stop: Order = stop_order(5, OrderSide::Bid, 120, 120, 10);
+    process_all(
+        &mut book,
+        vec![
+            ask.clone(),
+            bid.clone(),
+            cancelled.clone(),
+            killed.clone(),
+            stop.clone(),
+        ],
+    )
+    .await;
+
+    assert!(book.cancel(cancelled.id).unwrap().is_some());
+    assert!(book.cancel_stop(stop.id).is_some());
+
+    assert_eq!(book.termination(ask.id), Some(Termination::Filled));
+    assert_eq!(book.termination(bid.id), Some(Termination::Filled));
+    for order in [&cancelled, &killed, &stop].iter() {
+        assert_eq!(book.termination(order.id), Some(Termination::Cancelled));
+        assert!(book.is_duplicate(order.id));
+    }
+    assert_eq!(book.terminal.len(), 5);
+}
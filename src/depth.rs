@@ -0,0 +1,276 @@
+//! Contains logic for publishing the aggregated depth (i.e., L2 market data)
+//! of each order book
+//!
+//! Subscribers to a book first receive a snapshot of every one of its price
+//! levels, then an update listing the levels changed by each command applied
+//! to the book since. Every snapshot and update carries the depth sequence
+//! number of the book, which increases by exactly one with each update, so
+//! that subscribers can detect missed updates and request a new snapshot.
+//!
+//! Only the visible quantity of each order counts towards its price level, so
+//! the hidden remainder of iceberg orders is never disclosed.
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use warp::ws::{Message, WebSocket};
+use web3::futures::stream::{SplitSink, SplitStream};
+use web3::futures::{SinkExt, StreamExt};
+use web3::types::{Address, U256};
+
+use crate::book::Book;
+use crate::market::Market;
+use crate::order::OrderSide;
+use crate::util::{from_hex_de, from_hex_se};
+
+/// The number of messages buffered for each subscriber
+pub const DEPTH_CAPACITY: usize = 1024;
+
+/// The total visible quantity resting at each price of one side of a book
+pub type Levels = BTreeMap<U256, U256>;
+
+/// Represents the total visible quantity resting at a single price
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Level {
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub quantity: U256,
+}
+
+/// Represents the new total visible quantity of a single price level
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct LevelChange {
+    pub side: OrderSide,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub quantity: U256, /* zero if the level has been emptied */
+}
+
+/// Represents a single message sent to subscribers to the depth of a book
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DepthMessage {
+    Snapshot {
+        market: Address,
+        sequence: u64,
+        bids: Vec<Level>, /* best (i.e., highest) price first */
+        asks: Vec<Level>, /* best (i.e., lowest) price first */
+        timestamp: DateTime<Utc>,
+    },
+    Update {
+        market: Address,
+        sequence: u64,
+        changes: Vec<LevelChange>,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+/// Represents a request sent by a subscriber to the depth of a book
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DepthRequest {
+    Snapshot, /* e.g., upon detecting a gap in sequence numbers */
+}
+
+/// Represents the depth of a book as last published to its subscribers
+#[derive(Debug)]
+pub struct Depth {
+    market: Address,
+    sequence: u64, /* sequence number of the last update published */
+    bids: Levels,
+    asks: Levels,
+    sender: Sender<DepthMessage>,
+}
+
+impl Depth {
+    /// Constructor for the `Depth` type
+    ///
+    /// Takes the book as it stands, so that only changes made to it from now
+    /// on are published.
+    pub fn new(book: &Book) -> Self {
+        Self {
+            market: book.market,
+            sequence: 0,
+            bids: Depth::levels(book, OrderSide::Bid),
+            asks: Depth::levels(book, OrderSide::Ask),
+            sender: broadcast::channel(DEPTH_CAPACITY).0,
+        }
+    }
+
+    /// Aggregates the visible quantity of each price level of one side of
+    /// `book`
+    pub fn levels(book: &Book, side: OrderSide) -> Levels {
+        let levels = match side {
+            OrderSide::Bid => &book.bids,
+            OrderSide::Ask => &book.asks,
+        };
+
+        levels
+            .iter()
+            .map(|(price, orders)| {
+                (
+                    *price,
+                    orders.iter().fold(U256::zero(), |acc, order| {
+                        acc.saturating_add(order.visible())
+                    }),
+                )
+            })
+            .filter(|(_price, quantity)| !quantity.is_zero())
+            .collect()
+    }
+
+    /// Returns the sequence number of the last update published
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns a snapshot of every price level, as of the last update
+    /// published
+    pub fn snapshot(&self) -> DepthMessage {
+        let level = |(price, quantity): (&U256, &U256)| Level {
+            price: *price,
+            quantity: *quantity,
+        };
+
+        DepthMessage::Snapshot {
+            market: self.market,
+            sequence: self.sequence,
+            bids: self.bids.iter().rev().map(level).collect(),
+            asks: self.asks.iter().map(level).collect(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Returns a snapshot of every price level, along with a receiver of
+    /// every update published after it
+    pub fn subscribe(&self) -> (DepthMessage, Receiver<DepthMessage>) {
+        (self.snapshot(), self.sender.subscribe())
+    }
+
+    /// Publishes an update listing each price level of `book` that has
+    /// changed since the last update, if any has
+    ///
+    /// Returns the update published.
+    pub fn refresh(
+        &mut self,
+        book: &Book,
+        now: DateTime<Utc>,
+    ) -> Option<DepthMessage> {
+        let bids: Levels = Depth::levels(book, OrderSide::Bid);
+        let asks: Levels = Depth::levels(book, OrderSide::Ask);
+        let mut changes: Vec<LevelChange> =
+            Depth::changes(OrderSide::Bid, &self.bids, &bids);
+        changes.extend(Depth::changes(OrderSide::Ask, &self.asks, &asks));
+
+        if changes.is_empty() {
+            return None;
+        }
+
+        self.bids = bids;
+        self.asks = asks;
+        self.sequence += 1;
+
+        let update: DepthMessage = DepthMessage::Update {
+            market: self.market,
+            sequence: self.sequence,
+            changes,
+            timestamp: now,
+        };
+
+        /* having no subscribers is not an error */
+        let _ = self.sender.send(update.clone());
+
+        Some(update)
+    }
+
+    /// Lists each price level of one side that differs between `old` and
+    /// `new`, in order of price
+    fn changes(
+        side: OrderSide,
+        old: &Levels,
+        new: &Levels,
+    ) -> Vec<LevelChange> {
+        let mut prices: Vec<U256> =
+            old.keys().chain(new.keys()).cloned().collect();
+        prices.sort();
+        prices.dedup();
+
+        prices
+            .into_iter()
+            .filter(|price| old.get(price) != new.get(price))
+            .map(|price| LevelChange {
+                side,
+                price,
+                quantity: new.get(&price).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Sends `message` over `sink`, returning whether it was sent
+async fn send(
+    sink: &mut SplitSink<WebSocket, Message>,
+    message: &DepthMessage,
+) -> bool {
+    let text: String = match serde_json::to_string(message) {
+        Ok(t) => t,
+        Err(_e) => return false,
+    };
+
+    sink.send(Message::text(text)).await.is_ok()
+}
+
+/// Serves the depth of the book of `market` over `socket` until either side
+/// hangs up
+///
+/// A new snapshot is sent whenever the subscriber requests one, and whenever
+/// the subscriber falls so far behind that updates are lost.
+pub async fn serve(socket: WebSocket, market: Market) {
+    let (mut sink, mut stream): (
+        SplitSink<WebSocket, Message>,
+        SplitStream<WebSocket>,
+    ) = socket.split();
+
+    let mut updates: Receiver<DepthMessage> = match market.depth().await {
+        Ok((snapshot, updates)) if send(&mut sink, &snapshot).await => updates,
+        _ => return,
+    };
+
+    loop {
+        let resnapshot: bool = tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    if !send(&mut sink, &update).await {
+                        break;
+                    }
+                    false
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    info!("Depth subscriber missed {} updates", missed);
+                    true
+                }
+                Err(RecvError::Closed) => break, /* the book has gone */
+            },
+            request = stream.next() => match request {
+                Some(Ok(message)) if message.is_close() => break,
+                Some(Ok(message)) => matches!(
+                    message.to_str().map(serde_json::from_str),
+                    Ok(Ok(DepthRequest::Snapshot))
+                ),
+                Some(Err(_)) | None => break,
+            },
+        };
+
+        if resnapshot {
+            updates = match market.depth().await {
+                Ok((snapshot, t)) if send(&mut sink, &snapshot).await => t,
+                _ => break,
+            };
+        }
+    }
+
+    info!("Depth subscriber of {} left", market.market());
+}
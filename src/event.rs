@@ -0,0 +1,160 @@
+//! Contains type definitions for the events the OME publishes about orders
+//!
+//! Events are broadcast to any number of subscribers. Publishing never blocks
+//! the matching engine: subscribers that fall too far behind simply miss
+//! events.
+//!
+//! Events concerning fills carry the price and quantity of the fill, along
+//! with the ID of the order on the other side of it.
+use std::fmt;
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use web3::types::{Address, U256};
+
+use crate::book::{Match, MatchId};
+use crate::order::{Order, OrderId};
+use crate::util::{from_hex_de, from_hex_se};
+
+/// The number of events buffered for each subscriber
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Represents what happened to an order
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, Serialize, Deserialize,
+)]
+pub enum OrderEventKind {
+    Expired,         /* removed from the book upon reaching its expiration */
+    Corrected, /* a match of it was refused by the executioner, so undone */
+    Cancelled, /* removed from the book before being filled in full */
+    Accepted,  /* taken on by the matching engine */
+    PartiallyFilled, /* matched, with some quantity remaining */
+    Filled,    /* matched, with no quantity remaining */
+    Confirmed, /* a match of it was accepted by the executioner */
+}
+
+/// Represents a single fill of an order
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Fill {
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub quantity: U256,
+    pub counterparty: OrderId, /* the order on the other side of the fill */
+}
+
+/// Represents a single event concerning an order
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub kind: OrderEventKind,
+    pub market: Address,
+    pub order_id: OrderId,
+    pub trader: Address,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub match_id: Option<MatchId>, /* the match concerned, if any */
+    #[serde(default)]
+    pub fill: Option<Fill>, /* the fill concerned, if any */
+}
+
+impl OrderEvent {
+    /// Constructs an event of kind `kind` concerning `order`
+    pub fn new(
+        kind: OrderEventKind,
+        order: &Order,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            kind,
+            market: order.market,
+            order_id: order.id,
+            trader: order.trader,
+            timestamp,
+            match_id: None,
+            fill: None,
+        }
+    }
+
+    /// Constructs an event of kind `kind` concerning `order` and the match
+    /// with ID `match_id`
+    pub fn of_match(
+        kind: OrderEventKind,
+        order: &Order,
+        match_id: MatchId,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            match_id: Some(match_id),
+            ..Self::new(kind, order, timestamp)
+        }
+    }
+
+    /// Constructs an event of kind `kind` concerning `order` and its fill by
+    /// `matched`
+    ///
+    /// `order` must be one of the two orders of `matched`.
+    pub fn of_fill(
+        kind: OrderEventKind,
+        order: &Order,
+        matched: &Match,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        let counterparty: OrderId = if order.id == matched.maker.id {
+            matched.taker.id
+        } else {
+            matched.maker.id
+        };
+
+        Self {
+            fill: Some(Fill {
+                price: matched.price,
+                quantity: matched.amount,
+                counterparty,
+            }),
+            ..Self::of_match(kind, order, matched.id(), timestamp)
+        }
+    }
+
+    /// Constructs an event reporting the fill of `order` by `matched`, which
+    /// is either partial or complete depending on what remains of `order`
+    pub fn filled(
+        order: &Order,
+        matched: &Match,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        let kind: OrderEventKind = if order.remaining.is_zero() {
+            OrderEventKind::Filled
+        } else {
+            OrderEventKind::PartiallyFilled
+        };
+
+        Self::of_fill(kind, order, matched, timestamp)
+    }
+}
+
+impl fmt::Display for OrderEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<{} ID: {} Market: {} Trader: {} At: {}>",
+            self.kind, self.order_id, self.market, self.trader, self.timestamp
+        )
+    }
+}
+
+pub type EventSender = broadcast::Sender<OrderEvent>;
+
+/// Creates a new (as yet unsubscribed) event channel
+pub fn channel() -> EventSender {
+    broadcast::channel(EVENT_CAPACITY).0
+}
+
+/// Publishes `event` to every current subscriber
+pub fn publish(events: &EventSender, event: OrderEvent) {
+    info!("Publishing {}", event);
+
+    /* having no subscribers is not an error */
+    let _ = events.send(event);
+}
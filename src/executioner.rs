@@ -0,0 +1,134 @@
+//! Contains the interface through which the OME talks to the executioner
+//!
+//! The OME only ever asks two things of the executioner: whether an order may
+//! be accepted, and to settle a matched pair of orders. `HttpExecutioner`
+//! does so over HTTP (see `rpc`), whereas `MockExecutioner` answers from
+//! memory and records every request, so that tests need no network.
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use web3::types::H160;
+
+use crate::book::Match;
+use crate::order::Order;
+use crate::rpc::{self, MatchResponse, RpcError};
+
+/// The future returned by each request of the executioner
+pub type ExecutionerFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, RpcError>> + Send + 'a>>;
+
+/// Represents a client of the executioner
+pub trait Executioner: Debug + Send + Sync {
+    /// Returns whether the executioner would accept `order`
+    fn check(&self, order: Order) -> ExecutionerFuture<'_, bool>;
+
+    /// Forwards `matched` to the executioner for settlement, keyed by the ID
+    /// of the match
+    fn submit(&self, matched: Match) -> ExecutionerFuture<'_, MatchResponse>;
+}
+
+/// Represents an executioner reached over HTTP at `address`
+#[derive(Clone, Debug)]
+pub struct HttpExecutioner {
+    address: String,
+}
+
+impl HttpExecutioner {
+    /// Constructor for the `HttpExecutioner` type
+    pub fn new(address: String) -> Self {
+        Self { address }
+    }
+
+    /// Returns the address of the executioner
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl Executioner for HttpExecutioner {
+    fn check(&self, order: Order) -> ExecutionerFuture<'_, bool> {
+        Box::pin(rpc::check_order_validity(order, self.address.clone()))
+    }
+
+    fn submit(&self, matched: Match) -> ExecutionerFuture<'_, MatchResponse> {
+        /* the executioner has always been sent the taker first */
+        Box::pin(rpc::send_matched_orders(
+            matched.taker.clone(),
+            matched.maker.clone(),
+            self.address.clone(),
+            matched.id(),
+        ))
+    }
+}
+
+/// Represents an in-memory executioner that records every request made of it
+///
+/// Orders are deemed valid unless told otherwise, and matches are accepted
+/// unless responses have been queued for them (see `respond`).
+#[derive(Debug)]
+pub struct MockExecutioner {
+    valid: Mutex<bool>,
+    responses: Mutex<VecDeque<Result<MatchResponse, RpcError>>>,
+    checked: Mutex<Vec<Order>>,
+    submitted: Mutex<Vec<Match>>,
+}
+
+impl MockExecutioner {
+    /// Constructor for the `MockExecutioner` type
+    pub fn new() -> Self {
+        Self {
+            valid: Mutex::new(true),
+            responses: Mutex::new(VecDeque::new()),
+            checked: Mutex::new(vec![]),
+            submitted: Mutex::new(vec![]),
+        }
+    }
+
+    /// Sets whether orders checked from now on are deemed valid
+    pub fn set_valid(&self, valid: bool) {
+        *self.valid.lock().unwrap() = valid;
+    }
+
+    /// Queues `response` as the answer to the next match submitted
+    pub fn respond(&self, response: Result<MatchResponse, RpcError>) {
+        self.responses.lock().unwrap().push_back(response);
+    }
+
+    /// Returns every order checked so far, in the order checked
+    pub fn checked(&self) -> Vec<Order> {
+        self.checked.lock().unwrap().clone()
+    }
+
+    /// Returns every match submitted so far, in the order submitted
+    pub fn submitted(&self) -> Vec<Match> {
+        self.submitted.lock().unwrap().clone()
+    }
+}
+
+impl Default for MockExecutioner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executioner for MockExecutioner {
+    fn check(&self, order: Order) -> ExecutionerFuture<'_, bool> {
+        let valid: bool = *self.valid.lock().unwrap();
+        self.checked.lock().unwrap().push(order);
+        Box::pin(async move { Ok(valid) })
+    }
+
+    fn submit(&self, matched: Match) -> ExecutionerFuture<'_, MatchResponse> {
+        let response: Result<MatchResponse, RpcError> = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Ok(MatchResponse::Accepted(H160::zero())));
+        self.submitted.lock().unwrap().push(matched);
+        Box::pin(async move { response })
+    }
+}
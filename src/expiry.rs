@@ -0,0 +1,45 @@
+//! Contains logic for periodically removing expired orders from the books
+//!
+//! Sweeps are not journalled: expiry is a pure function of time, so an order
+//! swept before a crash is simply swept again (and, until then, skipped by the
+//! matching engine) once the journal has been replayed.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::event::{self, EventSender, OrderEvent, OrderEventKind};
+use crate::market::Markets;
+use crate::order::Order;
+
+/// Publishes an `Expired` event for each of `orders`
+pub fn report(events: &EventSender, orders: &[Order], now: DateTime<Utc>) {
+    for order in orders {
+        event::publish(
+            events,
+            OrderEvent::new(OrderEventKind::Expired, order, now),
+        );
+    }
+}
+
+/// Removes every order that has expired as of `now` from every book,
+/// publishing an `Expired` event for each
+///
+/// Returns the orders removed.
+pub async fn sweep(markets: &Markets, now: DateTime<Utc>) -> Vec<Order> {
+    markets.expire(now).await
+}
+
+/// Sweeps every book of expired orders every `interval`, forever
+pub async fn run(markets: Markets, interval: Duration) {
+    let mut ticker: tokio::time::Interval = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let expired: Vec<Order> = sweep(&markets, Utc::now()).await;
+
+        if !expired.is_empty() {
+            info!("Swept {} expired orders", expired.len());
+        }
+    }
+}
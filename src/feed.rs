@@ -0,0 +1,77 @@
+//! Contains logic for streaming the order events of a single trader to them
+//!
+//! Each trader may subscribe to the events concerning their own orders (see
+//! `event`) over a WebSocket, having authenticated themselves (see `auth`).
+//!
+//! Like every other subscriber to order events, a trader who falls too far
+//! behind misses events. Rather than silently skipping them, the socket is
+//! then closed, so that the trader can catch up on the state of their orders
+//! before subscribing again.
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use warp::ws::{Message, WebSocket};
+use web3::futures::stream::{SplitSink, SplitStream};
+use web3::futures::{SinkExt, StreamExt};
+use web3::types::Address;
+
+use crate::event::OrderEvent;
+
+/// The WebSocket close code sent to subscribers that have missed events
+/// (i.e., "try again later")
+pub const LAGGED_CLOSE_CODE: u16 = 1013;
+
+/// Sends `event` over `sink`, returning whether it was sent
+async fn send(
+    sink: &mut SplitSink<WebSocket, Message>,
+    event: &OrderEvent,
+) -> bool {
+    let text: String = match serde_json::to_string(event) {
+        Ok(t) => t,
+        Err(_e) => return false,
+    };
+
+    sink.send(Message::text(text)).await.is_ok()
+}
+
+/// Serves each event of `events` concerning the orders of `trader` over
+/// `socket` until either side hangs up
+pub async fn serve(
+    socket: WebSocket,
+    trader: Address,
+    mut events: Receiver<OrderEvent>,
+) {
+    let (mut sink, mut stream): (
+        SplitSink<WebSocket, Message>,
+        SplitStream<WebSocket>,
+    ) = socket.split();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.trader == trader => {
+                    if !send(&mut sink, &event).await {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    info!("Trader {} missed {} events", trader, missed);
+                    let _ = sink
+                        .send(Message::close_with(
+                            LAGGED_CLOSE_CODE,
+                            "Missed events",
+                        ))
+                        .await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            request = stream.next() => match request {
+                Some(Ok(message)) if message.is_close() => break,
+                Some(Ok(_)) => {} /* subscribers have nothing to ask for */
+                Some(Err(_)) | None => break,
+            },
+        }
+    }
+
+    info!("Trader {} unsubscribed from order events", trader);
+}
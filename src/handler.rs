@@ -2,21 +2,36 @@ use std::collections::HashMap;
 use std::convert::{From, Infallible, TryFrom};
 use std::sync::Arc;
 
-use chrono::serde::ts_seconds;
+use chrono::serde::{ts_seconds, ts_seconds_option};
 use chrono::{DateTime, Utc};
 use ethereum_types::{Address, H256, U256};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, MutexGuard};
+use tokio::sync::{broadcast, Mutex, MutexGuard};
 use warp::http;
 use warp::http::StatusCode;
 use warp::reply::json;
+use warp::ws::Ws;
 use warp::{Rejection, Reply};
 
-use crate::book::{Book, ExternalBook};
-use crate::order::{ExternalOrder, Order, OrderId, OrderSide};
-use crate::rpc;
-use crate::state::OmeState;
-use crate::util::{from_hex_de, from_hex_se};
+use crate::auth::{self, Action, AuthError};
+use crate::book::{Execution, ExternalBook, MatchId, OrderStatus, SelfTrade};
+use crate::depth;
+use crate::event::OrderEvent;
+use crate::feed;
+use crate::heartbeat::{self, Heartbeats};
+use crate::journal::JournalEvent;
+use crate::market::{Market, MarketError, Markets, Outcome};
+use crate::order::{
+    self, ExternalOrder, Order, OrderId, OrderSide, OrderType, PostOnly,
+    SelfTradePrevention, TimeInForce,
+};
+use crate::outbox::{Delivery, ExternalDelivery, Outbox};
+use crate::signature;
+use crate::tape::{self, TradeId};
+use crate::trigger::ExternalStopOrder;
+use crate::util::{
+    from_hex_de, from_hex_opt_de, from_hex_opt_se, from_hex_se, nullable_de,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OmeResponse {
@@ -30,9 +45,27 @@ pub struct CreateBookRequest {
     market: Address,
 }
 
+/// Represents an API request to update the configuration of an order book
+///
+/// Fields left out are left unchanged, whereas fields set to `null` are unset
+/// (save for the EIP-712 domain separator, which may never be unset).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpdateBookRequest {
+    #[serde(default, deserialize_with = "nullable_de")]
+    self_trade_prevention: Option<Option<SelfTradePrevention>>,
+    #[serde(default, deserialize_with = "nullable_de")]
+    domain_separator: Option<Option<H256>>,
+    #[serde(default, with = "ts_seconds_option")]
+    timestamp: Option<DateTime<Utc>>, /* time named by the challenge signed */
+    #[serde(default)]
+    signature: Option<String>, /* by the operator, if setting the separator */
+}
+
 /// Represents an API request to create a new order
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct CreateOrderRequest {
+    #[serde(default)]
+    id: Option<OrderId>, /* as computed by the client, checked if given */
     user: Address,          /* Ethereum address of trader */
     target_tracer: Address, /* Ethereum address of the Tracer smart contract */
     side: OrderSide,        /* side of the market of the order */
@@ -45,6 +78,40 @@ pub struct CreateOrderRequest {
     #[serde(with = "ts_seconds")]
     created: DateTime<Utc>, /* creation time of the order */
     signed_data: String,    /* digital signature of the order */
+    #[serde(default)]
+    order_type: OrderType, /* limit (default) or market */
+    #[serde(default)]
+    max_slippage: Option<u32>, /* market orders only, in basis points */
+    #[serde(default)]
+    time_in_force: TimeInForce, /* GTC (default), GTD, IOC or FOK */
+    #[serde(default)]
+    post_only: Option<PostOnly>, /* reject or reprice if taking liquidity */
+    #[serde(
+        default,
+        serialize_with = "from_hex_opt_se",
+        deserialize_with = "from_hex_opt_de"
+    )]
+    stop_price: Option<U256>, /* stop and stop-limit orders only */
+    #[serde(
+        default,
+        serialize_with = "from_hex_opt_se",
+        deserialize_with = "from_hex_opt_de"
+    )]
+    display_quantity: Option<U256>, /* iceberg orders only */
+    #[serde(default)]
+    self_trade_prevention: Option<SelfTradePrevention>, /* else market's */
+}
+
+/// Represents the response to an API request to create a new order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateOrderResponse {
+    pub status: u16,
+    pub message: String,
+    pub filled: String, /* quantity matched upon submission */
+    pub average_price: Option<String>, /* average price of those matches */
+    pub price: Option<String>, /* price now resting at, if repriced */
+    pub self_trades: Vec<SelfTrade>, /* self-trades prevented, if any */
+    pub matches: Vec<MatchId>, /* matches queued for the executioner */
 }
 
 impl From<CreateOrderRequest> for ExternalOrder {
@@ -84,8 +151,189 @@ impl From<CreateOrderRequest> for ExternalOrder {
     }
 }
 
+impl CreateOrderResponse {
+    /// Constructs the response to the submission of an order, given the
+    /// outcome of its execution and the order as it now rests in the book
+    fn new(
+        status: StatusCode,
+        execution: Execution,
+        resting: Option<&Order>,
+        matches: Vec<MatchId>,
+    ) -> Self {
+        Self {
+            status: status.as_u16(),
+            message: execution.status.to_string(),
+            filled: execution.filled.to_string(),
+            average_price: execution.average_price.map(|p| p.to_string()),
+            price: match execution.status {
+                OrderStatus::Repriced => {
+                    resting.map(|order| order.price.to_string())
+                }
+                _ => None,
+            },
+            self_trades: execution.self_trades,
+            matches,
+        }
+    }
+}
+
 pub type UpdateOrderRequest = CreateOrderRequest;
 
+/// Represents the response to an API request to amend an order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpdateOrderResponse {
+    pub order_id: OrderId, /* ID of the order as amended */
+    #[serde(flatten)]
+    pub execution: CreateOrderResponse,
+}
+
+/// Represents an API request to cancel many orders at once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MassCancelRequest {
+    trader: Address,
+    #[serde(default)]
+    side: Option<OrderSide>, /* both sides if unset */
+    #[serde(with = "ts_seconds")]
+    timestamp: DateTime<Utc>, /* time named by the challenge signed */
+    signature: String, /* signature of the challenge (see `auth`) */
+}
+
+/// Represents the cancellation of a single order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cancellation {
+    pub order_id: OrderId,
+    pub market: Address,
+    #[serde(with = "ts_seconds")]
+    pub timestamp: DateTime<Utc>, /* time of cancellation */
+}
+
+/// Represents the response to an API request to cancel many orders at once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MassCancelResponse {
+    pub status: u16,
+    pub message: String,
+    pub cancelled: Vec<Cancellation>,
+}
+
+/// Represents a heartbeat from a trader
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeartbeatRequest {
+    trader: Address,
+    timeout: u64, /* seconds until the next heartbeat is due, zero disarms */
+    #[serde(with = "ts_seconds")]
+    timestamp: DateTime<Utc>, /* time named by the challenge signed */
+    signature: String, /* signature of the challenge (see `auth`) */
+}
+
+/// Represents the response to a heartbeat
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeartbeatResponse {
+    pub status: u16,
+    pub message: String,
+    #[serde(with = "ts_seconds")]
+    pub deadline: DateTime<Utc>, /* time by which the next is due */
+}
+
+/// Represents a request to subscribe to the order events of a trader
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeedRequest {
+    #[serde(with = "ts_seconds")]
+    timestamp: DateTime<Utc>, /* time named by the challenge signed */
+    signature: String, /* signature of the challenge (see `auth`) */
+}
+
+/// Represents the optional authentication of a request to read orders, by
+/// which a trader is shown the hidden reserve of their own iceberg orders
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct InspectRequest {
+    #[serde(default, with = "ts_seconds_option")]
+    timestamp: Option<DateTime<Utc>>, /* time named by the challenge signed */
+    #[serde(default)]
+    signature: Option<String>, /* signature of the challenge (see `auth`) */
+}
+
+/// Converts an API request to create (or amend) an order into an order,
+/// checking that it is well-formed
+///
+/// Returns the reason the order is invalid if it is not.
+fn parse_order(request: CreateOrderRequest) -> Result<Order, &'static str> {
+    /* bounds check price and amount */
+    if request.price > U256::from(u128::MAX)
+        || request.amount > U256::from(u128::MAX)
+        || request.stop_price.unwrap_or_default() > U256::from(u128::MAX)
+        || request.display_quantity.unwrap_or_default() > U256::from(u128::MAX)
+    {
+        return Err("Integer out of bounds");
+    }
+
+    let order_type: OrderType = request.order_type;
+    let max_slippage: Option<u32> = request.max_slippage;
+    let time_in_force: TimeInForce = request.time_in_force;
+    let post_only: Option<PostOnly> = request.post_only;
+    let stop_price: Option<U256> = request.stop_price;
+    let display_quantity: Option<U256> = request.display_quantity;
+    let self_trade_prevention: Option<SelfTradePrevention> =
+        request.self_trade_prevention;
+    let id: Option<OrderId> = request.id;
+
+    let mut order: Order = match Order::try_from(ExternalOrder::from(request)) {
+        Ok(t) => Order {
+            order_type,
+            max_slippage,
+            time_in_force,
+            post_only,
+            stop_price,
+            display_quantity,
+            self_trade_prevention,
+            ..t
+        },
+        Err(_e) => return Err("Invalid order"),
+    };
+
+    /* the ID covers every field signed, not just those of `ExternalOrder` */
+    order.id = order::order_id(&order);
+
+    /* the client must agree with the OME on which order it signed */
+    if matches!(id, Some(id) if id != order.id) {
+        return Err("Order ID does not match order");
+    }
+
+    if order.is_expired(Utc::now()) {
+        return Err("Order has expired");
+    }
+
+    /* post-only orders only make sense if they may rest in the book */
+    if order.post_only.is_some() && !order.rests() {
+        return Err("Post-only order cannot rest in book");
+    }
+
+    /* likewise for iceberg orders, which must also show some quantity */
+    if let Some(display_quantity) = order.display_quantity {
+        if display_quantity.is_zero() || !order.rests() {
+            return Err("Invalid display quantity");
+        }
+    }
+
+    Ok(order)
+}
+
+/// Checks that `order` was signed by its trader for the market of `handle`
+///
+/// Markets without a domain separator accept no orders at all, as there is
+/// nothing to check their signatures against.
+async fn verify_signature(
+    handle: &Market,
+    order: &Order,
+) -> Result<(), MarketError> {
+    let domain_separator: Option<H256> =
+        handle.inspect(|book| book.domain_separator).await?;
+
+    match domain_separator {
+        Some(t) => Ok(signature::verify(t, order)?),
+        None => Err(MarketError::Unconfigured),
+    }
+}
+
 /// HEALTH POINT HANDLER
 pub async fn health_check_handler() -> Result<impl Reply, Infallible> {
     let status: StatusCode = http::StatusCode::OK;
@@ -99,17 +347,203 @@ pub async fn health_check_handler() -> Result<impl Reply, Infallible> {
     ))
 }
 
+/// REST API route handler for heartbeats
+///
+/// Once a trader has sent a heartbeat, every one of their orders is cancelled
+/// should they fail to send another before its timeout elapses.
+pub async fn heartbeat_handler(
+    request: HeartbeatRequest,
+    heartbeats: Arc<Mutex<Heartbeats>>,
+) -> Result<impl Reply, Rejection> {
+    if let Err(e) = authenticate(
+        Action::Heartbeat {
+            timeout: request.timeout,
+        },
+        request.trader,
+        request.timestamp,
+        &request.signature,
+    ) {
+        return Ok(unauthorized_reply(request.trader, e));
+    }
+
+    if request.timeout > heartbeat::MAX_TIMEOUT {
+        let status: StatusCode = StatusCode::BAD_REQUEST;
+        let resp_body: OmeResponse = OmeResponse {
+            status: status.as_u16(),
+            message: "Invalid timeout".to_string(),
+        };
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&resp_body),
+            status,
+        )
+        .into_response());
+    }
+
+    let mut heartbeats: MutexGuard<Heartbeats> = heartbeats.lock().await;
+
+    if request.timeout == 0 {
+        heartbeats.disarm(request.trader);
+        info!("Disarmed heartbeat of {}", request.trader);
+
+        let status: StatusCode = StatusCode::OK;
+        let resp_body: OmeResponse = OmeResponse {
+            status: status.as_u16(),
+            message: "Heartbeat disarmed".to_string(),
+        };
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&resp_body),
+            status,
+        )
+        .into_response());
+    }
+
+    let deadline: DateTime<Utc> = heartbeats.beat(
+        request.trader,
+        chrono::Duration::seconds(request.timeout as i64),
+        Utc::now(),
+    );
+    info!("Heartbeat of {} due again by {}", request.trader, deadline);
+
+    let status: StatusCode = StatusCode::OK;
+    let resp_body: HeartbeatResponse = HeartbeatResponse {
+        status: status.as_u16(),
+        message: "Heartbeat received".to_string(),
+        deadline,
+    };
+    Ok(
+        warp::reply::with_status(warp::reply::json(&resp_body), status)
+            .into_response(),
+    )
+}
+
+/// Checks that `signature`, in hexadecimal, authenticates `trader` to take
+/// `action` at `timestamp` (see `auth`)
+fn authenticate(
+    action: Action,
+    trader: Address,
+    timestamp: DateTime<Utc>,
+    signature: &str,
+) -> Result<(), AuthError> {
+    let signature: Vec<u8> =
+        hex::decode(signature.trim_start_matches("0x")).unwrap_or_default();
+
+    auth::authenticate(action, trader, timestamp, &signature, Utc::now())
+}
+
+/// Returns whether `request` authenticates `trader` to see their own orders
+/// in full, hidden reserve included
+///
+/// Requests naming neither a timestamp nor a signature are shown orders only
+/// as the order book shows them (see `Order::public`).
+fn inspects_own(
+    trader: Address,
+    request: &InspectRequest,
+) -> Result<bool, AuthError> {
+    match (request.timestamp, &request.signature) {
+        (Some(timestamp), Some(signature)) => {
+            authenticate(Action::Inspect, trader, timestamp, signature)
+                .map(|_| true)
+        }
+        (None, None) => Ok(false),
+        _ => Err(AuthError::InvalidSignature),
+    }
+}
+
+/// Constructs the response to a request that `trader` failed to authenticate
+fn unauthorized_reply(
+    trader: Address,
+    error: AuthError,
+) -> warp::reply::Response {
+    warn!("Failed to authenticate trader {}: {}", trader, error);
+
+    let status: StatusCode = StatusCode::UNAUTHORIZED;
+    let resp_body: OmeResponse = OmeResponse {
+        status: status.as_u16(),
+        message: "Invalid or expired signature".to_string(),
+    };
+    warp::reply::with_status(json(&resp_body), status).into_response()
+}
+
+/// Constructs the response to a command that `market` failed to apply,
+/// where `subject` names what the command would have persisted
+fn market_error_reply(
+    market: Address,
+    error: MarketError,
+    subject: &str,
+) -> warp::reply::Response {
+    let (status, message): (StatusCode, String) = match error {
+        MarketError::MarketExists => {
+            (StatusCode::CONFLICT, "Market already exists".to_string())
+        }
+        MarketError::MarketNotFound => {
+            (StatusCode::NOT_FOUND, "Market does not exist".to_string())
+        }
+        MarketError::OrderNotFound => (
+            StatusCode::NOT_FOUND,
+            "Order does not exist in this market".to_string(),
+        ),
+        MarketError::StopNotFound => (
+            StatusCode::NOT_FOUND,
+            "Stop order does not exist in this market".to_string(),
+        ),
+        MarketError::InvalidAmendment => {
+            (StatusCode::BAD_REQUEST, "Invalid amendment".to_string())
+        }
+        MarketError::SignatureReused => (
+            StatusCode::BAD_REQUEST,
+            "Amended order must be signed anew".to_string(),
+        ),
+        MarketError::InvalidSignature => (
+            StatusCode::BAD_REQUEST,
+            "Order not signed by its trader".to_string(),
+        ),
+        MarketError::Unconfigured => (
+            StatusCode::CONFLICT,
+            "Market has no domain separator set".to_string(),
+        ),
+        MarketError::DuplicateOrder => {
+            (StatusCode::CONFLICT, "Order already submitted".to_string())
+        }
+        MarketError::OrderFilled => {
+            (StatusCode::CONFLICT, "Order already filled".to_string())
+        }
+        MarketError::OrderCancelled => {
+            (StatusCode::CONFLICT, "Order already cancelled".to_string())
+        }
+        MarketError::JournalError => {
+            warn!("Failed to journal {} in market {}", subject, market);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to persist {}", subject),
+            )
+        }
+        MarketError::BookError => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Matching error occurred".to_string(),
+        ),
+        MarketError::Unavailable => {
+            error!("Task of market {} has stopped", market);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Market unavailable".to_string(),
+            )
+        }
+    };
+
+    let resp_body: OmeResponse = OmeResponse {
+        status: status.as_u16(),
+        message,
+    };
+    warp::reply::with_status(warp::reply::json(&resp_body), status)
+        .into_response()
+}
+
 /// REST API route handler for listing all order books
 pub async fn index_book_handler(
-    state: Arc<Mutex<OmeState>>,
+    markets: Markets,
 ) -> Result<impl Reply, Infallible> {
-    let ome_state: MutexGuard<OmeState> = state.lock().await;
-
     let mut result: HashMap<String, Vec<Address>> = HashMap::new();
-    result.insert(
-        "markets".to_string(),
-        ome_state.books().keys().cloned().collect(),
-    );
+    result.insert("markets".to_string(), markets.addresses().await);
 
     Ok(json(&result))
 }
@@ -117,32 +551,17 @@ pub async fn index_book_handler(
 /// REST API route handler for creating new order books
 pub async fn create_book_handler(
     request: CreateBookRequest,
-    state: Arc<Mutex<OmeState>>,
+    markets: Markets,
 ) -> Result<impl Reply, Rejection> {
-    /* build our new order book */
     let market: Address = request.market;
-    let new_book: Book = Book::new(market);
 
     info!("Creating book {}...", market);
 
-    let mut ome_state: MutexGuard<OmeState> = state.lock().await;
-
-    /* check if the market already exists and, if so, return HTTP 409 */
-    if ome_state.book(market).is_some() {
-        let status: StatusCode = StatusCode::CONFLICT;
-        let resp_body: OmeResponse = OmeResponse {
-            status: status.as_u16(),
-            message: "Market already exists".to_string(),
-        };
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&resp_body),
-            status,
-        ));
+    /* persist the new book, then start matching in it */
+    if let Err(e) = markets.add(market).await {
+        return Ok(market_error_reply(market, e, "market"));
     }
 
-    /* add the new book to the engine state */
-    ome_state.add_book(new_book);
-
     info!("Created book {}", market);
 
     /* indicate success to the caller */
@@ -151,83 +570,262 @@ pub async fn create_book_handler(
         status: status.as_u16(),
         message: "Market created".to_string(),
     };
-    Ok(warp::reply::with_status(
-        warp::reply::json(&resp_body),
-        status,
-    ))
+    Ok(
+        warp::reply::with_status(warp::reply::json(&resp_body), status)
+            .into_response(),
+    )
 }
 
 /// REST API route handler for retrieving a single order book
 pub async fn read_book_handler(
     market: Address,
-    state: Arc<Mutex<OmeState>>,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    let book: Result<ExternalBook, MarketError> =
+        match markets.get(market).await {
+            Some(t) => t.inspect(|book| ExternalBook::from(book.clone())).await,
+            None => Err(MarketError::MarketNotFound),
+        };
+
+    match book {
+        Ok(payload) => Ok(json(&payload).into_response()),
+        Err(MarketError::MarketNotFound) => Ok(warp::reply::with_status(
+            "Market does not exist".to_string(),
+            http::StatusCode::NOT_FOUND,
+        )
+        .into_response()),
+        Err(e) => Ok(market_error_reply(market, e, "read")),
+    }
+}
+
+/// WebSocket route handler for subscribing to the depth (i.e., the L2 market
+/// data) of a single order book
+pub async fn depth_handler(
+    market: Address,
+    ws: Ws,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    match markets.get(market).await {
+        Some(handle) => Ok(ws
+            .on_upgrade(move |socket| depth::serve(socket, handle))
+            .into_response()),
+        None => Ok(market_error_reply(
+            market,
+            MarketError::MarketNotFound,
+            "depth",
+        )),
+    }
+}
+
+/// Server-sent events route handler for subscribing to the trades of a single
+/// order book, resuming after the trade with ID `last_event_id` if given
+pub async fn trades_handler(
+    market: Address,
+    last_event_id: Option<String>,
+    markets: Markets,
 ) -> Result<impl Reply, Rejection> {
-    let ome_state: MutexGuard<OmeState> = state.lock().await;
-    let book: Book = match ome_state.book(market) {
-        Some(t) => t.clone(),
+    let last: Option<TradeId> = match last_event_id.map(|id| id.parse()) {
+        Some(Ok(t)) => Some(t),
+        Some(Err(_e)) => {
+            let status: StatusCode = StatusCode::BAD_REQUEST;
+            let resp_body: OmeResponse = OmeResponse {
+                status: status.as_u16(),
+                message: "Invalid Last-Event-ID".to_string(),
+            };
+            return Ok(warp::reply::with_status(json(&resp_body), status)
+                .into_response());
+        }
+        None => None,
+    };
+
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
         None => {
-            return Ok(warp::reply::with_status(
-                "Market does not exist".to_string(),
-                http::StatusCode::NOT_FOUND,
-            )
-            .into_response());
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "trades",
+            ))
         }
     };
-    let payload: ExternalBook = ExternalBook::from(book);
-    Ok(json(&payload).into_response())
+
+    match handle.trades(last).await {
+        Ok((backlog, trades)) => Ok(warp::sse::reply(
+            warp::sse::keep_alive().stream(tape::events(backlog, trades)),
+        )
+        .into_response()),
+        Err(e) => Ok(market_error_reply(market, e, "trades")),
+    }
 }
 
-/// REST API route handler for creating a single order
-pub async fn create_order_handler(
+/// WebSocket route handler for subscribing to the events concerning the
+/// orders of a single trader
+pub async fn feed_handler(
+    trader: Address,
+    request: FeedRequest,
+    ws: Ws,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    if let Err(e) = authenticate(
+        Action::Subscribe,
+        trader,
+        request.timestamp,
+        &request.signature,
+    ) {
+        return Ok(unauthorized_reply(trader, e));
+    }
+
+    /* subscribe now, so that no event is missed while upgrading */
+    let events: broadcast::Receiver<OrderEvent> = markets.events().subscribe();
+
+    Ok(ws
+        .on_upgrade(move |socket| feed::serve(socket, trader, events))
+        .into_response())
+}
+
+/// REST API route handler for updating the configuration of an order book
+///
+/// Only the operator may set the domain separator of a market, as it decides
+/// which signatures the market accepts.
+pub async fn update_book_handler(
     market: Address,
-    request: CreateOrderRequest,
-    state: Arc<Mutex<OmeState>>,
-    rpc_endpoint: String,
+    request: UpdateBookRequest,
+    operator: Option<Address>,
+    markets: Markets,
 ) -> Result<impl Reply, Rejection> {
-    /* bounds check price and amount */
-    if request.price > U256::from(u128::MAX)
-        || request.amount > U256::from(u128::MAX)
-    {
-        let status: StatusCode = http::StatusCode::BAD_REQUEST;
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "market",
+            ))
+        }
+    };
+
+    /* unsetting the separator would stop the OME verifying signatures */
+    if request.domain_separator == Some(None) {
+        let status: StatusCode = StatusCode::BAD_REQUEST;
         let resp_body: OmeResponse = OmeResponse {
             status: status.as_u16(),
-            message: "Integer out of bounds".to_string(),
+            message: "Domain separator cannot be unset".to_string(),
         };
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&resp_body),
-            status,
-        ));
+        return Ok(
+            warp::reply::with_status(json(&resp_body), status).into_response()
+        );
+    }
+
+    if let Some(Some(domain_separator)) = request.domain_separator {
+        let operator: Address = match operator {
+            Some(t) => t,
+            None => {
+                let status: StatusCode = StatusCode::FORBIDDEN;
+                let resp_body: OmeResponse = OmeResponse {
+                    status: status.as_u16(),
+                    message: "No operator configured".to_string(),
+                };
+                return Ok(warp::reply::with_status(json(&resp_body), status)
+                    .into_response());
+            }
+        };
+
+        let authenticated: Result<(), AuthError> =
+            match (request.timestamp, &request.signature) {
+                (Some(timestamp), Some(signature)) => authenticate(
+                    Action::Configure {
+                        market,
+                        domain_separator,
+                    },
+                    operator,
+                    timestamp,
+                    signature,
+                ),
+                _ => Err(AuthError::InvalidSignature),
+            };
+
+        if let Err(e) = authenticated {
+            return Ok(unauthorized_reply(operator, e));
+        }
     }
 
-    let new_order: ExternalOrder = ExternalOrder::from(request);
+    /* persist the new configuration, then apply it */
+    if let Err(e) = handle
+        .apply(JournalEvent::ConfigureBook {
+            market,
+            self_trade_prevention: request.self_trade_prevention,
+            domain_separator: request.domain_separator,
+        })
+        .await
+    {
+        return Ok(market_error_reply(market, e, "market"));
+    }
+
+    info!("Updated book {}", market);
+
+    let status: StatusCode = http::StatusCode::OK;
+    let resp_body: OmeResponse = OmeResponse {
+        status: status.as_u16(),
+        message: "Market updated".to_string(),
+    };
+    Ok(
+        warp::reply::with_status(warp::reply::json(&resp_body), status)
+            .into_response(),
+    )
+}
 
-    let internal_order: Order = match Order::try_from(new_order.clone()) {
+/// REST API route handler for creating a single order
+pub async fn create_order_handler(
+    market: Address,
+    request: CreateOrderRequest,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    let internal_order: Order = match parse_order(request) {
         Ok(t) => t,
-        Err(_e) => {
+        Err(message) => {
             let status: StatusCode = StatusCode::BAD_REQUEST;
             let resp_body: OmeResponse = OmeResponse {
                 status: status.as_u16(),
-                message: "Invalid order".to_string(),
+                message: message.to_string(),
             };
             return Ok(warp::reply::with_status(
                 warp::reply::json(&resp_body),
                 status,
-            ));
+            )
+            .into_response());
         }
     };
 
     info!("Creating order {}...", internal_order.clone());
 
-    let valid_order: bool = match rpc::check_order_validity(
-        Order::try_from(new_order.clone()).unwrap(),
-        rpc_endpoint.clone(),
-    )
-    .await
-    {
-        Ok(t) => t,
-        Err(_e) => false,
+    /* check that the order book exists */
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            warn!(
+                "Failed to create order {} as market does not exist!",
+                internal_order
+            );
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "order",
+            ));
+        }
     };
 
+    /* check the signature locally before asking the executioner */
+    if let Err(e) = verify_signature(&handle, &internal_order).await {
+        warn!("Failed to create order {}! {}", internal_order, e);
+        return Ok(market_error_reply(market, e, "order"));
+    }
+
+    let valid_order: bool = markets
+        .executioner()
+        .check(internal_order.clone())
+        .await
+        .unwrap_or_default();
+
     if !valid_order {
         let status: StatusCode = warp::http::StatusCode::BAD_REQUEST;
         let resp_body: OmeResponse = OmeResponse {
@@ -237,60 +835,43 @@ pub async fn create_order_handler(
         return Ok(warp::reply::with_status(
             warp::reply::json(&resp_body),
             status,
-        ));
+        )
+        .into_response());
     }
 
-    /* acquire lock on global state */
-    let mut ome_state: MutexGuard<OmeState> = state.lock().await;
-
-    /* retrieve order book from global state */
-    let book: &mut Book = match ome_state.book_mut(market) {
-        Some(b) => b,
-        None => {
-            warn!(
-                "Failed to create order {:?} as market does not exist!",
-                new_order
-            );
-            let status: StatusCode = warp::http::StatusCode::NOT_FOUND;
-            let resp_body: OmeResponse = OmeResponse {
-                status: status.as_u16(),
-                message: "Market does not exist".to_string(),
-            };
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&resp_body),
-                status,
-            ));
-        }
-    };
-
     /* submit order to the engine for matching */
-    match book
-        .submit(Order::try_from(new_order.clone()).unwrap(), rpc_endpoint)
+    match handle
+        .apply(JournalEvent::Submit {
+            market,
+            order: internal_order.clone(),
+        })
         .await
     {
-        Ok(order_status) => {
+        Ok(Outcome::Executed {
+            execution,
+            resting,
+            matches,
+        }) => {
             info!("Created order {}", internal_order.clone());
             let status: StatusCode = StatusCode::OK;
-            let resp_body: OmeResponse = OmeResponse {
-                status: status.as_u16(),
-                message: order_status.to_string(),
-            };
-            Ok(warp::reply::with_status(
-                warp::reply::json(&resp_body),
+            let resp_body: CreateOrderResponse = CreateOrderResponse::new(
                 status,
-            ))
+                execution,
+                resting.as_deref(),
+                matches,
+            );
+            Ok(
+                warp::reply::with_status(warp::reply::json(&resp_body), status)
+                    .into_response(),
+            )
         }
+        Ok(_) => unreachable!(), /* submissions are always executed */
         Err(e) => {
-            warn!("Failed to create order {:?}! Engine said: {}", new_order, e);
-            let status: StatusCode = StatusCode::INTERNAL_SERVER_ERROR;
-            let resp_body: OmeResponse = OmeResponse {
-                status: status.as_u16(),
-                message: "Matching error occurred".to_string(),
-            };
-            Ok(warp::reply::with_status(
-                warp::reply::json(&resp_body),
-                status,
-            ))
+            warn!(
+                "Failed to create order {}! Engine said: {}",
+                internal_order, e
+            );
+            Ok(market_error_reply(market, e, "order"))
         }
     }
 }
@@ -299,43 +880,145 @@ pub async fn create_order_handler(
 pub async fn read_order_handler(
     market: Address,
     id: OrderId,
-    state: Arc<Mutex<OmeState>>,
+    request: InspectRequest,
+    markets: Markets,
 ) -> Result<impl Reply, Rejection> {
-    let ome_state: MutexGuard<OmeState> = state.lock().await;
-
     /* retrieve order book */
-    let book: &Book = match ome_state.book(market) {
-        Some(b) => b,
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
         None => {
-            let status: StatusCode = warp::http::StatusCode::NOT_FOUND;
-            let resp_body: OmeResponse = OmeResponse {
-                status: status.as_u16(),
-                message: "Market does not exist".to_string(),
-            };
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&resp_body),
-                status,
-            ));
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "read",
+            ))
         }
     };
 
     /* retrieve order */
-    let order: ExternalOrder = match book.order(id) {
-        Some(o) => o.clone().into(),
-        None => {
-            let status: StatusCode = warp::http::StatusCode::NOT_FOUND;
+    let order: Order =
+        match handle.inspect(move |book| book.order(id).cloned()).await {
+            Ok(Some(o)) => o,
+            Ok(None) => {
+                return Ok(market_error_reply(
+                    market,
+                    MarketError::OrderNotFound,
+                    "read",
+                ))
+            }
+            Err(e) => return Ok(market_error_reply(market, e, "read")),
+        };
+
+    /* only its trader is shown any hidden reserve */
+    let order: ExternalOrder = match inspects_own(order.trader, &request) {
+        Ok(true) => order.into(),
+        Ok(false) => order.public().into(),
+        Err(e) => return Ok(unauthorized_reply(order.trader, e)),
+    };
+
+    Ok(warp::reply::with_status(json(&order), StatusCode::OK).into_response())
+}
+
+/// REST API route handler for amending a single order
+///
+/// The amended order replaces the original, and so must be signed anew.
+pub async fn update_order_handler(
+    market: Address,
+    id: OrderId,
+    request: UpdateOrderRequest,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    let amended_order: Order = match parse_order(request) {
+        Ok(t) => t,
+        Err(message) => {
+            let status: StatusCode = StatusCode::BAD_REQUEST;
             let resp_body: OmeResponse = OmeResponse {
                 status: status.as_u16(),
-                message: "Order does not exist in this market".to_string(),
+                message: message.to_string(),
             };
             return Ok(warp::reply::with_status(
                 warp::reply::json(&resp_body),
                 status,
-            ));
+            )
+            .into_response());
         }
     };
 
-    Ok(warp::reply::with_status(json(&order), StatusCode::OK))
+    info!("Amending order {} to {}...", id, amended_order);
+
+    /* retrieve order book */
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "amendment",
+            ))
+        }
+    };
+
+    /* check the new signature locally before asking the executioner */
+    if let Err(e) = verify_signature(&handle, &amended_order).await {
+        warn!("Failed to amend order {}! {}", id, e);
+        return Ok(market_error_reply(market, e, "amendment"));
+    }
+
+    let valid_order: bool = markets
+        .executioner()
+        .check(amended_order.clone())
+        .await
+        .unwrap_or_default();
+
+    if !valid_order {
+        let status: StatusCode = StatusCode::BAD_REQUEST;
+        let resp_body: OmeResponse = OmeResponse {
+            status: status.as_u16(),
+            message: "Invalid order".to_string(),
+        };
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&resp_body),
+            status,
+        )
+        .into_response());
+    }
+
+    /* the book checks that the amendment is valid before persisting it */
+    match handle
+        .apply(JournalEvent::Amend {
+            market,
+            order_id: id,
+            order: amended_order.clone(),
+        })
+        .await
+    {
+        Ok(Outcome::Executed {
+            execution,
+            resting,
+            matches,
+        }) => {
+            info!("Amended order {} to {}", id, amended_order);
+            let status: StatusCode = StatusCode::OK;
+            let resp_body: UpdateOrderResponse = UpdateOrderResponse {
+                order_id: amended_order.id,
+                execution: CreateOrderResponse::new(
+                    status,
+                    execution,
+                    resting.as_deref(),
+                    matches,
+                ),
+            };
+            Ok(
+                warp::reply::with_status(warp::reply::json(&resp_body), status)
+                    .into_response(),
+            )
+        }
+        Ok(_) => unreachable!(), /* amendments are always executed */
+        Err(e) => {
+            warn!("Failed to amend order {}! Engine said: {}", id, e);
+            Ok(market_error_reply(market, e, "amendment"))
+        }
+    }
 }
 
 /// REST API route handler for deleting a single order
@@ -344,13 +1027,11 @@ pub async fn read_order_handler(
 pub async fn destroy_order_handler(
     market: Address,
     id: OrderId,
-    state: Arc<Mutex<OmeState>>,
+    markets: Markets,
 ) -> Result<impl Reply, Rejection> {
-    let mut ome_state: MutexGuard<OmeState> = state.lock().await;
-
     /* retrieve order book */
-    let book: &mut Book = match ome_state.book_mut(market) {
-        Some(b) => b,
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
         None => {
             return Ok(warp::reply::with_status(
                 warp::reply::json(&"Market does not exist".to_string()),
@@ -360,10 +1041,16 @@ pub async fn destroy_order_handler(
         }
     };
 
-    /* cancel order */
-    match book.cancel(id) {
-        Ok(_t) => {}
-        Err(_e) => {
+    /* cancel order, which is only journalled if it actually exists */
+    match handle
+        .apply(JournalEvent::Cancel {
+            market,
+            order_id: id,
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(MarketError::OrderNotFound) => {
             return Ok(warp::reply::with_status(
                 warp::reply::json(
                     &"Order does not exist in market".to_string(),
@@ -372,7 +1059,8 @@ pub async fn destroy_order_handler(
             )
             .into_response());
         }
-    };
+        Err(e) => return Ok(market_error_reply(market, e, "cancellation")),
+    }
 
     let status: StatusCode = http::StatusCode::OK;
     let resp_body: OmeResponse = OmeResponse {
@@ -385,53 +1073,292 @@ pub async fn destroy_order_handler(
     )
 }
 
-#[allow(clippy::into_iter_on_ref)]
-pub async fn market_user_orders_handler(
+/// REST API route handler for cancelling every order of a single trader in a
+/// market, optionally only those on one side
+pub async fn destroy_market_orders_handler(
     market: Address,
-    user: Address,
-    state: Arc<Mutex<OmeState>>,
+    request: MassCancelRequest,
+    markets: Markets,
 ) -> Result<impl Reply, Rejection> {
-    let mut ome_state: MutexGuard<OmeState> = state.lock().await;
+    mass_cancel(Some(market), request, markets).await
+}
 
-    /* retrieve order book */
-    let book: &mut Book = match ome_state.book_mut(market) {
-        Some(b) => b,
+/// REST API route handler for cancelling every order of a single trader in
+/// every market, optionally only those on one side
+pub async fn destroy_orders_handler(
+    request: MassCancelRequest,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    mass_cancel(None, request, markets).await
+}
+
+async fn mass_cancel(
+    market: Option<Address>,
+    request: MassCancelRequest,
+    markets: Markets,
+) -> Result<warp::reply::Response, Rejection> {
+    if let Err(e) = authenticate(
+        Action::Cancel,
+        request.trader,
+        request.timestamp,
+        &request.signature,
+    ) {
+        return Ok(unauthorized_reply(request.trader, e));
+    }
+
+    let orders: Vec<Order> = match markets
+        .mass_cancel(market, request.trader, request.side)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => {
+            warn!("Failed to mass cancel orders: {}", e);
+            return Ok(market_error_reply(
+                market.unwrap_or_default(),
+                e,
+                "cancellation",
+            ));
+        }
+    };
+
+    let now: DateTime<Utc> = Utc::now();
+    let cancelled: Vec<Cancellation> = orders
+        .iter()
+        .map(|order| Cancellation {
+            order_id: order.id,
+            market: order.market,
+            timestamp: now,
+        })
+        .collect();
+
+    info!("Mass cancelled {} orders", cancelled.len());
+
+    let status: StatusCode = StatusCode::OK;
+    let resp_body: MassCancelResponse = MassCancelResponse {
+        status: status.as_u16(),
+        message: "Orders cancelled".to_string(),
+        cancelled,
+    };
+    Ok(
+        warp::reply::with_status(warp::reply::json(&resp_body), status)
+            .into_response(),
+    )
+}
+
+/// REST API route handler for retrieving the delivery status of a single
+/// match
+pub async fn read_match_handler(
+    id: MatchId,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    let outbox: MutexGuard<Outbox> = markets.outbox().lock().await;
+
+    match outbox.delivery(id) {
+        Some(delivery) => {
+            let delivery: ExternalDelivery = delivery.clone().into();
+            Ok(warp::reply::with_status(json(&delivery), StatusCode::OK)
+                .into_response())
+        }
         None => {
             let status: StatusCode = StatusCode::NOT_FOUND;
             let resp_body: OmeResponse = OmeResponse {
                 status: status.as_u16(),
-                message: "Market does not exist".to_string(),
+                message: "Match not found".to_string(),
             };
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&resp_body),
-                status,
-            )
-            .into_response());
+            Ok(warp::reply::with_status(json(&resp_body), status)
+                .into_response())
         }
-    };
+    }
+}
 
-    let bids: Vec<Order> = book
-        .bids
-        .values()
-        .into_iter()
-        .flat_map(|levels| levels.into_iter().filter(|o| o.trader == user))
-        .cloned()
-        .collect();
+/// REST API route handler for listing the delivery status of every match of a
+/// market still retained by the outbox
+pub async fn index_match_handler(
+    market: Address,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    if markets.get(market).await.is_none() {
+        return Ok(market_error_reply(
+            market,
+            MarketError::MarketNotFound,
+            "read",
+        ));
+    }
 
-    let asks: Vec<Order> = book
-        .asks
-        .values()
+    let outbox: MutexGuard<Outbox> = markets.outbox().lock().await;
+    let deliveries: Vec<ExternalDelivery> = outbox
+        .deliveries_in(market)
         .into_iter()
-        .flat_map(|levels| levels.into_iter().filter(|o| o.trader == user))
         .cloned()
+        .map(|delivery: Delivery| delivery.into())
         .collect();
 
-    let orders: Vec<ExternalOrder> = bids
-        .iter()
-        .cloned()
-        .chain(asks.iter().cloned())
-        .map(ExternalOrder::from)
-        .collect();
+    Ok(warp::reply::with_status(json(&deliveries), StatusCode::OK)
+        .into_response())
+}
+
+/// REST API route handler for listing every stop order of a market
+pub async fn index_stop_handler(
+    market: Address,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    /* retrieve order book */
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "read",
+            ))
+        }
+    };
+
+    let stops: Vec<ExternalStopOrder> = match handle
+        .inspect(|book| {
+            book.stops
+                .orders()
+                .map(|order| ExternalStopOrder::from(order.public()))
+                .collect()
+        })
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => return Ok(market_error_reply(market, e, "read")),
+    };
+
+    Ok(warp::reply::with_status(json(&stops), StatusCode::OK).into_response())
+}
+
+/// REST API route handler for retrieving a single stop order
+pub async fn read_stop_handler(
+    market: Address,
+    id: OrderId,
+    request: InspectRequest,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    /* retrieve order book */
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "read",
+            ))
+        }
+    };
+
+    /* retrieve stop order */
+    let order: Order =
+        match handle.inspect(move |book| book.stop(id).cloned()).await {
+            Ok(Some(o)) => o,
+            Ok(None) => {
+                return Ok(market_error_reply(
+                    market,
+                    MarketError::StopNotFound,
+                    "read",
+                ))
+            }
+            Err(e) => return Ok(market_error_reply(market, e, "read")),
+        };
+
+    /* only its trader is shown any hidden reserve */
+    let order: ExternalStopOrder = match inspects_own(order.trader, &request) {
+        Ok(true) => order.into(),
+        Ok(false) => order.public().into(),
+        Err(e) => return Ok(unauthorized_reply(order.trader, e)),
+    };
+
+    Ok(warp::reply::with_status(json(&order), StatusCode::OK).into_response())
+}
+
+/// REST API route handler for cancelling a single stop order
+pub async fn destroy_stop_handler(
+    market: Address,
+    id: OrderId,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    /* retrieve order book */
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "cancellation",
+            ))
+        }
+    };
+
+    /* cancel stop order, which is only journalled if it actually exists */
+    if let Err(e) = handle
+        .apply(JournalEvent::CancelStop {
+            market,
+            order_id: id,
+        })
+        .await
+    {
+        return Ok(market_error_reply(market, e, "cancellation"));
+    }
+
+    let status: StatusCode = http::StatusCode::OK;
+    let resp_body: OmeResponse = OmeResponse {
+        status: status.as_u16(),
+        message: "Stop order cancelled".to_string(),
+    };
+    Ok(
+        warp::reply::with_status(warp::reply::json(&resp_body), status)
+            .into_response(),
+    )
+}
+
+/// REST API route handler for listing every order of a single trader in a
+/// market
+///
+/// Only the trader themselves is shown the hidden reserve of their iceberg
+/// orders.
+pub async fn market_user_orders_handler(
+    market: Address,
+    user: Address,
+    request: InspectRequest,
+    markets: Markets,
+) -> Result<impl Reply, Rejection> {
+    let own: bool = match inspects_own(user, &request) {
+        Ok(t) => t,
+        Err(e) => return Ok(unauthorized_reply(user, e)),
+    };
+
+    /* retrieve order book */
+    let handle: Market = match markets.get(market).await {
+        Some(t) => t,
+        None => {
+            return Ok(market_error_reply(
+                market,
+                MarketError::MarketNotFound,
+                "read",
+            ))
+        }
+    };
+
+    let orders: Vec<ExternalOrder> = match handle
+        .inspect(move |book| {
+            book.orders_of(user)
+                .into_iter()
+                .map(|order| {
+                    if own {
+                        ExternalOrder::from(order.clone())
+                    } else {
+                        ExternalOrder::from(order.public())
+                    }
+                })
+                .collect()
+        })
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => return Ok(market_error_reply(market, e, "read")),
+    };
 
     Ok(json(&orders).into_response())
 }
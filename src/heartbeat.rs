@@ -0,0 +1,142 @@
+//! Contains logic for cancelling the orders of traders who stop sending
+//! heartbeats (i.e., a dead man's switch)
+//!
+//! Traders opt in by sending a heartbeat along with a timeout. Should no
+//! further heartbeat arrive within that timeout, every order of the trader is
+//! cancelled across every book. Heartbeats are held in memory only, so traders
+//! must send a fresh heartbeat once the OME restarts.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use web3::types::Address;
+
+use crate::market::{MarketError, Markets};
+use crate::order::Order;
+
+/// The interval between checks for lapsed heartbeats
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest timeout, in seconds, that a trader may request
+pub const MAX_TIMEOUT: u64 = 86_400;
+
+/// Represents the deadline by which each registered trader must next send a
+/// heartbeat
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Heartbeats {
+    deadlines: HashMap<Address, DateTime<Utc>>,
+}
+
+impl Heartbeats {
+    /// Constructor for the `Heartbeats` type
+    pub fn new() -> Self {
+        Self {
+            deadlines: HashMap::new(),
+        }
+    }
+
+    /// Records a heartbeat from `trader` at `now`, requiring the next within
+    /// `timeout`
+    ///
+    /// Returns the deadline for the next heartbeat.
+    pub fn beat(
+        &mut self,
+        trader: Address,
+        timeout: chrono::Duration,
+        now: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        let deadline: DateTime<Utc> = now + timeout;
+        self.deadlines.insert(trader, deadline);
+        deadline
+    }
+
+    /// Stops requiring heartbeats from `trader`
+    ///
+    /// Returns whether heartbeats were required of them.
+    pub fn disarm(&mut self, trader: Address) -> bool {
+        self.deadlines.remove(&trader).is_some()
+    }
+
+    /// Returns the deadline for the next heartbeat from `trader`, if any
+    pub fn deadline(&self, trader: Address) -> Option<DateTime<Utc>> {
+        self.deadlines.get(&trader).copied()
+    }
+
+    /// Stops requiring heartbeats from every trader whose deadline has passed
+    /// as of `now`, returning them
+    pub fn lapsed(&mut self, now: DateTime<Utc>) -> Vec<Address> {
+        let lapsed: Vec<Address> = self
+            .deadlines
+            .iter()
+            .filter(|(_trader, deadline)| **deadline <= now)
+            .map(|(trader, _deadline)| *trader)
+            .collect();
+
+        for trader in lapsed.iter() {
+            self.deadlines.remove(trader);
+        }
+
+        lapsed
+    }
+}
+
+/// Cancels every order of `trader` in every book
+///
+/// Returns the orders cancelled.
+pub async fn trip(
+    markets: &Markets,
+    trader: Address,
+) -> Result<Vec<Order>, MarketError> {
+    markets.mass_cancel(None, trader, None).await
+}
+
+/// Cancels every order of every trader whose heartbeat has lapsed as of `now`
+///
+/// Returns the orders cancelled.
+pub async fn sweep(
+    heartbeats: &Arc<Mutex<Heartbeats>>,
+    markets: &Markets,
+    now: DateTime<Utc>,
+) -> Vec<Order> {
+    let lapsed: Vec<Address> = heartbeats.lock().await.lapsed(now);
+    let mut cancelled: Vec<Order> = vec![];
+
+    for trader in lapsed {
+        match trip(markets, trader).await {
+            Ok(orders) => {
+                warn!(
+                    "Heartbeat of {} lapsed, cancelled {} orders",
+                    trader,
+                    orders.len()
+                );
+                cancelled.extend(orders);
+            }
+            Err(e) => {
+                error!("Failed to cancel orders of {}: {}", trader, e);
+
+                /* try again upon the next sweep */
+                heartbeats.lock().await.beat(
+                    trader,
+                    chrono::Duration::zero(),
+                    now,
+                );
+            }
+        }
+    }
+
+    cancelled
+}
+
+/// Cancels the orders of traders whose heartbeat has lapsed every
+/// `CHECK_INTERVAL`, forever
+pub async fn run(heartbeats: Arc<Mutex<Heartbeats>>, markets: Markets) {
+    let mut ticker: tokio::time::Interval =
+        tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        sweep(&heartbeats, &markets, Utc::now()).await;
+    }
+}
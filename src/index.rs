@@ -0,0 +1,107 @@
+//! Contains the indices an order book maintains over its resting orders
+//!
+//! The indices are derived entirely from the price levels of the book, so they
+//! are neither serialised nor compared: books are reindexed upon being loaded
+//! from a snapshot instead.
+//!
+//! The index locates each order by its side, price level and position within
+//! that level, which the order keeps for as long as it rests (see `Level`), so
+//! finding (or removing) an order takes constant time.
+use std::collections::{HashMap, HashSet};
+
+use ethereum_types::U256;
+use web3::types::Address;
+
+use crate::order::{Order, OrderId, OrderSide};
+
+/// Represents where in a book an order rests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub side: OrderSide,
+    pub price: U256,   /* of the level */
+    pub position: u64, /* within the level */
+}
+
+/// Represents the location of every resting order of a book, by order ID and
+/// by trader
+#[derive(Clone, Default, Debug)]
+pub struct OrderIndex {
+    locations: HashMap<OrderId, Location>,
+    traders: HashMap<Address, HashSet<OrderId>>,
+}
+
+impl OrderIndex {
+    /// Constructor for the `OrderIndex` type
+    pub fn new() -> Self {
+        Self {
+            locations: HashMap::new(),
+            traders: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of orders indexed
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Returns whether no orders are indexed
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Returns the location of the order with the provided ID
+    pub fn location(&self, id: OrderId) -> Option<Location> {
+        self.locations.get(&id).copied()
+    }
+
+    /// Returns the IDs of every order of `trader`
+    pub fn orders_of(&self, trader: Address) -> Option<&HashSet<OrderId>> {
+        self.traders.get(&trader)
+    }
+
+    /// Indexes `order` as resting at `position` within the level of its price
+    /// on its side of the book
+    pub fn insert(&mut self, order: &Order, position: u64) {
+        self.locations.insert(
+            order.id,
+            Location {
+                side: order.side,
+                price: order.price,
+                position,
+            },
+        );
+        self.traders
+            .entry(order.trader)
+            .or_default()
+            .insert(order.id);
+    }
+
+    /// Removes `order` from the index
+    pub fn remove(&mut self, order: &Order) {
+        self.locations.remove(&order.id);
+
+        if let Some(ids) = self.traders.get_mut(&order.trader) {
+            ids.remove(&order.id);
+
+            if ids.is_empty() {
+                self.traders.remove(&order.trader);
+            }
+        }
+    }
+
+    /// Removes every order from the index
+    pub fn clear(&mut self) {
+        self.locations.clear();
+        self.traders.clear();
+    }
+}
+
+/* two books with the same price levels always have the same index, so
+ * comparing the price levels compares the indices too */
+impl PartialEq for OrderIndex {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for OrderIndex {}
@@ -0,0 +1,290 @@
+//! Contains logic for the OME's write-ahead journal
+//!
+//! Every mutation of engine state is appended to the journal (and flushed to
+//! disk) *before* it is applied, so that the state can be reconstructed after
+//! a crash by loading the last state dump and replaying the journal tail.
+use std::fmt::Display;
+use std::fs::{read_to_string, rename, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use web3::types::{Address, H256};
+
+use crate::book::Match;
+use crate::order::{Order, OrderId, OrderSide, SelfTradePrevention};
+use crate::util::nullable_de;
+
+/// Represents a single mutation of engine state
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum JournalEvent {
+    AddBook {
+        market: Address,
+    },
+    RemoveBook {
+        market: Address,
+    },
+    ConfigureBook {
+        /* fields left out (rather than set to null) are left unchanged */
+        market: Address,
+        #[serde(
+            default,
+            deserialize_with = "nullable_de",
+            skip_serializing_if = "Option::is_none"
+        )]
+        self_trade_prevention: Option<Option<SelfTradePrevention>>,
+        #[serde(
+            default,
+            deserialize_with = "nullable_de",
+            skip_serializing_if = "Option::is_none"
+        )]
+        domain_separator: Option<Option<H256>>,
+    },
+    Submit {
+        market: Address,
+        order: Order,
+    },
+    Cancel {
+        market: Address,
+        order_id: OrderId,
+    },
+    Amend {
+        market: Address,
+        order_id: OrderId,
+        order: Order,
+    },
+    MassCancel {
+        market: Option<Address>, /* every market if unset */
+        trader: Option<Address>, /* every trader if unset */
+        side: Option<OrderSide>, /* both sides if unset */
+    },
+    CancelStop {
+        market: Address,
+        order_id: OrderId,
+    },
+    Rollback {
+        market: Address,
+        matched: Box<Match>, /* as refused by the executioner */
+        offender: Option<OrderId>, /* the order it blamed, if any */
+    },
+}
+
+impl JournalEvent {
+    /// Returns the market the event concerns, if it concerns only one
+    pub fn market(&self) -> Option<Address> {
+        match self {
+            JournalEvent::AddBook { market }
+            | JournalEvent::RemoveBook { market }
+            | JournalEvent::ConfigureBook { market, .. }
+            | JournalEvent::Submit { market, .. }
+            | JournalEvent::Cancel { market, .. }
+            | JournalEvent::Amend { market, .. }
+            | JournalEvent::CancelStop { market, .. }
+            | JournalEvent::Rollback { market, .. } => Some(*market),
+            JournalEvent::MassCancel { market, .. } => *market,
+        }
+    }
+}
+
+/// Represents a single (sequenced) line of the journal
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub event: JournalEvent,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum JournalError {
+    IoError,
+    SerialisationError,
+    CorruptEntry,
+}
+
+impl From<std::io::Error> for JournalError {
+    fn from(_error: std::io::Error) -> Self {
+        JournalError::IoError
+    }
+}
+
+impl From<serde_json::Error> for JournalError {
+    fn from(_error: serde_json::Error) -> Self {
+        JournalError::SerialisationError
+    }
+}
+
+/// Append-only, newline-delimited JSON log of `JournalEntry`s
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    sequence: u64, /* sequence number of the last entry written */
+    length: u64,   /* length of the file up to the end of that entry */
+    torn: bool,    /* whether a failed write is yet to be truncated away */
+}
+
+impl Journal {
+    /// Opens (or creates) the journal at `path` for appending
+    ///
+    /// The next sequence number handed out will be one greater than either
+    /// the last entry already in the journal or `sequence`, whichever is
+    /// larger.
+    pub fn open(path: &Path, sequence: u64) -> Result<Self, JournalError> {
+        let (entries, length): (Vec<JournalEntry>, u64) = Journal::read(path)?;
+        let last: u64 = entries
+            .last()
+            .map(|entry| entry.sequence)
+            .unwrap_or_default();
+
+        let file: File =
+            OpenOptions::new().create(true).append(true).open(path)?;
+
+        /* drop any torn entry so that new entries start on a fresh line */
+        file.set_len(length)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            sequence: last.max(sequence),
+            length,
+            torn: false,
+        })
+    }
+
+    /// Reads every entry currently stored in the journal at `path`
+    ///
+    /// A missing journal is treated as an empty one. A torn final line (i.e.,
+    /// a write interrupted by a crash, which was therefore never acknowledged)
+    /// is ignored; corruption anywhere else is an error.
+    pub fn entries(path: &Path) -> Result<Vec<JournalEntry>, JournalError> {
+        Ok(Journal::read(path)?.0)
+    }
+
+    /// Reads the journal at `path`, returning its entries alongside the
+    /// length (in bytes) of the intact prefix of the file
+    fn read(path: &Path) -> Result<(Vec<JournalEntry>, u64), JournalError> {
+        if !path.exists() {
+            return Ok((vec![], 0));
+        }
+
+        let data: String = read_to_string(path)?;
+        let mut entries: Vec<JournalEntry> = vec![];
+        let mut length: usize = 0;
+
+        for line in data.split_inclusive('\n') {
+            let torn: bool = !line.ends_with('\n');
+
+            match serde_json::from_str::<JournalEntry>(line.trim_end()) {
+                Ok(t) if !torn => entries.push(t),
+                _ if torn || length + line.len() == data.len() => {
+                    warn!("Ignoring torn journal entry: {}", line);
+                    break;
+                }
+                _ => return Err(JournalError::CorruptEntry),
+            }
+
+            length += line.len();
+        }
+
+        Ok((entries, length as u64))
+    }
+
+    /// Returns the path of the underlying journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the sequence number of the last entry written
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Appends `event` to the journal
+    ///
+    /// The entry is synced to disk before this function returns. Returns the
+    /// sequence number assigned to the entry.
+    pub fn append(&mut self, event: JournalEvent) -> Result<u64, JournalError> {
+        self.append_at(event, Utc::now())
+    }
+
+    /// Appends `event` to the journal, timestamped as having occurred at
+    /// `timestamp`
+    ///
+    /// Should the entry fail to be written (or synced), it is truncated away
+    /// again, as the command it records fails too and so must never be
+    /// replayed.
+    pub fn append_at(
+        &mut self,
+        event: JournalEvent,
+        timestamp: DateTime<Utc>,
+    ) -> Result<u64, JournalError> {
+        /* finish undoing any failed write before writing anew */
+        if self.torn {
+            self.truncate()?;
+        }
+
+        let entry: JournalEntry = JournalEntry {
+            sequence: self.sequence + 1,
+            timestamp,
+            event,
+        };
+
+        let mut line: Vec<u8> = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        if let Err(e) = self
+            .file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data())
+        {
+            self.torn = true;
+            if let Err(t) = self.truncate() {
+                error!("Failed to truncate torn journal entry: {}", t);
+            }
+            return Err(e.into());
+        }
+
+        self.sequence = entry.sequence;
+        self.length += line.len() as u64;
+        Ok(self.sequence)
+    }
+
+    /// Truncates the journal back to the end of the last entry written
+    fn truncate(&mut self) -> Result<(), JournalError> {
+        self.file.set_len(self.length)?;
+        self.file.sync_data()?;
+        self.torn = false;
+        Ok(())
+    }
+
+    /// Discards every entry at or below `sequence`
+    ///
+    /// Called once a state dump reflecting those entries has been durably
+    /// written, so that the journal only ever holds the tail that needs to be
+    /// replayed on top of the latest dump.
+    pub fn compact(&mut self, sequence: u64) -> Result<(), JournalError> {
+        let entries: Vec<JournalEntry> = Journal::entries(&self.path)?;
+        let mut data: Vec<u8> = vec![];
+
+        for entry in entries.iter().filter(|entry| entry.sequence > sequence) {
+            data.append(&mut serde_json::to_vec(entry)?);
+            data.push(b'\n');
+        }
+
+        /* write the retained tail aside and atomically swap it in */
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut tmp_file: File = File::create(&tmp_path)?;
+        tmp_file.write_all(&data)?;
+        tmp_file.sync_all()?;
+        rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.length = data.len() as u64;
+        self.torn = false;
+        info!("Compacted journal up to entry {}", sequence);
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Contains the price levels of an order book
+//!
+//! Each order keeps the same position within its level for as long as it
+//! rests, so that the book can find (or remove) any order in constant time by
+//! looking its position up in the index (see `OrderIndex`). An order leaving
+//! from the middle of a level leaves its slot vacant rather than shifting the
+//! orders behind it, and vacant slots are only dropped once they reach the
+//! front of the level. Positions thus only ever increase, so no position is
+//! ever reused within a level.
+//!
+//! Positions are never serialised: a level is stored as the list of its
+//! orders, in order of priority, and positions are assigned afresh when a
+//! level is loaded.
+use std::collections::{vec_deque, VecDeque};
+use std::iter::{Flatten, FromIterator};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::order::Order;
+
+/// Represents the orders resting at a single price, in order of priority
+#[derive(Clone, Debug, Default)]
+pub struct Level {
+    slots: VecDeque<Option<Order>>, /* vacant where an order has left */
+    first: u64,                     /* position of the front slot */
+    len: usize,                     /* number of orders resting */
+}
+
+impl Level {
+    /// Constructor for the `Level` type
+    pub fn new() -> Self {
+        Self {
+            slots: VecDeque::new(),
+            first: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of orders resting at this level
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether no orders rest at this level
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the position of the front slot of the level
+    pub fn start(&self) -> u64 {
+        self.first
+    }
+
+    /// Returns the position one past the back slot of the level
+    pub fn end(&self) -> u64 {
+        self.first + self.slots.len() as u64
+    }
+
+    /// Returns the index into `slots` of `position`, if within the level
+    fn slot(&self, position: u64) -> Option<usize> {
+        position
+            .checked_sub(self.first)
+            .map(|t| t as usize)
+            .filter(|t| *t < self.slots.len())
+    }
+
+    /// Returns the order at `position`, if any
+    pub fn get(&self, position: u64) -> Option<&Order> {
+        self.slots.get(self.slot(position)?)?.as_ref()
+    }
+
+    /// Returns the order at `position` mutably, if any
+    pub fn get_mut(&mut self, position: u64) -> Option<&mut Order> {
+        let slot: usize = self.slot(position)?;
+        self.slots.get_mut(slot)?.as_mut()
+    }
+
+    /// Returns the order with the highest priority, if any
+    pub fn front(&self) -> Option<&Order> {
+        self.iter().next()
+    }
+
+    /// Adds `order` to the back of the level, returning its position
+    pub fn push_back(&mut self, order: Order) -> u64 {
+        let position: u64 = self.end();
+        self.slots.push_back(Some(order));
+        self.len += 1;
+        position
+    }
+
+    /// Removes the order at `position`, returning it
+    pub fn remove(&mut self, position: u64) -> Option<Order> {
+        let slot: usize = self.slot(position)?;
+        let order: Option<Order> = self.slots.get_mut(slot)?.take();
+
+        if order.is_some() {
+            self.len -= 1;
+            self.trim();
+        }
+
+        order
+    }
+
+    /// Removes every order for which `f` returns true, returning each along
+    /// with the position it held, in order of priority
+    pub fn remove_where<F>(&mut self, mut f: F) -> Vec<(u64, Order)>
+    where
+        F: FnMut(&Order) -> bool,
+    {
+        let mut removed: Vec<(u64, Order)> = vec![];
+
+        for (position, slot) in (self.first..).zip(self.slots.iter_mut()) {
+            if matches!(slot, Some(order) if f(order)) {
+                removed.extend(slot.take().map(|order| (position, order)));
+            }
+        }
+
+        self.len -= removed.len();
+        self.trim();
+        removed
+    }
+
+    /// Drops the vacant slots at the front of the level
+    fn trim(&mut self) {
+        while let Some(None) = self.slots.front() {
+            self.slots.pop_front();
+            self.first += 1;
+        }
+    }
+
+    /// Returns an iterator over the orders of the level, in order of priority
+    pub fn iter(&self) -> Flatten<vec_deque::Iter<'_, Option<Order>>> {
+        self.slots.iter().flatten()
+    }
+
+    /// Returns a mutable iterator over the orders of the level, in order of
+    /// priority
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Order> {
+        self.slots.iter_mut().flatten()
+    }
+
+    /// Returns an iterator over the orders of the level along with their
+    /// positions, in order of priority
+    pub fn positions(&self) -> impl Iterator<Item = (u64, &Order)> {
+        (self.first..)
+            .zip(self.slots.iter())
+            .filter_map(|(position, slot)| slot.as_ref().map(|t| (position, t)))
+    }
+}
+
+impl<'a> IntoIterator for &'a Level {
+    type Item = &'a Order;
+    type IntoIter = Flatten<vec_deque::Iter<'a, Option<Order>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Extend<Order> for Level {
+    fn extend<I: IntoIterator<Item = Order>>(&mut self, orders: I) {
+        for order in orders {
+            self.push_back(order);
+        }
+    }
+}
+
+impl FromIterator<Order> for Level {
+    fn from_iter<I: IntoIterator<Item = Order>>(orders: I) -> Self {
+        let mut level: Level = Level::new();
+        level.extend(orders);
+        level
+    }
+}
+
+/* vacant slots and positions are incidental to how the level came to be, so
+ * only the orders themselves are compared */
+impl PartialEq for Level {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Level {}
+
+impl Serialize for Level {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let orders: Vec<Order> = Deserialize::deserialize(deserializer)?;
+        Ok(orders.into_iter().collect())
+    }
+}
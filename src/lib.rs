@@ -9,8 +9,25 @@ extern crate enum_display_derive;
 extern crate log;
 extern crate pretty_env_logger;
 
+pub mod auth;
 pub mod book;
+pub mod depth;
+pub mod event;
+pub mod executioner;
+pub mod expiry;
+pub mod feed;
+pub mod heartbeat;
+pub mod index;
+pub mod journal;
+pub mod level;
+pub mod market;
 pub mod order;
+pub mod outbox;
+pub mod registry;
 pub mod rpc;
+pub mod signature;
+pub mod snapshot;
 pub mod state;
+pub mod tape;
+pub mod trigger;
 pub mod util;
@@ -3,10 +3,12 @@
 #![feature(result_contains_err)]
 #![feature(destructuring_assignment)]
 use std::convert::TryInto;
+use std::process;
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use clap::{App, Arg};
-use tokio::sync::Mutex;
+use tokio::sync::{oneshot, Mutex};
 use warp::Filter;
 use web3::types::Address;
 
@@ -18,19 +20,43 @@ extern crate log;
 extern crate pretty_env_logger;
 
 pub mod args;
+pub mod auth;
 pub mod book;
+pub mod depth;
+pub mod event;
+pub mod executioner;
+pub mod expiry;
+pub mod feed;
 pub mod handler;
+pub mod heartbeat;
+pub mod index;
+pub mod journal;
+pub mod level;
+pub mod market;
 pub mod order;
+pub mod outbox;
+pub mod registry;
 pub mod rpc;
+pub mod signature;
+pub mod snapshot;
 pub mod state;
+pub mod tape;
 pub mod tests;
+pub mod trigger;
 pub mod util;
 
 #[cfg(test)]
 pub mod book_tests;
 
 use crate::args::Arguments;
+use crate::book::{Match, MatchId};
+use crate::event::EventSender;
+use crate::executioner::HttpExecutioner;
+use crate::heartbeat::Heartbeats;
+use crate::journal::{Journal, JournalEntry, JournalEvent};
+use crate::market::Markets;
 use crate::order::OrderId;
+use crate::outbox::Outbox;
 use crate::state::OmeState;
 
 #[tokio::main]
@@ -63,6 +89,41 @@ async fn main() {
                 .help("The path to the dump file to use for state resumes")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("snapshot_interval")
+                .long("snapshot_interval")
+                .value_name("snapshot_interval")
+                .help("The number of seconds between state dumps")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot_generations")
+                .long("snapshot_generations")
+                .value_name("snapshot_generations")
+                .help("The number of previous state dumps to keep")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("journal")
+                .long("journal")
+                .value_name("journal")
+                .help("The path to the write-ahead journal file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("outbox")
+                .long("outbox")
+                .value_name("outbox")
+                .help("The path to the outbox of matches for the executioner")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("expiry_interval")
+                .long("expiry_interval")
+                .value_name("expiry_interval")
+                .help("The number of seconds between sweeps for expired orders")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("executioner_address")
                 .long("executioner_address")
@@ -84,6 +145,13 @@ async fn main() {
                 .help("File path to the TLS private key file")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("operator")
+                .long("operator")
+                .value_name("operator")
+                .help("Address of the operator, who sets domain separators")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("force-no-tls")
                 .long("force-no-tls")
@@ -99,86 +167,280 @@ async fn main() {
         }
     };
 
-    let internal_state = if util::is_existing_state(&arguments.dumpfile_path) {
-        match OmeState::from_dumpfile(&arguments.dumpfile_path) {
-            Some(s) => s,
-            None => OmeState::new(),
+    let mut internal_state: OmeState =
+        match snapshot::read_latest(&arguments.dumpfile_path) {
+            Ok(s) => s.unwrap_or_default(),
+            Err(e) => {
+                eprintln!(
+                    "Failed to load state dump {}: {}",
+                    arguments.dumpfile_path.display(),
+                    e
+                );
+                process::exit(1);
+            }
+        };
+
+    let mut outbox: Outbox = match Outbox::open(&arguments.outbox_path) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to open outbox: {}", e);
+            process::exit(1);
         }
-    } else {
-        Default::default()
     };
 
-    /* initialise engine state */
-    let state: Arc<Mutex<OmeState>> = Arc::new(Mutex::new(internal_state));
+    let entries: Vec<JournalEntry> =
+        match Journal::entries(&arguments.journal_path) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Failed to read journal: {}", e);
+                process::exit(1);
+            }
+        };
+
+    /* the journal must hold every entry since the dump was taken */
+    if let Some(first) = entries.first() {
+        if first.sequence > internal_state.sequence() + 1 {
+            eprintln!(
+                "Journal starts at entry {}, after the state dump at entry {}",
+                first.sequence,
+                internal_state.sequence()
+            );
+            process::exit(1);
+        }
+    }
+
+    /* rollbacks journalled just before a crash must not be made twice */
+    for entry in entries.iter() {
+        if let JournalEvent::Rollback { matched, .. } = &entry.event {
+            let id: MatchId = matched.id();
+
+            if outbox.delivery(id).is_some() {
+                if let Err(e) = outbox.reconcile(id) {
+                    eprintln!("Failed to record rollback: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    /* every entry before the first was compacted away */
+    let compacted: u64 = match entries.first() {
+        Some(first) => first.sequence - 1,
+        None => internal_state.sequence(),
+    };
+
+    /* recover any mutations made since the dump was taken */
+    let replayed: Vec<Match> = internal_state.replay(entries).await;
+
+    /* queue any matches lost between journalling and queueing them */
+    let now: DateTime<Utc> = Utc::now();
+    let latest: u64 = internal_state.sequence();
+    for matched in replayed {
+        if let Err(e) = outbox.enqueue(matched, latest, now) {
+            eprintln!("Failed to queue replayed match: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if let Err(e) = outbox.recover(compacted, latest) {
+        eprintln!("Failed to recover outbox: {}", e);
+        process::exit(1);
+    }
+
+    let journal: Journal =
+        match Journal::open(&arguments.journal_path, internal_state.sequence())
+        {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Failed to open journal: {}", e);
+                process::exit(1);
+            }
+        };
+
+    /* initialise engine state, with each book matching on its own task */
+    let journal: Arc<Mutex<Journal>> = Arc::new(Mutex::new(journal));
+    let outbox: Arc<Mutex<Outbox>> = Arc::new(Mutex::new(outbox));
+    let events: EventSender = event::channel();
+    let markets: Markets = Markets::new(
+        internal_state,
+        journal.clone(),
+        outbox,
+        events,
+        Arc::new(HttpExecutioner::new(arguments.executioner_address.clone())),
+    );
+    let heartbeats: Arc<Mutex<Heartbeats>> =
+        Arc::new(Mutex::new(Heartbeats::new()));
 
     /* Clone global engine state for each handler. This is only done because of
      * the nature of move semantics for Rust closures.
      *
-     * This is quite hacky and likely quite expensive.
+     * Cloning `Markets` only clones handles to the tasks owning each book.
      */
-    let index_book_state: Arc<Mutex<OmeState>> = state.clone();
-    let create_book_state: Arc<Mutex<OmeState>> = state.clone();
-    let read_book_state: Arc<Mutex<OmeState>> = state.clone();
+    let index_book_markets: Markets = markets.clone();
+    let create_book_markets: Markets = markets.clone();
+    let read_book_markets: Markets = markets.clone();
+    let update_book_markets: Markets = markets.clone();
+    let update_book_operator: Option<Address> = arguments.operator;
+
+    let create_order_markets: Markets = markets.clone();
+    let read_order_markets: Markets = markets.clone();
+    let update_order_markets: Markets = markets.clone();
+    let destroy_order_markets: Markets = markets.clone();
+    let destroy_market_orders_markets: Markets = markets.clone();
+    let destroy_orders_markets: Markets = markets.clone();
 
-    let create_order_state: Arc<Mutex<OmeState>> = state.clone();
-    let read_order_state: Arc<Mutex<OmeState>> = state.clone();
-    let destroy_order_state: Arc<Mutex<OmeState>> = state.clone();
+    let index_stop_markets: Markets = markets.clone();
+    let read_stop_markets: Markets = markets.clone();
+    let destroy_stop_markets: Markets = markets.clone();
 
-    let market_user_orders_state: Arc<Mutex<OmeState>> = state.clone();
+    let index_match_markets: Markets = markets.clone();
+    let read_match_markets: Markets = markets.clone();
+
+    let depth_markets: Markets = markets.clone();
+    let trades_markets: Markets = markets.clone();
+    let feed_markets: Markets = markets.clone();
+
+    let market_user_orders_markets: Markets = markets.clone();
+    let heartbeat_heartbeats: Arc<Mutex<Heartbeats>> = heartbeats.clone();
 
     /* define CRUD routes for order books */
     let book_prefix = warp::path!("book");
     let index_book_route = book_prefix
         .and(warp::get())
-        .and(warp::any().map(move || index_book_state.clone()))
+        .and(warp::any().map(move || index_book_markets.clone()))
         .and_then(handler::index_book_handler);
     let create_book_route = book_prefix
         .and(warp::post())
         .and(warp::body::json())
-        .and(warp::any().map(move || create_book_state.clone()))
+        .and(warp::any().map(move || create_book_markets.clone()))
         .and_then(handler::create_book_handler);
     let read_book_route = warp::path!("book" / Address)
         .and(warp::get())
-        .and(warp::any().map(move || read_book_state.clone()))
+        .and(warp::any().map(move || read_book_markets.clone()))
         .and_then(handler::read_book_handler);
+    let update_book_route = warp::path!("book" / Address)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(warp::any().map(move || update_book_operator))
+        .and(warp::any().map(move || update_book_markets.clone()))
+        .and_then(handler::update_book_handler);
 
     /* define CRUD routes for orders */
-    let tmp_args: Arguments = arguments.clone();
     let create_order_route = warp::path!("book" / Address / "order")
         .and(warp::post())
         .and(warp::body::json())
-        .and(warp::any().map(move || create_order_state.clone()))
-        .and(warp::any().map(move || tmp_args.executioner_address.clone()))
+        .and(warp::any().map(move || create_order_markets.clone()))
         .and_then(handler::create_order_handler);
     let read_order_route = warp::path!("book" / Address / "order" / OrderId)
         .and(warp::get())
-        .and(warp::any().map(move || read_order_state.clone()))
+        .and(warp::query())
+        .and(warp::any().map(move || read_order_markets.clone()))
         .and_then(handler::read_order_handler);
+    let update_order_route = warp::path!("book" / Address / "order" / OrderId)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(warp::any().map(move || update_order_markets.clone()))
+        .and_then(handler::update_order_handler);
     let destroy_order_route = warp::path!("book" / Address / "order" / OrderId)
         .and(warp::delete())
-        .and(warp::any().map(move || destroy_order_state.clone()))
+        .and(warp::any().map(move || destroy_order_markets.clone()))
         .and_then(handler::destroy_order_handler);
+    let destroy_market_orders_route = warp::path!("book" / Address / "order")
+        .and(warp::delete())
+        .and(warp::query())
+        .and(warp::any().map(move || destroy_market_orders_markets.clone()))
+        .and_then(handler::destroy_market_orders_handler);
+    let destroy_orders_route = warp::path!("order")
+        .and(warp::delete())
+        .and(warp::query())
+        .and(warp::any().map(move || destroy_orders_markets.clone()))
+        .and_then(handler::destroy_orders_handler);
+
+    /* define routes for stop orders awaiting activation */
+    let index_stop_route = warp::path!("book" / Address / "stop")
+        .and(warp::get())
+        .and(warp::any().map(move || index_stop_markets.clone()))
+        .and_then(handler::index_stop_handler);
+    let read_stop_route = warp::path!("book" / Address / "stop" / OrderId)
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::any().map(move || read_stop_markets.clone()))
+        .and_then(handler::read_stop_handler);
+    let destroy_stop_route = warp::path!("book" / Address / "stop" / OrderId)
+        .and(warp::delete())
+        .and(warp::any().map(move || destroy_stop_markets.clone()))
+        .and_then(handler::destroy_stop_handler);
+
+    /* define routes for the delivery of matches to the executioner */
+    let index_match_route = warp::path!("book" / Address / "match")
+        .and(warp::get())
+        .and(warp::any().map(move || index_match_markets.clone()))
+        .and_then(handler::index_match_handler);
+    let read_match_route = warp::path!("match" / MatchId)
+        .and(warp::get())
+        .and(warp::any().map(move || read_match_markets.clone()))
+        .and_then(handler::read_match_handler);
+
+    /* define routes for market data */
+    let depth_route = warp::path!("book" / Address / "depth")
+        .and(warp::ws())
+        .and(warp::any().map(move || depth_markets.clone()))
+        .and_then(handler::depth_handler);
+    let trades_route = warp::path!("book" / Address / "trades" / "stream")
+        .and(warp::get())
+        .and(warp::sse::last_event_id::<String>())
+        .and(warp::any().map(move || trades_markets.clone()))
+        .and_then(handler::trades_handler);
+
+    /* define routes for private order events */
+    let feed_route = warp::path!("trader" / Address / "events")
+        .and(warp::query())
+        .and(warp::ws())
+        .and(warp::any().map(move || feed_markets.clone()))
+        .and_then(handler::feed_handler);
 
     let market_user_orders_route = warp::path!("book" / Address / Address)
         .and(warp::get())
-        .and(warp::any().map(move || market_user_orders_state.clone()))
+        .and(warp::query())
+        .and(warp::any().map(move || market_user_orders_markets.clone()))
         .and_then(handler::market_user_orders_handler);
 
+    let heartbeat_route = warp::path!("heartbeat")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || heartbeat_heartbeats.clone()))
+        .and_then(handler::heartbeat_handler);
+
     // Healthcheck
     let health_route = warp::path::end()
         .and(warp::get())
         .and_then(handler::health_check_handler);
 
     /* aggregate all of our order book routes */
-    let book_routes =
-        index_book_route.or(create_book_route).or(read_book_route);
+    let book_routes = index_book_route
+        .or(create_book_route)
+        .or(read_book_route)
+        .or(update_book_route);
 
     /* aggregate all of our order routes */
     let order_routes = create_order_route
         .or(read_order_route)
-        .or(destroy_order_route);
+        .or(update_order_route)
+        .or(destroy_order_route)
+        .or(destroy_market_orders_route)
+        .or(destroy_orders_route);
+
+    /* aggregate all of our stop order routes */
+    let stop_routes =
+        index_stop_route.or(read_stop_route).or(destroy_stop_route);
+
+    /* aggregate all of our match routes */
+    let match_routes = index_match_route.or(read_match_route);
 
-    let misc_routes = market_user_orders_route;
+    /* aggregate all of our market data routes */
+    let market_data_routes = depth_route.or(trades_route);
+
+    let misc_routes = market_user_orders_route.or(heartbeat_route);
 
     let cors = warp::cors()
         .allow_any_origin()
@@ -194,20 +456,56 @@ async fn main() {
     let routes = health_route
         .or(book_routes)
         .or(order_routes)
+        .or(stop_routes)
+        .or(match_routes)
+        .or(market_data_routes)
+        .or(feed_route)
         .or(misc_routes)
         .with(cors);
 
-    /* start the web server */
+    /* forward matches to the executioner, undoing those it rejects */
+    tokio::spawn(outbox::run(markets.clone()));
+
+    /* periodically remove expired orders from every book */
+    tokio::spawn(expiry::run(markets.clone(), arguments.expiry_interval));
+
+    /* cancel the orders of traders whose heartbeats lapse */
+    tokio::spawn(heartbeat::run(heartbeats.clone(), markets.clone()));
+
+    /* periodically dump engine state, plus once more on shutdown */
+    let (snapshot_shutdown, snapshot_shutdown_rx) = oneshot::channel::<()>();
+    let snapshotter = tokio::spawn(snapshot::run(
+        markets.clone(),
+        journal.clone(),
+        arguments.dumpfile_path.clone(),
+        arguments.snapshot_interval,
+        arguments.snapshot_generations,
+        snapshot_shutdown_rx,
+    ));
+
+    /* start the web server, draining it upon SIGINT or SIGTERM */
     if arguments.force_no_tls {
-        warp::serve(routes)
-            .run((arguments.listen_address, arguments.listen_port))
-            .await;
+        let (_addr, server) = warp::serve(routes).bind_with_graceful_shutdown(
+            (arguments.listen_address, arguments.listen_port),
+            util::shutdown_signal(),
+        );
+        server.await;
     } else {
-        warp::serve(routes)
+        let (_addr, server) = warp::serve(routes)
             .tls()
             .cert_path(arguments.certificate_path)
             .key_path(arguments.private_key_path)
-            .run((arguments.listen_address, arguments.listen_port))
-            .await;
+            .bind_with_graceful_shutdown(
+                (arguments.listen_address, arguments.listen_port),
+                util::shutdown_signal(),
+            );
+        server.await;
+    }
+
+    info!("Shutting down, dumping state...");
+    if snapshot_shutdown.send(()).is_ok() {
+        if let Err(e) = snapshotter.await {
+            error!("Failed to dump state on shutdown: {}", e);
+        }
     }
 }
@@ -0,0 +1,806 @@
+//! Contains logic for running each order book on a task of its own
+//!
+//! Each book is owned by a single task, to which the rest of the OME sends
+//! commands over a channel. A task applies its commands one at a time, so no
+//! lock guards the book, and a book awaiting the executioner (or matching a
+//! large order) only delays commands sent to that same book.
+//!
+//! Tasks journal each event themselves immediately before applying it, so the
+//! entries of each market appear in the journal in the order in which they
+//! were applied to its book.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, MutexGuard, RwLock};
+use web3::types::Address;
+
+use crate::book::{Book, BookError, Execution, Match, MatchId, Reconciliation};
+use crate::depth::{Depth, DepthMessage};
+use crate::event::{self, EventSender, OrderEvent, OrderEventKind};
+use crate::executioner::Executioner;
+use crate::expiry;
+use crate::journal::{Journal, JournalError, JournalEvent};
+use crate::order::{Order, OrderId, OrderSide};
+use crate::outbox::Outbox;
+use crate::registry::Termination;
+use crate::signature::SignatureError;
+use crate::state::OmeState;
+use crate::tape::{Backlog, Tape, TradeId, TradeReport};
+
+/// The number of commands that may await each market before senders block
+pub const COMMAND_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
+pub enum MarketError {
+    JournalError,
+    BookError,
+    MarketExists,
+    MarketNotFound,
+    OrderNotFound,
+    StopNotFound,
+    InvalidAmendment,
+    SignatureReused,
+    InvalidSignature,
+    Unconfigured, /* no domain separator set, so no signature verifiable */
+    DuplicateOrder,
+    OrderFilled,
+    OrderCancelled,
+    Unavailable, /* the task of the market has stopped */
+}
+
+impl From<JournalError> for MarketError {
+    fn from(_error: JournalError) -> Self {
+        MarketError::JournalError
+    }
+}
+
+impl From<BookError> for MarketError {
+    fn from(_error: BookError) -> Self {
+        MarketError::BookError
+    }
+}
+
+impl From<SignatureError> for MarketError {
+    fn from(_error: SignatureError) -> Self {
+        MarketError::InvalidSignature
+    }
+}
+
+/// Represents the result of applying an event to a book
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Executed {
+        execution: Execution,
+        resting: Option<Box<Order>>, /* the order as it now rests, if it does */
+        matches: Vec<MatchId>,       /* as queued for the executioner */
+    },
+    Cancelled(Vec<Order>),
+    Configured,
+    RolledBack(Box<Reconciliation>),
+}
+
+type Reply<T> = oneshot::Sender<Result<T, MarketError>>;
+
+/// Represents a request made of the task owning a book
+enum Command {
+    Apply {
+        event: Box<JournalEvent>,
+        reply: Reply<Outcome>,
+    },
+    Expire {
+        now: DateTime<Utc>,
+        reply: Reply<Vec<Order>>,
+    },
+    Inspect(Box<dyn FnOnce(&Book) + Send>),
+    Capture {
+        reply: Reply<(Book, u64)>,
+    },
+    Depth {
+        reply: Reply<(DepthMessage, broadcast::Receiver<DepthMessage>)>,
+    },
+    Trades {
+        last: Option<TradeId>,
+        reply: Reply<(Backlog, broadcast::Receiver<TradeReport>)>,
+    },
+}
+
+/// Applies `event` to `book` as of `now`, appending any resulting matches to
+/// `matches`, and any orders expired beforehand to `expired`
+///
+/// Events that add or remove books concern the registry of markets rather
+/// than any one book, and so are ignored.
+///
+/// As both the live engine and replay apply events through here alone, an
+/// event is applied alike by both.
+pub async fn apply(
+    book: &mut Book,
+    event: JournalEvent,
+    matches: &mut Vec<Match>,
+    expired: &mut Vec<Order>,
+    now: DateTime<Utc>,
+) -> Result<Outcome, MarketError> {
+    let first: usize = matches.len();
+
+    /* clear out expired orders so they are never matched nor amended */
+    if let JournalEvent::Submit { .. } | JournalEvent::Amend { .. } = event {
+        expired.extend(book.expire(now));
+    }
+
+    match event {
+        JournalEvent::AddBook { .. } | JournalEvent::RemoveBook { .. } => {
+            Ok(Outcome::Configured)
+        }
+        JournalEvent::ConfigureBook {
+            self_trade_prevention,
+            domain_separator,
+            ..
+        } => {
+            /* only what the request named is changed */
+            if let Some(mode) = self_trade_prevention {
+                book.self_trade_prevention = mode;
+            }
+            if let Some(separator) = domain_separator {
+                book.domain_separator = separator;
+            }
+            Ok(Outcome::Configured)
+        }
+        JournalEvent::Submit { order, .. } => {
+            let id: OrderId = order.id;
+            let execution: Execution =
+                book.process(order, matches, now).await?;
+
+            Ok(Outcome::Executed {
+                execution,
+                resting: book.order(id).cloned().map(Box::new),
+                matches: matches[first..].iter().map(Match::id).collect(),
+            })
+        }
+        JournalEvent::Cancel { order_id, .. } => {
+            let order: Order = match book.order(order_id) {
+                Some(t) => t.clone(),
+                None => return Err(MarketError::OrderNotFound),
+            };
+            book.cancel(order_id)?;
+            Ok(Outcome::Cancelled(vec![order]))
+        }
+        JournalEvent::Amend {
+            order_id, order, ..
+        } => {
+            let id: OrderId = order.id;
+
+            match book.amend(order_id, order, matches, now).await {
+                Ok(Some(execution)) => Ok(Outcome::Executed {
+                    execution,
+                    resting: book.order(id).cloned().map(Box::new),
+                    matches: matches[first..].iter().map(Match::id).collect(),
+                }),
+                Ok(None) => Err(MarketError::OrderNotFound),
+                Err(BookError::InvalidAmendment) => {
+                    Err(MarketError::InvalidAmendment)
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+        JournalEvent::MassCancel { trader, side, .. } => {
+            Ok(Outcome::Cancelled(book.mass_cancel(trader, side)))
+        }
+        JournalEvent::CancelStop { order_id, .. } => {
+            match book.remove_stop(order_id) {
+                Some(order) => Ok(Outcome::Cancelled(vec![order])),
+                None => Err(MarketError::StopNotFound),
+            }
+        }
+        JournalEvent::Rollback {
+            matched, offender, ..
+        } => Ok(Outcome::RolledBack(Box::new(
+            book.rollback(&matched, offender),
+        ))),
+    }
+}
+
+/// Publishes a `Corrected` event for each order of the match undone by
+/// `reconciliation`, plus a `Cancelled` event for the order blamed for it
+fn report(
+    events: &EventSender,
+    reconciliation: &Reconciliation,
+    now: DateTime<Utc>,
+) {
+    let id: MatchId = reconciliation.matched.id();
+
+    for order in
+        [&reconciliation.matched.maker, &reconciliation.matched.taker].iter()
+    {
+        event::publish(
+            events,
+            OrderEvent::of_match(OrderEventKind::Corrected, order, id, now),
+        );
+    }
+
+    if let Some(order) = &reconciliation.cancelled {
+        event::publish(
+            events,
+            OrderEvent::of_match(OrderEventKind::Cancelled, order, id, now),
+        );
+    }
+}
+
+/// Publishes an event for each change to an order made by applying `event`
+/// to `book`, given the outcome of doing so and the matches it made
+///
+/// Expiries and rollbacks are reported separately (see `expiry::report` and
+/// `report`).
+fn announce(
+    events: &EventSender,
+    book: &Book,
+    event: &JournalEvent,
+    outcome: &Outcome,
+    matches: &[Match],
+    now: DateTime<Utc>,
+) {
+    let (order, execution): (&Order, &Execution) = match (event, outcome) {
+        (
+            JournalEvent::Submit { order, .. },
+            Outcome::Executed { execution, .. },
+        ) => (order, execution),
+        (
+            JournalEvent::Amend {
+                order_id, order, ..
+            },
+            Outcome::Executed { execution, .. },
+        ) => {
+            /* the amended order takes the place of the original */
+            event::publish(
+                events,
+                OrderEvent {
+                    order_id: *order_id,
+                    ..OrderEvent::new(OrderEventKind::Cancelled, order, now)
+                },
+            );
+            (order, execution)
+        }
+        (_, Outcome::Cancelled(orders)) => {
+            for order in orders {
+                event::publish(
+                    events,
+                    OrderEvent::new(OrderEventKind::Cancelled, order, now),
+                );
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    event::publish(
+        events,
+        OrderEvent::new(OrderEventKind::Accepted, order, now),
+    );
+
+    for matched in matches {
+        for side in [&matched.maker, &matched.taker].iter() {
+            event::publish(events, OrderEvent::filled(side, matched, now));
+        }
+    }
+
+    /* resting orders of the same trader cancelled to prevent self-trades */
+    for self_trade in execution.self_trades.iter() {
+        if book.termination(self_trade.resting_id)
+            == Some(Termination::Cancelled)
+        {
+            event::publish(
+                events,
+                OrderEvent {
+                    order_id: self_trade.resting_id,
+                    ..OrderEvent::new(OrderEventKind::Cancelled, order, now)
+                },
+            );
+        }
+    }
+
+    /* e.g., immediate-or-cancel remainders and rejected post-only orders */
+    if book.termination(order.id) == Some(Termination::Cancelled) {
+        event::publish(
+            events,
+            OrderEvent::new(OrderEventKind::Cancelled, order, now),
+        );
+    }
+}
+
+/// Checks that `event` may be applied to `book`, so that events that cannot
+/// be are never journalled
+fn validate(book: &Book, event: &JournalEvent) -> Result<(), MarketError> {
+    match event {
+        /* replaying a signed order must never fill its trader twice */
+        JournalEvent::Submit { order, .. } if book.is_duplicate(order.id) => {
+            Err(duplicate(book, order.id))
+        }
+        JournalEvent::Cancel { order_id, .. }
+            if book.order(*order_id).is_none() =>
+        {
+            Err(MarketError::OrderNotFound)
+        }
+        JournalEvent::CancelStop { order_id, .. }
+            if book.stop(*order_id).is_none() =>
+        {
+            Err(MarketError::StopNotFound)
+        }
+        JournalEvent::Amend {
+            order_id, order, ..
+        } => match book.order(*order_id) {
+            None => Err(MarketError::OrderNotFound),
+            /* the original signature cannot authorise the amended order */
+            Some(original) if original.signed_data == order.signed_data => {
+                Err(MarketError::SignatureReused)
+            }
+            Some(original) if !Book::is_amendment(original, order) => {
+                Err(MarketError::InvalidAmendment)
+            }
+            Some(_) if book.is_duplicate(order.id) => {
+                Err(duplicate(book, order.id))
+            }
+            Some(_) => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Returns the error explaining why the order with ID `id` may not be
+/// submitted to `book` again
+fn duplicate(book: &Book, id: OrderId) -> MarketError {
+    match book.termination(id) {
+        Some(Termination::Filled) => MarketError::OrderFilled,
+        Some(Termination::Cancelled) => MarketError::OrderCancelled,
+        None => MarketError::DuplicateOrder,
+    }
+}
+
+/// Represents the task owning a single book
+struct Task {
+    book: Book,
+    sequence: u64, /* sequence number of the last journal entry applied */
+    journal: Arc<Mutex<Journal>>,
+    outbox: Arc<Mutex<Outbox>>,
+    events: EventSender,
+    depth: Depth, /* as last published to market data subscribers */
+    tape: Tape,
+}
+
+impl Task {
+    /// Applies each command received until every sender has been dropped
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>) {
+        while let Some(command) = commands.recv().await {
+            match command {
+                Command::Apply { event, reply } => {
+                    let outcome: Result<Outcome, MarketError> =
+                        self.apply(*event).await;
+                    self.depth.refresh(&self.book, Utc::now());
+                    let _ = reply.send(outcome);
+                }
+                Command::Expire { now, reply } => {
+                    let expired: Vec<Order> = self.book.expire(now);
+                    expiry::report(&self.events, &expired, now);
+                    self.depth.refresh(&self.book, now);
+                    let _ = reply.send(Ok(expired));
+                }
+                Command::Inspect(f) => f(&self.book),
+                Command::Capture { reply } => {
+                    let _ = reply.send(Ok((self.book.clone(), self.sequence)));
+                }
+                Command::Depth { reply } => {
+                    let _ = reply.send(Ok(self.depth.subscribe()));
+                }
+                Command::Trades { last, reply } => {
+                    let _ = reply.send(Ok(self.tape.subscribe(last)));
+                }
+            }
+        }
+
+        info!("Stopped task of book {}", self.book.market);
+    }
+
+    /// Journals `event`, then applies it to the book
+    async fn apply(
+        &mut self,
+        event: JournalEvent,
+    ) -> Result<Outcome, MarketError> {
+        validate(&self.book, &event)?;
+
+        /* persist the event before it touches the book */
+        let now: DateTime<Utc> = Utc::now();
+        self.sequence =
+            self.journal.lock().await.append_at(event.clone(), now)?;
+
+        let mut matches: Vec<Match> = vec![];
+        let mut expired: Vec<Order> = vec![];
+        let outcome: Result<Outcome, MarketError> = apply(
+            &mut self.book,
+            event.clone(),
+            &mut matches,
+            &mut expired,
+            now,
+        )
+        .await;
+        expiry::report(&self.events, &expired, now);
+
+        /* tell traders what became of their orders */
+        if let Ok(outcome) = &outcome {
+            announce(&self.events, &self.book, &event, outcome, &matches, now);
+        }
+        self.tape.record(self.sequence, &matches, now);
+
+        /* hand the matches over to the executioner (see `outbox`); should
+         * one fail to persist, snapshots wait for it, so replay restores it */
+        let mut outbox: MutexGuard<Outbox> = self.outbox.lock().await;
+        for matched in matches {
+            if let Err(e) = outbox.enqueue(matched, self.sequence, now) {
+                error!(
+                    "Failed to persist match in {}: {}",
+                    self.book.market, e
+                );
+            }
+        }
+
+        /* tell clients which fills no longer stand */
+        if let Ok(Outcome::RolledBack(reconciliation)) = &outcome {
+            report(&self.events, reconciliation, now);
+        }
+
+        outcome
+    }
+}
+
+/// Represents a handle to the task owning the book of a single market
+#[derive(Clone, Debug)]
+pub struct Market {
+    market: Address,
+    commands: mpsc::Sender<Command>,
+}
+
+impl Market {
+    /// Spawns a task owning `book`, which reflects every journal entry up to
+    /// and including `sequence`
+    fn spawn(
+        book: Book,
+        sequence: u64,
+        journal: Arc<Mutex<Journal>>,
+        outbox: Arc<Mutex<Outbox>>,
+        events: EventSender,
+    ) -> Self {
+        let (commands, receiver) = mpsc::channel(COMMAND_CAPACITY);
+        let market: Market = Self {
+            market: book.market,
+            commands,
+        };
+
+        tokio::spawn(
+            Task {
+                depth: Depth::new(&book),
+                tape: Tape::new(sequence),
+                book,
+                sequence,
+                journal,
+                outbox,
+                events,
+            }
+            .run(receiver),
+        );
+
+        market
+    }
+
+    /// Returns the address of the market
+    pub fn market(&self) -> Address {
+        self.market
+    }
+
+    async fn send<T>(
+        &self,
+        command: Command,
+        response: oneshot::Receiver<Result<T, MarketError>>,
+    ) -> Result<T, MarketError> {
+        if self.commands.send(command).await.is_err() {
+            return Err(MarketError::Unavailable);
+        }
+
+        match response.await {
+            Ok(t) => t,
+            Err(_e) => Err(MarketError::Unavailable),
+        }
+    }
+
+    /// Journals `event`, then applies it to the book
+    ///
+    /// Events that could not be applied (e.g., cancellations of orders not in
+    /// the book) are rejected without being journalled.
+    pub async fn apply(
+        &self,
+        event: JournalEvent,
+    ) -> Result<Outcome, MarketError> {
+        let (reply, response) = oneshot::channel();
+        self.send(
+            Command::Apply {
+                event: Box::new(event),
+                reply,
+            },
+            response,
+        )
+        .await
+    }
+
+    /// Removes every order that has expired as of `now` from the book,
+    /// publishing an `Expired` event for each
+    ///
+    /// Returns the orders removed.
+    pub async fn expire(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Order>, MarketError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Expire { now, reply }, response).await
+    }
+
+    /// Returns the result of calling `f` on the book, as of every command
+    /// previously sent to it
+    pub async fn inspect<F, T>(&self, f: F) -> Result<T, MarketError>
+    where
+        F: FnOnce(&Book) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (reply, response) = oneshot::channel();
+        let inspect: Box<dyn FnOnce(&Book) + Send> = Box::new(move |book| {
+            let _ = reply.send(Ok(f(book)));
+        });
+        self.send(Command::Inspect(inspect), response).await
+    }
+
+    /// Returns a snapshot of the depth of the book, along with a receiver of
+    /// every update to it published after the snapshot (see `depth`)
+    pub async fn depth(
+        &self,
+    ) -> Result<(DepthMessage, broadcast::Receiver<DepthMessage>), MarketError>
+    {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Depth { reply }, response).await
+    }
+
+    /// Returns the backlog of a subscriber resuming from the trade with ID
+    /// `last` (if given), along with a receiver of every trade made from now
+    /// on (see `tape`)
+    pub async fn trades(
+        &self,
+        last: Option<TradeId>,
+    ) -> Result<(Backlog, broadcast::Receiver<TradeReport>), MarketError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Trades { last, reply }, response).await
+    }
+
+    /// Returns a copy of the book, along with the sequence number of the last
+    /// journal entry applied to it
+    async fn capture(&self) -> Result<(Book, u64), MarketError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Capture { reply }, response).await
+    }
+}
+
+/// Represents the registry of every market of the OME
+#[derive(Clone, Debug)]
+pub struct Markets {
+    markets: Arc<RwLock<HashMap<Address, Market>>>,
+    journal: Arc<Mutex<Journal>>,
+    outbox: Arc<Mutex<Outbox>>,
+    events: EventSender,
+    executioner: Arc<dyn Executioner>,
+}
+
+impl Markets {
+    /// Spawns a task for each book of `state`
+    pub fn new(
+        state: OmeState,
+        journal: Arc<Mutex<Journal>>,
+        outbox: Arc<Mutex<Outbox>>,
+        events: EventSender,
+        executioner: Arc<dyn Executioner>,
+    ) -> Self {
+        let sequence: u64 = state.sequence();
+        let markets: HashMap<Address, Market> = state
+            .into_books()
+            .into_iter()
+            .map(|(address, book)| {
+                (
+                    address,
+                    Market::spawn(
+                        book,
+                        sequence,
+                        journal.clone(),
+                        outbox.clone(),
+                        events.clone(),
+                    ),
+                )
+            })
+            .collect();
+
+        Self {
+            markets: Arc::new(RwLock::new(markets)),
+            journal,
+            outbox,
+            events,
+            executioner,
+        }
+    }
+
+    /// Returns the executioner orders are checked against and matches are
+    /// forwarded to
+    pub fn executioner(&self) -> &dyn Executioner {
+        self.executioner.as_ref()
+    }
+
+    /// Returns the channel every market publishes its order events on
+    pub fn events(&self) -> &EventSender {
+        &self.events
+    }
+
+    /// Returns the outbox every market queues its matches in
+    pub fn outbox(&self) -> &Arc<Mutex<Outbox>> {
+        &self.outbox
+    }
+
+    /// Returns the address of every market
+    pub async fn addresses(&self) -> Vec<Address> {
+        self.markets.read().await.keys().cloned().collect()
+    }
+
+    /// Returns a handle to the market at `market`, if any
+    pub async fn get(&self, market: Address) -> Option<Market> {
+        self.markets.read().await.get(&market).cloned()
+    }
+
+    /// Journals the creation of a new, empty book for `market`, then spawns
+    /// its task
+    pub async fn add(&self, market: Address) -> Result<(), MarketError> {
+        let mut markets = self.markets.write().await;
+
+        if markets.contains_key(&market) {
+            return Err(MarketError::MarketExists);
+        }
+
+        let sequence: u64 = self
+            .journal
+            .lock()
+            .await
+            .append(JournalEvent::AddBook { market })?;
+
+        markets.insert(
+            market,
+            Market::spawn(
+                Book::new(market),
+                sequence,
+                self.journal.clone(),
+                self.outbox.clone(),
+                self.events.clone(),
+            ),
+        );
+        Ok(())
+    }
+
+    /// Journals the removal of the book of `market`, then stops its task once
+    /// every command already sent to it has been applied
+    pub async fn remove(&self, market: Address) -> Result<(), MarketError> {
+        let mut markets = self.markets.write().await;
+
+        if !markets.contains_key(&market) {
+            return Err(MarketError::MarketNotFound);
+        }
+
+        self.journal
+            .lock()
+            .await
+            .append(JournalEvent::RemoveBook { market })?;
+        markets.remove(&market);
+        Ok(())
+    }
+
+    /// Cancels every open order of `trader` on `side` in `market`, where
+    /// `None` matches either side or every market respectively
+    ///
+    /// Each market journals its own cancellation. Returns the orders
+    /// cancelled, or the first error encountered.
+    pub async fn mass_cancel(
+        &self,
+        market: Option<Address>,
+        trader: Address,
+        side: Option<OrderSide>,
+    ) -> Result<Vec<Order>, MarketError> {
+        let targets: Vec<Market> = match market {
+            Some(market) => match self.get(market).await {
+                Some(t) => vec![t],
+                None => return Err(MarketError::MarketNotFound),
+            },
+            None => self.markets.read().await.values().cloned().collect(),
+        };
+        let mut cancelled: Vec<Order> = vec![];
+
+        for target in targets {
+            let event: JournalEvent = JournalEvent::MassCancel {
+                market: Some(target.market()),
+                trader: Some(trader),
+                side,
+            };
+
+            if let Outcome::Cancelled(orders) = target.apply(event).await? {
+                cancelled.extend(orders);
+            }
+        }
+
+        Ok(cancelled)
+    }
+
+    /// Undoes `matched`, which the executioner has refused for good, blaming
+    /// `offender` (see `Book::rollback`)
+    ///
+    /// The rollback is journalled by the market that made the match.
+    pub async fn rollback(
+        &self,
+        matched: Match,
+        offender: Option<OrderId>,
+    ) -> Result<Reconciliation, MarketError> {
+        let market: Address = matched.taker.market;
+        let target: Market = match self.get(market).await {
+            Some(t) => t,
+            None => return Err(MarketError::MarketNotFound),
+        };
+
+        match target
+            .apply(JournalEvent::Rollback {
+                market,
+                matched: Box::new(matched),
+                offender,
+            })
+            .await?
+        {
+            Outcome::RolledBack(reconciliation) => Ok(*reconciliation),
+            _ => unreachable!(), /* rollbacks are always rolled back */
+        }
+    }
+
+    /// Removes every order that has expired as of `now` from every book,
+    /// publishing an `Expired` event for each
+    ///
+    /// Returns the orders removed.
+    pub async fn expire(&self, now: DateTime<Utc>) -> Vec<Order> {
+        let targets: Vec<Market> =
+            self.markets.read().await.values().cloned().collect();
+        let mut expired: Vec<Order> = vec![];
+
+        for target in targets {
+            match target.expire(now).await {
+                Ok(orders) => expired.extend(orders),
+                Err(e) => {
+                    warn!("Failed to expire orders of {}: {}", target.market, e)
+                }
+            }
+        }
+
+        expired
+    }
+
+    /// Returns a copy of the state of every book
+    ///
+    /// Books are copied one at a time, so some may reflect journal entries
+    /// more recent than `sequence()` of the state returned. Such books record
+    /// as much, so that replaying the journal over the state skips those
+    /// entries for them.
+    pub async fn snapshot(&self) -> Result<OmeState, MarketError> {
+        /* no book may be added or removed mid-copy */
+        let markets = self.markets.read().await;
+        let sequence: u64 = self.journal.lock().await.sequence();
+        let mut books: Vec<(Book, u64)> = vec![];
+
+        for market in markets.values() {
+            books.push(market.capture().await?);
+        }
+
+        Ok(OmeState::from_books(books, sequence))
+    }
+}
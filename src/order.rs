@@ -40,6 +40,86 @@ impl FromStr for OrderSide {
     }
 }
 
+/// Represents how an order interacts with the book
+///
+/// - `Limit` orders match at their price or better, with any remainder being
+///   stored in the book
+/// - `Market` orders sweep the opposite side of the book, with their price
+///   acting as the worst acceptable price, and any remainder being cancelled
+#[derive(
+    Clone, Copy, PartialEq, Eq, Default, Debug, Display, Serialize, Deserialize,
+)]
+pub enum OrderType {
+    #[default]
+    Limit,
+    Market,
+}
+
+/// Represents how long an order remains active once submitted
+///
+/// - `GoodTilCancelled` orders are stored in the book until filled or
+///   cancelled
+/// - `GoodTilDate` orders are stored in the book until filled, cancelled or
+///   their expiration has passed
+/// - `ImmediateOrCancel` orders are matched as far as possible, with any
+///   remainder being cancelled
+/// - `FillOrKill` orders are either matched in full immediately or cancelled
+///   without touching the book
+///
+/// Whatever its time in force, an order is never matched once its expiration
+/// has passed (see `Order::is_expired`), so that `GoodTilCancelled` orders
+/// are in fact also removed upon expiring.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Default, Debug, Display, Serialize, Deserialize,
+)]
+pub enum TimeInForce {
+    #[default]
+    #[serde(rename = "GTC")]
+    GoodTilCancelled,
+    #[serde(rename = "GTD")]
+    GoodTilDate,
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel,
+    #[serde(rename = "FOK")]
+    FillOrKill,
+}
+
+/// Represents what happens to a post-only order that would take liquidity
+///
+/// - `Reject` orders are rejected outright
+/// - `Reprice` orders are instead stored in the book one tick (i.e., the
+///   smallest possible price increment) behind the top of the opposite side
+///   of the book, retaining their original ID and signature
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, Serialize, Deserialize,
+)]
+pub enum PostOnly {
+    Reject,
+    Reprice,
+}
+
+/// Represents how an order that would match against a resting order of the
+/// same trader is handled
+///
+/// - `CancelNewest` cancels the remainder of the incoming order
+/// - `CancelOldest` cancels the resting order, then carries on matching
+/// - `CancelBoth` cancels both the resting order and the remainder of the
+///   incoming order
+/// - `DecrementAndCancel` reduces both orders by the smaller of their
+///   remaining quantities (cancelling the smaller outright), then carries on
+///   matching whatever is left of the incoming order
+///
+/// In no case is a trade made between the two orders.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, Serialize, Deserialize,
+)]
+pub enum SelfTradePrevention {
+    CancelNewest,
+    CancelOldest,
+    CancelBoth,
+    DecrementAndCancel,
+}
+
 /// Represents an actual order in the market
 ///
 /// Comprises a struct with all order fields needed for the Tracer market.
@@ -55,6 +135,22 @@ pub struct Order {
     pub expiration: DateTime<Utc>,
     pub created: DateTime<Utc>,
     pub signed_data: Vec<u8>,
+    #[serde(default)]
+    pub order_type: OrderType,
+    #[serde(default)]
+    pub max_slippage: Option<u32>, /* in basis points of top of book */
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    #[serde(default)]
+    pub post_only: Option<PostOnly>, /* never take liquidity if set */
+    #[serde(default)]
+    pub stop_price: Option<U256>, /* held in the trigger book until reached */
+    #[serde(default)]
+    pub display_quantity: Option<U256>, /* size of each visible slice */
+    #[serde(default)]
+    pub displayed: U256, /* remainder of the current visible slice */
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>, /* or market's */
 }
 
 impl fmt::Display for Order {
@@ -116,34 +212,72 @@ impl From<ParseIntError> for OrderParseError {
     }
 }
 
-pub fn order_id(
-    user: Address,
-    target_tracer: Address,
-    side: OrderSide,
-    price: U256,
-    amount: U256,
-    expiration: DateTime<Utc>,
-    created: DateTime<Utc>,
-) -> OrderId {
+/// Returns the ABI encoding of each field of `order` signed by its trader, in
+/// the order in which they are signed (see `signature::ORDER_TYPE`)
+///
+/// Enumerations are encoded as the index of their variant, counting from one
+/// for those that are optional so that zero stands for their absence. Absent
+/// stop prices and display quantities are likewise encoded as zero, whereas
+/// an absent maximum slippage is encoded as the largest 256-bit integer (i.e.,
+/// as unbounded).
+pub fn signed_fields(order: &Order) -> Vec<Token> {
     /* handle indirect conversions */
-    let side_num: U256 = U256::from(match side {
+    let side_num: U256 = U256::from(match order.side {
         OrderSide::Bid => 0u8,
         OrderSide::Ask => 1u8,
     });
-    let expiration_timestamp: U256 = U256::from(expiration.timestamp());
-    let created_timestamp: U256 = U256::from(created.timestamp());
-
-    let components: Vec<Token> = vec![
-        Token::Address(user),
-        Token::Address(target_tracer),
-        Token::Uint(price),
-        Token::Uint(amount),
+    let expiration_timestamp: U256 = U256::from(order.expiration.timestamp());
+    let created_timestamp: U256 = U256::from(order.created.timestamp());
+    let order_type_num: U256 = U256::from(match order.order_type {
+        OrderType::Limit => 0u8,
+        OrderType::Market => 1u8,
+    });
+    let max_slippage_num: U256 = match order.max_slippage {
+        Some(t) => U256::from(t),
+        None => U256::MAX,
+    };
+    let time_in_force_num: U256 = U256::from(match order.time_in_force {
+        TimeInForce::GoodTilCancelled => 0u8,
+        TimeInForce::GoodTilDate => 1u8,
+        TimeInForce::ImmediateOrCancel => 2u8,
+        TimeInForce::FillOrKill => 3u8,
+    });
+    let post_only_num: U256 = U256::from(match order.post_only {
+        None => 0u8,
+        Some(PostOnly::Reject) => 1u8,
+        Some(PostOnly::Reprice) => 2u8,
+    });
+    let self_trade_prevention_num: U256 =
+        U256::from(match order.self_trade_prevention {
+            None => 0u8,
+            Some(SelfTradePrevention::CancelNewest) => 1u8,
+            Some(SelfTradePrevention::CancelOldest) => 2u8,
+            Some(SelfTradePrevention::CancelBoth) => 3u8,
+            Some(SelfTradePrevention::DecrementAndCancel) => 4u8,
+        });
+
+    vec![
+        Token::Address(order.trader),
+        Token::Address(order.market),
+        Token::Uint(order.price),
+        Token::Uint(order.quantity),
         Token::Uint(side_num),
         Token::Uint(expiration_timestamp),
         Token::Uint(created_timestamp),
-    ];
+        Token::Uint(order_type_num),
+        Token::Uint(max_slippage_num),
+        Token::Uint(time_in_force_num),
+        Token::Uint(post_only_num),
+        Token::Uint(order.stop_price.unwrap_or_default()),
+        Token::Uint(order.display_quantity.unwrap_or_default()),
+        Token::Uint(self_trade_prevention_num),
+    ]
+}
 
-    web3::signing::keccak256(&ethabi::encode(&components)).into()
+/// Computes the ID of `order` from every field signed by its trader (see
+/// `signed_fields`)
+pub fn order_id(order: &Order) -> OrderId {
+    web3::signing::keccak256(&ethabi::encode(&signed_fields(order))).into()
 }
 
 impl Order {
@@ -162,12 +296,8 @@ impl Order {
         created: DateTime<Utc>,
         signed_data: Vec<u8>,
     ) -> Self {
-        let id: OrderId = order_id(
-            trader, market, side, price, quantity, expiration, created,
-        );
-
-        Self {
-            id,
+        let mut order: Order = Self {
+            id: OrderId::zero(),
             trader,
             market,
             side,
@@ -177,6 +307,74 @@ impl Order {
             expiration,
             created,
             signed_data,
+            order_type: OrderType::Limit,
+            max_slippage: None,
+            time_in_force: TimeInForce::GoodTilCancelled,
+            post_only: None,
+            stop_price: None,
+            display_quantity: None,
+            displayed: U256::zero(),
+            self_trade_prevention: None,
+        };
+        order.id = order_id(&order);
+
+        order
+    }
+
+    /// Returns whether any unmatched remainder of this order may be stored
+    /// in the book
+    pub fn rests(&self) -> bool {
+        self.order_type == OrderType::Limit
+            && matches!(
+                self.time_in_force,
+                TimeInForce::GoodTilCancelled | TimeInForce::GoodTilDate
+            )
+    }
+
+    /// Returns whether this order is no longer active as of `now`
+    ///
+    /// The expiration is signed along with the rest of the order, and so
+    /// binds every order, whatever its time in force.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiration <= now
+    }
+
+    /// Returns the quantity of this order visible to other traders
+    ///
+    /// For iceberg orders (i.e., those with a display quantity), this is only
+    /// the current slice of the remaining quantity. All other orders are
+    /// fully visible.
+    pub fn visible(&self) -> U256 {
+        match self.display_quantity {
+            Some(_) => self.displayed.min(self.remaining),
+            None => self.remaining,
+        }
+    }
+
+    /// Refills the visible slice of this order from its hidden remainder
+    pub fn replenish(&mut self) {
+        if let Some(display_quantity) = self.display_quantity {
+            self.displayed = display_quantity.min(self.remaining);
+        }
+    }
+
+    /// Returns this order as it may be shown to other traders, i.e., with any
+    /// hidden quantity omitted
+    pub fn public(&self) -> Order {
+        match self.display_quantity {
+            /* orders yet to rest (e.g., stop orders) have no slice on show
+             * yet, so show the first slice they would */
+            Some(display_quantity) if self.displayed.is_zero() => Order {
+                quantity: display_quantity,
+                remaining: display_quantity.min(self.remaining),
+                ..self.clone()
+            },
+            Some(display_quantity) => Order {
+                quantity: display_quantity,
+                remaining: self.visible(),
+                ..self.clone()
+            },
+            None => self.clone(),
         }
     }
 }
@@ -272,12 +470,8 @@ impl TryFrom<ExternalOrder> for Order {
             Err(e) => return Err(e.into()),
         };
 
-        let id: OrderId = order_id(
-            trader, market, side, price, quantity, expiration, created,
-        );
-
-        Ok(Self {
-            id,
+        let mut order: Order = Self {
+            id: OrderId::zero(),
             trader,
             market,
             side,
@@ -287,6 +481,17 @@ impl TryFrom<ExternalOrder> for Order {
             expiration,
             created,
             signed_data,
-        })
+            order_type: OrderType::Limit,
+            max_slippage: None,
+            time_in_force: TimeInForce::GoodTilCancelled,
+            post_only: None,
+            stop_price: None,
+            display_quantity: None,
+            displayed: U256::zero(),
+            self_trade_prevention: None,
+        };
+        order.id = order_id(&order);
+
+        Ok(order)
     }
 }
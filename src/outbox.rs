@@ -0,0 +1,627 @@
+//! Contains logic for durably delivering matches to the executioner
+//!
+//! Matching never waits on the executioner. Instead, each match is written to
+//! the outbox (and flushed to disk) as the book produces it, and a background
+//! worker delivers pending matches, retrying failed deliveries with
+//! exponential backoff. Every delivery carries the ID of its match as an
+//! idempotency key, so the executioner can discard a match it has already
+//! received should a retry follow a delivery that did in fact arrive.
+//!
+//! Should the executioner reject a match for good, the book that made it is
+//! reconciled with the rejection (see `Book::rollback`). Rejections are only
+//! marked as reconciled once the rollback has been journalled, so that none is
+//! lost to a crash.
+//!
+//! The outbox is stored as newline-delimited JSON, with each line holding the
+//! latest state of a single delivery. Later lines supersede earlier ones, and
+//! the file is compacted once superseded lines outnumber live ones.
+//!
+//! Each delivery records the journal entry that made its match. A settled
+//! delivery is only ever forgotten once that entry has been compacted out of
+//! the journal (see `Outbox::release`), as replaying the entry would otherwise
+//! queue its match for delivery all over again.
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::fs::{read_to_string, rename, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{Mutex, MutexGuard};
+use web3::types::{Address, H160, U256};
+
+use crate::book::{Match, MatchId, Reconciliation};
+use crate::event::{self, EventSender, OrderEvent, OrderEventKind};
+use crate::executioner::Executioner;
+use crate::market::{MarketError, Markets};
+use crate::order::OrderId;
+use crate::rpc::{MatchResponse, RpcError};
+use crate::util::{from_hex_de, from_hex_se};
+
+/// The interval between checks for deliveries that are due
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The delay before the first retry of a failed delivery, which doubles with
+/// each subsequent failure
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest delay between retries of a failed delivery
+pub const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// The number of settled (i.e., delivered or rejected) deliveries kept for
+/// inspection before the oldest whose journal entries have been compacted
+/// away are forgotten
+pub const RETAINED_DELIVERIES: usize = 10_000;
+
+/// Represents how far along its delivery to the executioner a match is
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Display, Serialize, Deserialize,
+)]
+pub enum DeliveryStatus {
+    Pending,   /* awaiting its first or a further attempt */
+    Delivered, /* accepted by the executioner */
+    Rejected,  /* refused by the executioner, so never retried */
+}
+
+/// Represents the delivery of a single match to the executioner
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: MatchId, /* also the idempotency key */
+    pub matched: Match,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub created: DateTime<Utc>,
+    pub next_attempt: DateTime<Utc>,
+    pub transaction: Option<H160>, /* as reported by the executioner */
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub offender: Option<OrderId>, /* as blamed by the executioner */
+    #[serde(default)]
+    pub reconciled: bool, /* whether the book has undone a rejected match */
+    #[serde(default)]
+    pub sequence: Option<u64>, /* of the journal entry making the match */
+}
+
+impl Delivery {
+    /// Returns whether the delivery will never be attempted again
+    pub fn is_settled(&self) -> bool {
+        self.status != DeliveryStatus::Pending
+    }
+
+    /// Returns whether the match was rejected but not yet undone
+    pub fn awaits_rollback(&self) -> bool {
+        self.status == DeliveryStatus::Rejected && !self.reconciled
+    }
+}
+
+/// Represents a delivery as presented to API consumers
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ExternalDelivery {
+    pub id: MatchId,
+    pub market: Address,
+    pub maker: OrderId,
+    pub taker: OrderId,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub amount: U256,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub created: DateTime<Utc>,
+    pub next_attempt: Option<DateTime<Utc>>, /* unless settled */
+    pub transaction: Option<H160>,
+    pub last_error: Option<String>,
+    pub offender: Option<OrderId>,
+    pub reconciled: bool,
+}
+
+impl From<Delivery> for ExternalDelivery {
+    fn from(value: Delivery) -> Self {
+        Self {
+            id: value.id,
+            market: value.matched.taker.market,
+            maker: value.matched.maker.id,
+            taker: value.matched.taker.id,
+            price: value.matched.price,
+            amount: value.matched.amount,
+            next_attempt: if value.is_settled() {
+                None
+            } else {
+                Some(value.next_attempt)
+            },
+            status: value.status,
+            attempts: value.attempts,
+            created: value.created,
+            transaction: value.transaction,
+            last_error: value.last_error,
+            offender: value.offender,
+            reconciled: value.reconciled,
+        }
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum OutboxError {
+    IoError,
+    SerialisationError,
+    CorruptEntry,
+    DeliveryNotFound,
+}
+
+impl From<std::io::Error> for OutboxError {
+    fn from(_error: std::io::Error) -> Self {
+        OutboxError::IoError
+    }
+}
+
+impl From<serde_json::Error> for OutboxError {
+    fn from(_error: serde_json::Error) -> Self {
+        OutboxError::SerialisationError
+    }
+}
+
+/// Returns the delay before the next attempt of a delivery that has failed
+/// `attempts` times
+pub fn backoff(attempts: u32) -> chrono::Duration {
+    let factor: u32 = 2u32.saturating_pow(attempts.saturating_sub(1));
+    let delay: Duration = INITIAL_BACKOFF
+        .checked_mul(factor)
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF);
+    chrono::Duration::from_std(delay)
+        .unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+/// Persistent queue of matches awaiting delivery to the executioner
+#[derive(Debug)]
+pub struct Outbox {
+    path: PathBuf,
+    file: File,
+    deliveries: HashMap<MatchId, Delivery>,
+    queue: VecDeque<MatchId>, /* every delivery, oldest first */
+    lines: usize,             /* lines currently in the file */
+    compacted: u64,           /* journal entries at or below this are gone */
+    unpersisted: bool,        /* whether the file lacks changes in memory */
+}
+
+impl Outbox {
+    /// Opens (or creates) the outbox at `path`
+    ///
+    /// As with the journal, a torn final line is ignored, while corruption
+    /// anywhere else is an error.
+    pub fn open(path: &Path) -> Result<Self, OutboxError> {
+        let mut deliveries: HashMap<MatchId, Delivery> = HashMap::new();
+        let mut queue: VecDeque<MatchId> = VecDeque::new();
+        let mut length: usize = 0;
+        let mut lines: usize = 0;
+
+        if path.exists() {
+            let data: String = read_to_string(path)?;
+
+            for line in data.split_inclusive('\n') {
+                let torn: bool = !line.ends_with('\n');
+
+                match serde_json::from_str::<Delivery>(line.trim_end()) {
+                    Ok(t) if !torn => {
+                        if !deliveries.contains_key(&t.id) {
+                            queue.push_back(t.id);
+                        }
+                        deliveries.insert(t.id, t);
+                    }
+                    _ if torn || length + line.len() == data.len() => {
+                        warn!("Ignoring torn outbox entry: {}", line);
+                        break;
+                    }
+                    _ => return Err(OutboxError::CorruptEntry),
+                }
+
+                length += line.len();
+                lines += 1;
+            }
+        }
+
+        let file: File =
+            OpenOptions::new().create(true).append(true).open(path)?;
+
+        /* drop any torn entry so that new entries start on a fresh line */
+        file.set_len(length as u64)?;
+
+        /* nothing is forgotten until the journal is known (see `recover`) */
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            deliveries,
+            queue,
+            lines,
+            compacted: 0,
+            unpersisted: false,
+        })
+    }
+
+    /// Brings the outbox in line with a journal holding no entries at or
+    /// below `compacted`, and none beyond `latest`
+    ///
+    /// Called on startup, before the journal is next compacted. Deliveries
+    /// made before the outbox recorded journal entries are taken to have been
+    /// made by the entry `latest`, so that they are kept for as long as any
+    /// entry that may have made them.
+    pub fn recover(
+        &mut self,
+        compacted: u64,
+        latest: u64,
+    ) -> Result<(), OutboxError> {
+        for delivery in self.deliveries.values_mut() {
+            delivery.sequence.get_or_insert(latest);
+        }
+
+        self.release(compacted)
+    }
+
+    /// Records that every journal entry at or below `sequence` has been
+    /// compacted away, so that deliveries of their matches may be forgotten
+    pub fn release(&mut self, sequence: u64) -> Result<(), OutboxError> {
+        self.compacted = self.compacted.max(sequence);
+        self.evict()
+    }
+
+    /// Returns the path of the underlying outbox file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the delivery of the match with ID `id`, if known
+    pub fn delivery(&self, id: MatchId) -> Option<&Delivery> {
+        self.deliveries.get(&id)
+    }
+
+    /// Returns every known delivery of a match in `market`, oldest first
+    pub fn deliveries_in(&self, market: Address) -> Vec<&Delivery> {
+        self.queue
+            .iter()
+            .filter_map(|id| self.deliveries.get(id))
+            .filter(|delivery| delivery.matched.taker.market == market)
+            .collect()
+    }
+
+    /// Returns the number of deliveries not yet settled
+    pub fn pending(&self) -> usize {
+        self.deliveries
+            .values()
+            .filter(|delivery| !delivery.is_settled())
+            .count()
+    }
+
+    /// Queues `matched`, as made by the journal entry `sequence`, for
+    /// delivery as of `now`
+    ///
+    /// Matches already in the outbox are left untouched, so that matches
+    /// derived again by replaying the journal are never delivered twice.
+    /// Returns the ID of the match.
+    pub fn enqueue(
+        &mut self,
+        matched: Match,
+        sequence: u64,
+        now: DateTime<Utc>,
+    ) -> Result<MatchId, OutboxError> {
+        let id: MatchId = matched.id();
+
+        if self.deliveries.contains_key(&id) {
+            return Ok(id);
+        }
+
+        let delivery: Delivery = Delivery {
+            id,
+            matched,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            created: now,
+            next_attempt: now,
+            transaction: None,
+            last_error: None,
+            offender: None,
+            reconciled: false,
+            sequence: Some(sequence),
+        };
+
+        /* the worker picks up the delivery even should persisting it fail */
+        self.queue.push_back(id);
+        self.deliveries.insert(id, delivery.clone());
+        self.persist(&delivery)?;
+        Ok(id)
+    }
+
+    /// Returns every pending delivery whose next attempt is due as of `now`,
+    /// oldest first
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<Delivery> {
+        self.queue
+            .iter()
+            .filter_map(|id| self.deliveries.get(id))
+            .filter(|delivery| {
+                !delivery.is_settled() && delivery.next_attempt <= now
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns every rejected delivery whose match is yet to be undone, oldest
+    /// first
+    pub fn unreconciled(&self) -> Vec<Delivery> {
+        self.queue
+            .iter()
+            .filter_map(|id| self.deliveries.get(id))
+            .filter(|delivery| delivery.awaits_rollback())
+            .cloned()
+            .collect()
+    }
+
+    /// Records the outcome of an attempt to deliver the match with ID `id`
+    /// made at `now`
+    ///
+    /// Rejections are final, whereas any other failure schedules a further
+    /// attempt after an exponentially increasing delay.
+    pub fn record(
+        &mut self,
+        id: MatchId,
+        result: Result<MatchResponse, RpcError>,
+        now: DateTime<Utc>,
+    ) -> Result<(), OutboxError> {
+        let delivery: &mut Delivery = match self.deliveries.get_mut(&id) {
+            Some(t) => t,
+            None => return Err(OutboxError::DeliveryNotFound),
+        };
+
+        delivery.attempts += 1;
+
+        match result {
+            Ok(MatchResponse::Accepted(transaction)) => {
+                delivery.status = DeliveryStatus::Delivered;
+                delivery.transaction = Some(transaction);
+                delivery.last_error = None;
+            }
+            Ok(MatchResponse::Rejected(offender)) => {
+                delivery.status = DeliveryStatus::Rejected;
+                delivery.offender = offender;
+                delivery.last_error = None;
+            }
+            Err(e) => {
+                delivery.next_attempt = now + backoff(delivery.attempts);
+                delivery.last_error = Some(e.to_string());
+            }
+        }
+
+        let delivery: Delivery = delivery.clone();
+        self.persist(&delivery)?;
+        self.evict()
+    }
+
+    /// Records that the rejected match with ID `id` has been undone
+    pub fn reconcile(&mut self, id: MatchId) -> Result<(), OutboxError> {
+        let delivery: &mut Delivery = match self.deliveries.get_mut(&id) {
+            Some(t) => t,
+            None => return Err(OutboxError::DeliveryNotFound),
+        };
+
+        delivery.reconciled = true;
+
+        let delivery: Delivery = delivery.clone();
+        self.persist(&delivery)?;
+        self.evict()
+    }
+
+    /// Writes any changes the outbox file lacks, e.g. as a write failed
+    pub fn flush(&mut self) -> Result<(), OutboxError> {
+        if self.unpersisted {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends the current state of `delivery` to the outbox file
+    ///
+    /// The entry is synced to disk before this function returns.
+    fn persist(&mut self, delivery: &Delivery) -> Result<(), OutboxError> {
+        /* a failed write may have left a torn line, so rewrite the file */
+        if self.unpersisted {
+            return self.compact();
+        }
+
+        let mut line: Vec<u8> = serde_json::to_vec(delivery)?;
+        line.push(b'\n');
+
+        if let Err(e) = self
+            .file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data())
+        {
+            self.unpersisted = true;
+            return Err(e.into());
+        }
+        self.lines += 1;
+
+        if self.lines > 2 * self.deliveries.len().max(1) {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the oldest settled deliveries beyond `RETAINED_DELIVERIES`,
+    /// sparing those awaiting a rollback and those whose journal entries may
+    /// yet be replayed
+    fn evict(&mut self) -> Result<(), OutboxError> {
+        let settled = |delivery: &Delivery| {
+            delivery.is_settled() && !delivery.awaits_rollback()
+        };
+        let compacted: u64 = self.compacted;
+        let evictable = |delivery: &Delivery| {
+            settled(delivery)
+                && matches!(delivery.sequence, Some(t) if t <= compacted)
+        };
+        let mut excess: usize = self
+            .deliveries
+            .values()
+            .filter(|delivery| settled(delivery))
+            .count()
+            .saturating_sub(RETAINED_DELIVERIES);
+
+        if excess == 0 {
+            return Ok(());
+        }
+
+        let before: usize = self.queue.len();
+        let deliveries: &mut HashMap<MatchId, Delivery> = &mut self.deliveries;
+        self.queue.retain(|id| {
+            let evict: bool = excess > 0
+                && matches!(deliveries.get(id), Some(d) if evictable(d));
+
+            if evict {
+                deliveries.remove(id);
+                excess -= 1;
+            }
+
+            !evict
+        });
+
+        /* deliveries still awaiting compaction of the journal stay put */
+        if self.queue.len() == before {
+            return Ok(());
+        }
+
+        self.compact()
+    }
+
+    /// Rewrites the outbox file to hold only the latest state of each known
+    /// delivery
+    fn compact(&mut self) -> Result<(), OutboxError> {
+        let mut data: Vec<u8> = vec![];
+
+        for delivery in
+            self.queue.iter().filter_map(|id| self.deliveries.get(id))
+        {
+            data.append(&mut serde_json::to_vec(delivery)?);
+            data.push(b'\n');
+        }
+
+        /* write the live entries aside and atomically swap them in */
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut tmp_file: File = File::create(&tmp_path)?;
+        tmp_file.write_all(&data)?;
+        tmp_file.sync_all()?;
+        rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.lines = self.queue.len();
+        self.unpersisted = false;
+        info!("Compacted outbox to {} entries", self.lines);
+        Ok(())
+    }
+}
+
+/// Attempts every delivery that is due as of `now` against `executioner`
+///
+/// The outbox is not locked while awaiting the executioner. Returns the
+/// deliveries attempted, as they stood after the attempt.
+pub async fn deliver(
+    outbox: &Arc<Mutex<Outbox>>,
+    executioner: &dyn Executioner,
+    now: DateTime<Utc>,
+) -> Vec<Delivery> {
+    let due: Vec<Delivery> = outbox.lock().await.due(now);
+    let mut attempted: Vec<Delivery> = vec![];
+
+    for delivery in due {
+        let result: Result<MatchResponse, RpcError> =
+            executioner.submit(delivery.matched.clone()).await;
+
+        if let Err(e) = &result {
+            warn!("Failed to deliver match {:#x}: {}", delivery.id, e);
+        }
+
+        let mut outbox: MutexGuard<Outbox> = outbox.lock().await;
+
+        if let Err(e) = outbox.record(delivery.id, result, Utc::now()) {
+            error!("Failed to record delivery of {:#x}: {}", delivery.id, e);
+        }
+
+        if let Some(t) = outbox.delivery(delivery.id) {
+            attempted.push(t.clone());
+        }
+    }
+
+    attempted
+}
+
+/// Publishes a `Confirmed` event for both orders of each match among
+/// `deliveries` that the executioner has accepted
+pub fn confirm(
+    events: &EventSender,
+    deliveries: &[Delivery],
+    now: DateTime<Utc>,
+) {
+    for delivery in deliveries
+        .iter()
+        .filter(|delivery| delivery.status == DeliveryStatus::Delivered)
+    {
+        let matched: &Match = &delivery.matched;
+
+        for order in [&matched.maker, &matched.taker].iter() {
+            event::publish(
+                events,
+                OrderEvent::of_fill(
+                    OrderEventKind::Confirmed,
+                    order,
+                    matched,
+                    now,
+                ),
+            );
+        }
+    }
+}
+
+/// Undoes every rejected match not yet undone in the book that made it
+///
+/// Returns the resulting reconciliations.
+pub async fn reconcile(markets: &Markets) -> Vec<Reconciliation> {
+    let unreconciled: Vec<Delivery> =
+        markets.outbox().lock().await.unreconciled();
+    let mut reconciliations: Vec<Reconciliation> = vec![];
+
+    for delivery in unreconciled {
+        match markets.rollback(delivery.matched, delivery.offender).await {
+            Ok(t) => reconciliations.push(t),
+            /* a book that no longer exists has nothing to undo */
+            Err(MarketError::MarketNotFound) => {}
+            Err(e) => {
+                error!("Failed to roll back match {:#x}: {}", delivery.id, e);
+                continue;
+            }
+        }
+
+        if let Err(e) = markets.outbox().lock().await.reconcile(delivery.id) {
+            error!("Failed to record rollback of {:#x}: {}", delivery.id, e);
+        }
+    }
+
+    reconciliations
+}
+
+/// Delivers matches to the executioner as they fall due, undoing those it
+/// rejects, forever
+pub async fn run(markets: Markets) {
+    let mut ticker: tokio::time::Interval =
+        tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        let attempted: Vec<Delivery> =
+            deliver(markets.outbox(), markets.executioner(), Utc::now()).await;
+        confirm(markets.events(), &attempted, Utc::now());
+        reconcile(&markets).await;
+    }
+}
@@ -0,0 +1,113 @@
+//! Contains the registry of orders that have left an order book for good
+//!
+//! Once filled or cancelled, an order vanishes from the price levels of its
+//! book, yet its signature remains valid. The registry remembers the IDs of
+//! such orders so that the same signed order is never accepted twice. Unlike
+//! the indices of a book, the registry cannot be derived from the price
+//! levels, and so is serialised along with them.
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::order::OrderId;
+
+/// The number of order IDs each registry remembers before forgetting the
+/// oldest
+pub const TERMINAL_ORDERS: usize = 100_000;
+
+/// Represents how an order left the book
+#[derive(
+    Clone, Copy, Debug, Display, Serialize, Deserialize, PartialEq, Eq,
+)]
+pub enum Termination {
+    Filled,
+    Cancelled, /* including expiry and self-trade prevention */
+}
+
+/// Represents the most recent orders to leave a book, oldest first
+#[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(
+    from = "VecDeque<(OrderId, Termination)>",
+    into = "VecDeque<(OrderId, Termination)>"
+)]
+pub struct TerminalOrders {
+    order: VecDeque<OrderId>, /* in order of leaving the book */
+    terminations: HashMap<OrderId, Termination>,
+}
+
+impl TerminalOrders {
+    /// Constructor for the `TerminalOrders` type
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            terminations: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of order IDs remembered
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns whether no order IDs are remembered
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns how the order with ID `id` left the book, if it is remembered
+    pub fn get(&self, id: OrderId) -> Option<Termination> {
+        self.terminations.get(&id).copied()
+    }
+
+    /// Remembers that the order with ID `id` left the book, forgetting the
+    /// oldest order if need be
+    ///
+    /// Orders already remembered keep their original termination.
+    pub fn record(&mut self, id: OrderId, termination: Termination) {
+        if self.terminations.contains_key(&id) {
+            return;
+        }
+
+        self.terminations.insert(id, termination);
+        self.order.push_back(id);
+
+        if self.order.len() > TERMINAL_ORDERS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.terminations.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets the order with ID `id`, e.g., should the trade that filled it
+    /// be undone
+    pub fn forget(&mut self, id: OrderId) -> Option<Termination> {
+        let termination: Termination = self.terminations.remove(&id)?;
+        self.order.retain(|other| *other != id);
+        Some(termination)
+    }
+}
+
+impl From<VecDeque<(OrderId, Termination)>> for TerminalOrders {
+    fn from(value: VecDeque<(OrderId, Termination)>) -> Self {
+        let mut orders: TerminalOrders = TerminalOrders::new();
+
+        for (id, termination) in value {
+            orders.record(id, termination);
+        }
+
+        orders
+    }
+}
+
+impl From<TerminalOrders> for VecDeque<(OrderId, Termination)> {
+    fn from(value: TerminalOrders) -> Self {
+        let terminations: HashMap<OrderId, Termination> = value.terminations;
+
+        value
+            .order
+            .into_iter()
+            .map(|id| (id, terminations[&id]))
+            .collect()
+    }
+}
@@ -1,11 +1,21 @@
 use std::fmt::Display;
 use std::str::FromStr;
 
-use reqwest::{header, Client, Response};
+use reqwest::{header, Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
-use web3::types::H160;
+use web3::types::{H160, H256};
 
-use crate::order::{ExternalOrder, Order};
+use crate::order::{ExternalOrder, Order, OrderId};
+
+/// The header carrying the idempotency key of each forwarded match
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// The statuses with which the executioner refuses a matched pair for good
+pub const REJECTION_STATUSES: [StatusCode; 3] = [
+    StatusCode::BAD_REQUEST,
+    StatusCode::FORBIDDEN,
+    StatusCode::UNPROCESSABLE_ENTITY,
+];
 
 #[derive(Display, Debug)]
 pub enum RpcError {
@@ -26,15 +36,43 @@ impl From<rustc_hex::FromHexError> for RpcError {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MatchRequest {
-    maker: ExternalOrder,
-    taker: ExternalOrder,
+    pub maker: ExternalOrder,
+    pub taker: ExternalOrder,
+}
+
+/// Represents the executioner's final answer to a matched pair
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchResponse {
+    Accepted(H160), /* with the hash of the resulting transaction */
+    Rejected(Option<OrderId>), /* never to be accepted, blaming this order */
+}
+
+/// Represents the body of the executioner's response upon rejecting a
+/// matched pair
+///
+/// The executioner names the offending order should the pair be refused
+/// because of one order alone (e.g., an invalid signature or insufficient
+/// margin).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RejectionResponse {
+    #[serde(default)]
+    pub offender: Option<OrderId>,
+}
+
+/// Returns whether the executioner refused a matched pair for good by
+/// responding with `status`
+///
+/// Other client errors (e.g., 408 Request Timeout or 429 Too Many Requests)
+/// say nothing of the pair itself, so the pair is forwarded again later.
+pub fn is_rejection(status: StatusCode) -> bool {
+    REJECTION_STATUSES.contains(&status)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CheckRequest {
-    order: ExternalOrder,
+    pub order: ExternalOrder,
 }
 
 #[allow(unused_must_use)]
@@ -69,11 +107,19 @@ pub async fn check_order_validity(
     Ok(response.status().is_success())
 }
 
+/// Forwards a matched pair of orders to the executioner at `address`
+///
+/// The executioner may use `key` to recognise a pair it has already received,
+/// as pairs are forwarded again should a previous attempt have failed.
+/// Returns `MatchResponse::Rejected` if the executioner refused the pair
+/// outright (i.e., retrying would be futile), and an error if the attempt
+/// failed otherwise.
 pub async fn send_matched_orders(
     maker: Order,
     taker: Order,
     address: String,
-) -> Result<H160, RpcError> {
+    key: H256,
+) -> Result<MatchResponse, RpcError> {
     info!(
         "Forwarding matched pair ({}, {}) to {}...",
         maker, taker, address
@@ -90,6 +136,7 @@ pub async fn send_matched_orders(
     let result: Response = match client
         .post(endpoint)
         .header(header::CONTENT_TYPE, "application/json")
+        .header(IDEMPOTENCY_KEY_HEADER, format!("{:#x}", key))
         .body(serde_json::to_string(&payload).unwrap())
         .send()
         .await
@@ -102,6 +149,19 @@ pub async fn send_matched_orders(
 
     info!("{} said {}", address, result.status());
 
+    if is_rejection(result.status()) {
+        /* the executioner need not explain itself */
+        let rejection: RejectionResponse = match result.text().await {
+            Ok(t) => serde_json::from_str(&t).unwrap_or_default(),
+            Err(_e) => RejectionResponse::default(),
+        };
+        return Ok(MatchResponse::Rejected(rejection.offender));
+    }
+
+    if !result.status().is_success() {
+        return Err(RpcError::HttpError);
+    }
+
     /* extract the transaction hash from the response body */
     let hash: H160 = match result.text().await {
         Ok(t) => match H160::from_str(&t) {
@@ -113,5 +173,5 @@ pub async fn send_matched_orders(
         Err(e) => return Err(RpcError::from(e)),
     };
 
-    Ok(hash)
+    Ok(MatchResponse::Accepted(hash))
 }
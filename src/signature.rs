@@ -0,0 +1,133 @@
+//! Contains logic for verifying the signatures of orders
+//!
+//! Orders are signed as EIP-712 typed data: the signature covers the hash of
+//! the order's fields (see `ORDER_TYPE`), prefixed by the domain separator of
+//! the market it was signed for. Each market configures its own domain
+//! separator, as the separator commits to the address of the contract that
+//! settles the market's trades.
+//!
+//! Messages that concern no market (e.g., those authenticating a trader) are
+//! instead signed as EIP-191 personal messages.
+use std::fmt::Display;
+
+use ethabi::Token;
+use thiserror::Error;
+use web3::signing::{keccak256, recover};
+use web3::types::{Address, H256, U256};
+
+use crate::order::{self, Order};
+
+/// The EIP-712 type of an order, as signed by traders
+pub const ORDER_TYPE: &str = "Order(address maker,address market,uint256 price,uint256 amount,uint256 side,uint256 expires,uint256 created,uint256 orderType,uint256 maxSlippage,uint256 timeInForce,uint256 postOnly,uint256 stopPrice,uint256 displayQuantity,uint256 selfTradePrevention)";
+
+/// The EIP-712 type of the domain of a market
+pub const DOMAIN_TYPE: &str = "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// Length, in bytes, of a signature (i.e., `r`, `s` and `v`)
+pub const SIGNATURE_LENGTH: usize = 65;
+
+#[derive(Clone, Copy, Debug, Display, Error, PartialEq, Eq)]
+pub enum SignatureError {
+    InvalidLength,
+    InvalidRecoveryId,
+    InvalidSignature,
+    WrongSigner, /* signed by someone other than the order's trader */
+}
+
+/// Computes the domain separator of a market from its EIP-712 domain
+pub fn domain_separator(
+    name: &str,
+    version: &str,
+    chain_id: U256,
+    verifying_contract: Address,
+) -> H256 {
+    let components: Vec<Token> = vec![
+        Token::FixedBytes(keccak256(DOMAIN_TYPE.as_bytes()).to_vec()),
+        Token::FixedBytes(keccak256(name.as_bytes()).to_vec()),
+        Token::FixedBytes(keccak256(version.as_bytes()).to_vec()),
+        Token::Uint(chain_id),
+        Token::Address(verifying_contract),
+    ];
+
+    keccak256(&ethabi::encode(&components)).into()
+}
+
+/// Computes the EIP-712 hash of the fields of `order` (i.e., its struct hash)
+pub fn order_hash(order: &Order) -> H256 {
+    let mut components: Vec<Token> =
+        vec![Token::FixedBytes(keccak256(ORDER_TYPE.as_bytes()).to_vec())];
+    components.extend(order::signed_fields(order));
+
+    keccak256(&ethabi::encode(&components)).into()
+}
+
+/// Computes the digest signed by the trader of `order`, given the domain
+/// separator of its market
+pub fn digest(domain_separator: H256, order: &Order) -> H256 {
+    let mut message: Vec<u8> = vec![0x19, 0x01];
+    message.extend_from_slice(domain_separator.as_bytes());
+    message.extend_from_slice(order_hash(order).as_bytes());
+
+    keccak256(&message).into()
+}
+
+/// Computes the digest signed by Ethereum clients when asked to sign
+/// `message` as a personal message (i.e., as per EIP-191)
+pub fn personal_digest(message: &[u8]) -> H256 {
+    let mut data: Vec<u8> =
+        format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message);
+
+    keccak256(&data).into()
+}
+
+/// Recovers the address that produced `signature` over `digest`
+///
+/// The recovery ID (`v`) may be given either as 27 or 28 (as Ethereum
+/// clients produce) or as 0 or 1.
+pub fn recover_signer(
+    digest: H256,
+    signature: &[u8],
+) -> Result<Address, SignatureError> {
+    if signature.len() != SIGNATURE_LENGTH {
+        return Err(SignatureError::InvalidLength);
+    }
+
+    let (signature, v): (&[u8], u8) = (
+        &signature[..SIGNATURE_LENGTH - 1],
+        signature[SIGNATURE_LENGTH - 1],
+    );
+
+    let recovery_id: i32 = match v {
+        0 | 1 => v as i32,
+        27 | 28 => v as i32 - 27,
+        _ => return Err(SignatureError::InvalidRecoveryId),
+    };
+
+    match recover(digest.as_bytes(), signature, recovery_id) {
+        Ok(t) => Ok(t),
+        Err(_e) => Err(SignatureError::InvalidSignature),
+    }
+}
+
+/// Recovers the address that signed `order`, given the domain separator of
+/// its market
+pub fn signer(
+    domain_separator: H256,
+    order: &Order,
+) -> Result<Address, SignatureError> {
+    recover_signer(digest(domain_separator, order), &order.signed_data)
+}
+
+/// Checks that `order` was signed by its trader, given the domain separator
+/// of its market
+pub fn verify(
+    domain_separator: H256,
+    order: &Order,
+) -> Result<(), SignatureError> {
+    if signer(domain_separator, order)? == order.trader {
+        Ok(())
+    } else {
+        Err(SignatureError::WrongSigner)
+    }
+}
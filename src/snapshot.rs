@@ -0,0 +1,466 @@
+//! Contains logic for periodically persisting the OME's state to disk
+//!
+//! Snapshots are stored as a versioned envelope around the serialised
+//! `OmeState`. Snapshots written by older versions of the OME are upgraded to
+//! the current format on load by the migrations in this module.
+//!
+//! Previous snapshots are kept as rotated generations alongside the current
+//! one, and the journal is only compacted up to the oldest generation kept,
+//! so that the state can still be recovered from an older generation should
+//! the newer ones be unreadable.
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{copy, hard_link, read_to_string, remove_file, rename, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio::sync::{oneshot, Mutex};
+use web3::types::U256;
+
+use crate::journal::{Journal, JournalError};
+use crate::market::{MarketError, Markets};
+use crate::outbox::OutboxError;
+use crate::state::OmeState;
+
+/// The version of the snapshot format written by this build of the OME
+///
+/// Version history:
+/// - 0: unversioned, raw `OmeState` with `LTP` and `spread` as (possibly
+///   truncated) JSON numbers
+/// - 1: versioned envelope, with `LTP` and `spread` as decimal strings
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Represents a snapshot of the entire state of the OME, as stored on disk
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub engine_version: String,
+    pub created: DateTime<Utc>,
+    pub checksum: String, /* Keccak-256 digest of `state` */
+    pub state: Value,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum SnapshotError {
+    IoError,
+    SerialisationError,
+    JournalError,
+    MarketError,
+    OutboxError,
+    ChecksumMismatch,
+    UnsupportedVersion,
+    MigrationError,
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(_error: std::io::Error) -> Self {
+        SnapshotError::IoError
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(_error: serde_json::Error) -> Self {
+        SnapshotError::SerialisationError
+    }
+}
+
+impl From<JournalError> for SnapshotError {
+    fn from(_error: JournalError) -> Self {
+        SnapshotError::JournalError
+    }
+}
+
+impl From<MarketError> for SnapshotError {
+    fn from(_error: MarketError) -> Self {
+        SnapshotError::MarketError
+    }
+}
+
+impl From<OutboxError> for SnapshotError {
+    fn from(_error: OutboxError) -> Self {
+        SnapshotError::OutboxError
+    }
+}
+
+impl From<tokio::task::JoinError> for SnapshotError {
+    fn from(_error: tokio::task::JoinError) -> Self {
+        SnapshotError::IoError
+    }
+}
+
+/// Returns the path of the `generation`th most recent rotated snapshot
+///
+/// Generation zero is the current snapshot itself.
+pub fn generation_path(path: &Path, generation: usize) -> PathBuf {
+    if generation == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut rotated: OsString = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", generation));
+    rotated.into()
+}
+
+/// Computes the checksum of a serialised `OmeState`
+fn checksum(state: &Value) -> Result<String, SnapshotError> {
+    let digest: [u8; 32] =
+        web3::signing::keccak256(&serde_json::to_vec(state)?);
+    Ok("0x".to_string() + &hex::encode(digest))
+}
+
+impl Snapshot {
+    /// Wraps `state` in a snapshot envelope of the current version
+    pub fn new(state: &OmeState) -> Result<Self, SnapshotError> {
+        let state: Value = serde_json::to_value(state)?;
+
+        Ok(Self {
+            version: SNAPSHOT_VERSION,
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: Utc::now(),
+            checksum: checksum(&state)?,
+            state,
+        })
+    }
+
+    /// Verifies, migrates and unwraps the `OmeState` held in this snapshot
+    pub fn into_state(self) -> Result<OmeState, SnapshotError> {
+        if checksum(&self.state)? != self.checksum {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+
+        load(migrate(self.version, self.state)?)
+    }
+}
+
+/// Deserialises a serialised `OmeState` of the current snapshot format
+fn load(state: Value) -> Result<OmeState, SnapshotError> {
+    let mut state: OmeState = serde_json::from_value(state)?;
+
+    /* indices are not serialised, so must be rebuilt */
+    state.reindex();
+    Ok(state)
+}
+
+/// Upgrades a serialised `OmeState` from snapshot format `version` to
+/// `SNAPSHOT_VERSION`, one version at a time
+fn migrate(mut version: u32, mut state: Value) -> Result<Value, SnapshotError> {
+    if version > SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion);
+    }
+
+    while version < SNAPSHOT_VERSION {
+        info!("Migrating snapshot from version {}...", version);
+
+        state = match version {
+            0 => migrate_v0(state)?,
+            _ => return Err(SnapshotError::UnsupportedVersion),
+        };
+        version += 1;
+    }
+
+    Ok(state)
+}
+
+/// Version 0 stored each book's `LTP` and `spread` as JSON numbers
+///
+/// Those too large for a `u64` must already have been quoted (see
+/// `quote_wide_integers`), as they would otherwise have been parsed as floats.
+fn migrate_v0(mut state: Value) -> Result<Value, SnapshotError> {
+    let books: &mut Map<String, Value> = match state["books"].as_object_mut() {
+        Some(t) => t,
+        None => return Err(SnapshotError::MigrationError),
+    };
+
+    for book in books.values_mut() {
+        for field in ["LTP", "spread"].iter() {
+            let value: String = match &book[*field] {
+                Value::Number(t) if t.is_u64() => t.to_string(),
+                Value::String(t) if U256::from_dec_str(t).is_ok() => t.clone(),
+                _ => return Err(SnapshotError::MigrationError),
+            };
+            book[*field] = Value::String(value);
+        }
+    }
+
+    Ok(state)
+}
+
+/// Quotes each integer in the JSON document `json` that is too large for a
+/// `u64`, so that it can be parsed without losing precision
+fn quote_wide_integers(json: &str) -> String {
+    let mut quoted: String = String::with_capacity(json.len());
+    let mut chars = json.chars().peekable();
+    let mut in_string: bool = false;
+    let mut escaped: bool = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            quoted.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        if c == '"' {
+            in_string = true;
+            quoted.push(c);
+            continue;
+        }
+
+        if !c.is_ascii_digit() && c != '-' {
+            quoted.push(c);
+            continue;
+        }
+
+        /* consume the whole number, fraction and exponent included */
+        let mut number: String = c.to_string();
+        while let Some(&next) = chars.peek() {
+            if !next.is_ascii_digit() && !"+-.eE".contains(next) {
+                break;
+            }
+            number.push(next);
+            chars.next();
+        }
+
+        if number.chars().all(|t| t.is_ascii_digit())
+            && number.parse::<u64>().is_err()
+        {
+            quoted.push_str(&format!("\"{}\"", number));
+        } else {
+            quoted.push_str(&number);
+        }
+    }
+
+    quoted
+}
+
+/// Reads the snapshot at `path`, upgrading it to the current format if need be
+///
+/// Unlike a missing snapshot, a snapshot that cannot be read in full is always
+/// an error, as silently starting from an empty state would drop every order
+/// it held.
+pub fn read(path: &Path) -> Result<OmeState, SnapshotError> {
+    let text: String = read_to_string(path)?;
+    let data: Value = serde_json::from_str(&text)?;
+
+    /* unversioned snapshots are the bare state itself */
+    if data.get("version").is_none() && data.get("books").is_some() {
+        /* read again, keeping every digit of integers beyond a u64 */
+        let data: Value = serde_json::from_str(&quote_wide_integers(&text))?;
+        return load(migrate(0, data)?);
+    }
+
+    let snapshot: Snapshot = serde_json::from_value(data)?;
+    info!(
+        "Loading version {} snapshot written by OME {} at {}...",
+        snapshot.version, snapshot.engine_version, snapshot.created
+    );
+    snapshot.into_state()
+}
+
+/// Reads the most recent generation of the snapshot at `path` that can be
+/// read in full, returning `None` if there is no snapshot at all
+///
+/// Older generations are only read should every newer one be missing or
+/// unreadable. If no generation can be read, the error reading the most
+/// recent one is returned.
+pub fn read_latest(path: &Path) -> Result<Option<OmeState>, SnapshotError> {
+    let mut first_error: Option<SnapshotError> = None;
+
+    for generation in 0.. {
+        let current: PathBuf = generation_path(path, generation);
+
+        /* the current snapshot may be missing mid-rotation, but no other */
+        if !current.exists() {
+            if generation == 0 {
+                continue;
+            }
+            break;
+        }
+
+        match read(&current) {
+            Ok(state) => {
+                if generation > 0 {
+                    warn!("Recovering from snapshot {}...", current.display());
+                }
+                return Ok(Some(state));
+            }
+            Err(e) => {
+                error!("Failed to read snapshot {}: {}", current.display(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
+
+/// Writes `state` to `path`, replacing any existing snapshot atomically
+///
+/// The state is first written in full to a temporary file alongside `path`,
+/// then renamed over it, so readers only ever see complete snapshots. Up to
+/// `generations` previous snapshots are kept as `path.1`, `path.2`, etc.
+/// (most recent first). The previous snapshot is linked (or copied) to
+/// `path.1` rather than moved there, so that a complete snapshot remains at
+/// `path` throughout.
+pub fn write(
+    state: &OmeState,
+    path: &Path,
+    generations: usize,
+) -> Result<(), SnapshotError> {
+    let data: Vec<u8> = serde_json::to_vec(&Snapshot::new(state)?)?;
+
+    let mut tmp_path: OsString = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut tmp_file: File = File::create(&tmp_path)?;
+    tmp_file.write_all(&data)?;
+    tmp_file.sync_all()?;
+
+    /* shift each older generation back by one, dropping the oldest */
+    for generation in (1..generations).rev() {
+        let source: PathBuf = generation_path(path, generation);
+
+        if source.exists() {
+            rename(&source, generation_path(path, generation + 1))?;
+        }
+    }
+
+    /* keep the current snapshot in place until the new one replaces it */
+    if generations > 0 && path.exists() {
+        let previous: PathBuf = generation_path(path, 1);
+        let mut link_path: OsString = previous.as_os_str().to_owned();
+        link_path.push(".tmp");
+
+        if Path::new(&link_path).exists() {
+            remove_file(&link_path)?;
+        }
+        if hard_link(path, &link_path).is_err() {
+            copy(path, &link_path)?;
+            File::open(&link_path)?.sync_all()?;
+        }
+        rename(&link_path, &previous)?;
+    }
+
+    rename(&tmp_path, path)?;
+
+    /* make the renames themselves durable */
+    if let Some(parent) = path.parent() {
+        let parent: &Path = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Snapshots the current engine state to `path`, returning the sequence
+/// number of the last journal entry the snapshot reflects
+///
+/// Matches are only replayed from journal entries after the snapshot, so no
+/// snapshot is written until every match it reflects is in the outbox file.
+pub async fn snapshot(
+    markets: &Markets,
+    path: &Path,
+    generations: usize,
+) -> Result<u64, SnapshotError> {
+    /* copy each book in turn, without pausing the others */
+    let ome_state: OmeState = markets.snapshot().await?;
+    let sequence: u64 = ome_state.sequence();
+
+    /* books queue their matches before answering, so this covers them all */
+    markets.outbox().lock().await.flush()?;
+
+    let dump_path: PathBuf = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        write(&ome_state, &dump_path, generations)
+    })
+    .await??;
+
+    info!(
+        "Snapshotted state at entry {} to {}",
+        sequence,
+        path.display()
+    );
+    Ok(sequence)
+}
+
+/// Snapshots engine state to `path` every `interval` until `shutdown` fires,
+/// at which point a final snapshot is taken
+///
+/// Running every snapshot from this one task guarantees that two snapshots
+/// never race to write the dump (and compact the journal).
+///
+/// Journal entries are only compacted away once every generation kept
+/// reflects them, so that any generation can be brought up to date by
+/// replaying the journal. Until this task has written as many snapshots as
+/// there are generations, the journal is therefore left as it is.
+pub async fn run(
+    markets: Markets,
+    journal: Arc<Mutex<Journal>>,
+    path: PathBuf,
+    interval: Duration,
+    generations: usize,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let mut ticker: tokio::time::Interval = tokio::time::interval(interval);
+    let mut written: VecDeque<u64> = VecDeque::new(); /* most recent first */
+
+    /* the first tick completes immediately, so skip it */
+    ticker.tick().await;
+
+    loop {
+        let last: bool = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = &mut shutdown => true,
+        };
+
+        match snapshot(&markets, &path, generations).await {
+            Ok(sequence) => {
+                written.push_front(sequence);
+                written.truncate(generations + 1);
+            }
+            Err(e) => {
+                error!("Failed to snapshot state to {}: {}", path.display(), e)
+            }
+        }
+
+        /* the oldest generation kept, once this task has written it */
+        if let Some(oldest) = written.get(generations).copied() {
+            let compacted: Result<(), JournalError> =
+                journal.lock().await.compact(oldest);
+
+            match compacted {
+                /* replaying can no longer re-derive the compacted matches */
+                Ok(()) => {
+                    if let Err(e) =
+                        markets.outbox().lock().await.release(oldest)
+                    {
+                        error!("Failed to release outbox deliveries: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to compact journal: {}", e),
+            }
+        }
+
+        if last {
+            break;
+        }
+    }
+}
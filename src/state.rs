@@ -1,17 +1,25 @@
 //! Contains logic for interacting with the OME's state
 use std::collections::HashMap;
-use std::fs::read_to_string;
 use std::path::Path;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use web3::types::Address;
 
-use crate::book::Book;
+use crate::book::{Book, Match};
+use crate::journal::{Journal, JournalEntry, JournalError, JournalEvent};
+use crate::market;
+use crate::order::{Order, OrderSide};
+use crate::snapshot::{self, SnapshotError};
 
 /// Represents the entire state of the OME
 #[derive(Clone, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
 pub struct OmeState {
     books: HashMap<Address, Book>,
+    #[serde(default)]
+    sequence: u64, /* sequence number of the last journal entry applied */
+    #[serde(default)]
+    ahead: HashMap<Address, u64>, /* books reflecting later entries */
 }
 
 impl OmeState {
@@ -19,19 +27,34 @@ impl OmeState {
     pub fn new() -> Self {
         Self {
             books: HashMap::new(),
+            sequence: 0,
+            ahead: HashMap::new(),
         }
     }
 
-    pub fn from_dumpfile(path: &Path) -> Option<Self> {
-        let dump_data: String = match read_to_string(path) {
-            Ok(t) => t,
-            Err(_e) => return None,
-        };
+    /// Assembles the state of the OME as of journal entry `sequence` from
+    /// each book and the sequence number of the last journal entry applied to
+    /// it
+    ///
+    /// Books may reflect entries more recent than `sequence`, which replay
+    /// then skips for those books alone.
+    pub fn from_books(books: Vec<(Book, u64)>, sequence: u64) -> Self {
+        let mut state: OmeState = Self::new();
+        state.sequence = sequence;
 
-        match serde_json::from_str(&dump_data) {
-            Ok(t) => Some(t),
-            Err(_e) => None,
+        for (book, applied) in books {
+            if applied > sequence {
+                state.ahead.insert(book.market, applied);
+            }
+            state.add_book(book);
         }
+
+        state
+    }
+
+    /// Loads the state stored in the snapshot at `path`
+    pub fn from_dumpfile(path: &Path) -> Result<Self, SnapshotError> {
+        snapshot::read(path)
     }
 
     /// Returns a reference to the mapping from tickers to `Book` types
@@ -59,4 +82,134 @@ impl OmeState {
     pub fn remove_book(&mut self, market: Address) -> Option<Book> {
         self.books.remove(&market)
     }
+
+    /// Consumes the state, returning every book
+    pub fn into_books(self) -> HashMap<Address, Book> {
+        self.books
+    }
+
+    /// Rebuilds the indices of every book (see `Book::reindex`)
+    pub fn reindex(&mut self) {
+        for book in self.books.values_mut() {
+            book.reindex();
+        }
+    }
+
+    /// Removes every order that has expired as of `now` from every book
+    ///
+    /// Returns the orders removed.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<Order> {
+        self.books
+            .values_mut()
+            .flat_map(|book| book.expire(now))
+            .collect()
+    }
+
+    /// Cancels every open order of `trader` on `side` in `market`, where
+    /// `None` matches any trader, either side or every market respectively
+    ///
+    /// Returns the orders cancelled.
+    pub fn mass_cancel(
+        &mut self,
+        market: Option<Address>,
+        trader: Option<Address>,
+        side: Option<OrderSide>,
+    ) -> Vec<Order> {
+        self.books
+            .values_mut()
+            .filter(|book| market.is_none() || market == Some(book.market))
+            .flat_map(|book| book.mass_cancel(trader, side))
+            .collect()
+    }
+
+    /// Returns the sequence number of the last journal entry applied to this
+    /// state
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Writes `event` to the journal ahead of it being applied to this state
+    ///
+    /// Callers must only apply the event if this succeeds. Returns the time
+    /// at which the event was recorded, which is also the time as of which it
+    /// will be applied upon replay.
+    pub fn record(
+        &mut self,
+        journal: &mut Journal,
+        event: JournalEvent,
+    ) -> Result<DateTime<Utc>, JournalError> {
+        let now: DateTime<Utc> = Utc::now();
+        self.sequence = journal.append_at(event, now)?;
+        Ok(now)
+    }
+
+    /// Applies every journal entry more recent than this state
+    ///
+    /// Entries at or below `self.sequence()` are assumed to already be
+    /// reflected in the state (i.e., they precede the dump it was loaded
+    /// from) and are skipped, as are later entries already reflected in the
+    /// book they concern.
+    ///
+    /// Returns every match made in doing so, so that any not yet queued for
+    /// the executioner before a crash can be (see `Outbox::enqueue`).
+    pub async fn replay(&mut self, entries: Vec<JournalEntry>) -> Vec<Match> {
+        let mut matches: Vec<Match> = vec![];
+
+        for entry in entries {
+            if entry.sequence <= self.sequence {
+                continue;
+            }
+
+            match entry.event {
+                JournalEvent::AddBook { market } => {
+                    self.add_book(Book::new(market));
+                }
+                JournalEvent::RemoveBook { market } => {
+                    self.remove_book(market);
+                }
+                event => {
+                    let markets: Vec<Address> = match &event {
+                        /* older journals cancel across markets at once */
+                        JournalEvent::MassCancel { market: None, .. } => {
+                            self.books.keys().cloned().collect()
+                        }
+                        _ => event.market().into_iter().collect(),
+                    };
+
+                    for market in markets {
+                        if entry.sequence
+                            <= self.ahead.get(&market).copied().unwrap_or(0)
+                        {
+                            continue;
+                        }
+
+                        if let Some(book) = self.books.get_mut(&market) {
+                            if let Err(e) = market::apply(
+                                book,
+                                event.clone(),
+                                &mut matches,
+                                &mut vec![],
+                                entry.timestamp,
+                            )
+                            .await
+                            {
+                                warn!(
+                                    "Failed to replay entry {}: {}",
+                                    entry.sequence, e
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            self.sequence = entry.sequence;
+        }
+
+        /* every book now reflects every entry */
+        self.ahead.clear();
+
+        info!("Replayed journal up to entry {}", self.sequence);
+        matches
+    }
 }
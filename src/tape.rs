@@ -0,0 +1,269 @@
+//! Contains logic for publishing the trades of each order book (i.e., its
+//! tape)
+//!
+//! Each trade is reported to every subscriber as the book makes it. The book
+//! also keeps its most recent trades in memory, so that a subscriber that
+//! loses its connection can resume from the last trade it received.
+//!
+//! Trades are identified by the sequence number of the journal entry that
+//! made them, along with their position among the trades made by that entry.
+//! Trade IDs therefore keep increasing across restarts, although trades made
+//! before a restart can no longer be resumed from. A subscriber resuming from
+//! a trade older than those kept is told that it missed trades (see
+//! `Backlog`), rather than silently being sent only those kept.
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use warp::sse::Event;
+use web3::futures::stream::{self, Stream, StreamExt};
+use web3::types::{Address, U256};
+
+use crate::book::Match;
+use crate::order::{OrderId, OrderSide};
+use crate::util::{from_hex_de, from_hex_se};
+
+/// The number of trades each book keeps for subscribers to resume from
+pub const TAPE_LENGTH: usize = 1024;
+
+/// The number of trades buffered for each subscriber
+pub const TAPE_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Display, Error, PartialEq, Eq)]
+pub enum TapeError {
+    InvalidTradeId,
+}
+
+/// Represents the ID of a single trade, formatted as the sequence number of
+/// the journal entry that made it and its index among the trades made by
+/// that entry (e.g., `"42-0"`)
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct TradeId {
+    pub sequence: u64,
+    pub index: usize,
+}
+
+impl fmt::Display for TradeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.sequence, self.index)
+    }
+}
+
+impl FromStr for TradeId {
+    type Err = TapeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sequence, index): (&str, &str) = match s.split_once('-') {
+            Some(t) => t,
+            None => return Err(TapeError::InvalidTradeId),
+        };
+
+        match (sequence.parse(), index.parse()) {
+            (Ok(sequence), Ok(index)) => Ok(Self { sequence, index }),
+            _ => Err(TapeError::InvalidTradeId),
+        }
+    }
+}
+
+impl TryFrom<String> for TradeId {
+    type Error = TapeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TradeId> for String {
+    fn from(value: TradeId) -> Self {
+        value.to_string()
+    }
+}
+
+/// Represents a single trade as reported to subscribers
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TradeReport {
+    pub id: TradeId,
+    pub market: Address,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub price: U256,
+    #[serde(serialize_with = "from_hex_se", deserialize_with = "from_hex_de")]
+    pub quantity: U256,
+    pub aggressor: OrderSide, /* side of the taker */
+    pub maker: OrderId,
+    pub taker: OrderId,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TradeReport {
+    /// Constructs the report of `matched`, made at `timestamp`
+    pub fn new(id: TradeId, matched: &Match, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            id,
+            market: matched.taker.market,
+            price: matched.price,
+            quantity: matched.amount,
+            aggressor: matched.taker.side,
+            maker: matched.maker.id,
+            taker: matched.taker.id,
+            timestamp,
+        }
+    }
+}
+
+/// Represents the trades a resuming subscriber is sent before any trade made
+/// from now on
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Backlog {
+    pub missed: Option<TradeId>, /* resumed from, if trades since are lost */
+    pub trades: Vec<TradeReport>, /* every trade kept since, oldest first */
+}
+
+/// Represents the most recent trades of a book, oldest first, along with the
+/// channel they are published on
+#[derive(Debug)]
+pub struct Tape {
+    trades: VecDeque<TradeReport>,
+    forgotten: TradeId, /* trades up to and including this are not kept */
+    sender: Sender<TradeReport>,
+}
+
+impl Tape {
+    /// Constructs the tape of a book reflecting every journal entry up to and
+    /// including `sequence`, whose trades are therefore not kept
+    pub fn new(sequence: u64) -> Self {
+        Self {
+            trades: VecDeque::new(),
+            forgotten: TradeId {
+                sequence,
+                index: usize::MAX,
+            },
+            sender: broadcast::channel(TAPE_CAPACITY).0,
+        }
+    }
+
+    /// Publishes a report of each of `matches`, made by the journal entry
+    /// with sequence number `sequence` at `now`, forgetting the oldest
+    /// trades if need be
+    pub fn record(
+        &mut self,
+        sequence: u64,
+        matches: &[Match],
+        now: DateTime<Utc>,
+    ) {
+        for (index, matched) in matches.iter().enumerate() {
+            let report: TradeReport =
+                TradeReport::new(TradeId { sequence, index }, matched, now);
+
+            /* having no subscribers is not an error */
+            let _ = self.sender.send(report.clone());
+
+            self.trades.push_back(report);
+            if self.trades.len() > TAPE_LENGTH {
+                if let Some(oldest) = self.trades.pop_front() {
+                    self.forgotten = oldest.id;
+                }
+            }
+        }
+    }
+
+    /// Returns every trade kept that was made after the trade with ID
+    /// `last`, oldest first
+    pub fn since(&self, last: TradeId) -> Vec<TradeReport> {
+        self.trades
+            .iter()
+            .filter(|report| report.id > last)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns whether any trade made after the trade with ID `last` is no
+    /// longer kept (e.g., as it was made before the OME last restarted)
+    pub fn missed(&self, last: TradeId) -> bool {
+        last < self.forgotten
+    }
+
+    /// Returns the backlog of a subscriber resuming from the trade with ID
+    /// `last` (if given), along with a receiver of every trade made from now
+    /// on
+    pub fn subscribe(
+        &self,
+        last: Option<TradeId>,
+    ) -> (Backlog, Receiver<TradeReport>) {
+        let backlog: Backlog = match last {
+            Some(last) => Backlog {
+                missed: Some(last).filter(|last| self.missed(*last)),
+                trades: self.since(last),
+            },
+            None => Backlog::default(),
+        };
+
+        (backlog, self.sender.subscribe())
+    }
+}
+
+/// Converts `report` into a server-sent event, identified by its trade ID
+fn event(report: &TradeReport) -> Result<Event, serde_json::Error> {
+    Event::default()
+        .id(report.id.to_string())
+        .event("trade")
+        .json_data(report)
+}
+
+/// Converts the notice that trades made after the trade with ID `last` were
+/// missed into a server-sent event
+///
+/// The event carries no ID, so that the subscriber still resumes from `last`
+/// should it reconnect before receiving any trade.
+fn gap(last: TradeId) -> Result<Event, serde_json::Error> {
+    Event::default()
+        .event("gap")
+        .json_data(serde_json::json!({ "last_event_id": last }))
+}
+
+/// Returns the server-sent events reporting each trade of `backlog`,
+/// followed by each trade received by `trades`
+///
+/// Should trades have been missed since the subscriber last received one,
+/// the stream starts with a `gap` event, so that the subscriber knows to
+/// resynchronise. The stream ends should the subscriber fall so far behind
+/// that trades are lost, so that the subscriber reconnects and resumes from
+/// the last trade it received.
+pub fn events(
+    backlog: Backlog,
+    trades: Receiver<TradeReport>,
+) -> impl Stream<Item = Result<Event, serde_json::Error>> {
+    let live = stream::unfold(trades, |mut trades| async move {
+        match trades.recv().await {
+            Ok(report) => Some((report, trades)),
+            Err(RecvError::Lagged(missed)) => {
+                info!("Trade subscriber missed {} trades", missed);
+                None
+            }
+            Err(RecvError::Closed) => None, /* the book has gone */
+        }
+    });
+
+    stream::iter(backlog.missed.map(gap)).chain(
+        stream::iter(backlog.trades)
+            .chain(live)
+            .map(|report| event(&report)),
+    )
+}
@@ -1,9 +1,70 @@
+/// Helpers shared by the tests of markets run by their own tasks (see
+/// `Markets`)
+#[cfg(test)]
+mod market_helpers {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+    use web3::types::H256;
+
+    use crate::event::{self, EventSender};
+    use crate::executioner::MockExecutioner;
+    use crate::journal::{Journal, JournalEvent};
+    use crate::market::{Market, Markets};
+    use crate::order::Order;
+    use crate::outbox::Outbox;
+    use crate::OmeState;
+
+    /// Returns a fresh path for a journal (its outbox is kept beside it, see
+    /// `spawn_markets_of`)
+    pub fn journal_path() -> PathBuf {
+        std::env::temp_dir().join(format!("ometest-{:x}", H256::random()))
+    }
+
+    /// Spawns a task for each book of `state`, journalling to `path` (and
+    /// keeping the outbox beside it) and publishing order events to `events`
+    pub fn spawn_markets_of(
+        state: OmeState,
+        path: &Path,
+        events: EventSender,
+    ) -> Markets {
+        let journal: Journal = Journal::open(path, state.sequence()).unwrap();
+        let outbox: Outbox =
+            Outbox::open(&path.with_extension("outbox")).unwrap();
+        Markets::new(
+            state,
+            Arc::new(Mutex::new(journal)),
+            Arc::new(Mutex::new(outbox)),
+            events,
+            Arc::new(MockExecutioner::new()),
+        )
+    }
+
+    /// Spawns markets without any books yet, journalling to `path`
+    pub fn spawn_markets(path: &Path) -> Markets {
+        spawn_markets_of(OmeState::new(), path, event::channel())
+    }
+
+    /// Submits `order` to the market of `handle`, which must accept it
+    pub async fn submit(handle: &Market, order: &Order) {
+        handle
+            .apply(JournalEvent::Submit {
+                market: handle.market(),
+                order: order.clone(),
+            })
+            .await
+            .unwrap();
+    }
+}
+
 #[cfg(test)]
 mod order_tests {
-    use chrono::Utc;
+    use chrono::{Duration, Utc};
+    use serde_json::Value;
     use web3::types::{Address, U256};
 
-    use crate::order::{Order, OrderSide};
+    use crate::order::{ExternalOrder, Order, OrderSide};
 
     #[test]
     pub fn basic_order_info() {
@@ -18,7 +79,7 @@ mod order_tests {
             OrderSide::Bid,
             price,
             amount,
-            Utc::now(),
+            Utc::now() + Duration::days(1),
             Utc::now(),
             vec![],
         );
@@ -30,13 +91,39 @@ mod order_tests {
         assert_eq!(order.quantity, amount);
         assert!(order.signed_data.is_empty());
     }
+
+    #[test]
+    pub fn public_orders_only_hide_the_reserve() {
+        let mut order = Order::new(
+            Address::from_low_u64_be(1),
+            Address::zero(),
+            OrderSide::Ask,
+            U256::from(5),
+            U256::from(10),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![0xaa; 65],
+        );
+        order.display_quantity = Some(U256::from(3));
+
+        let public: Value =
+            serde_json::to_value(ExternalOrder::from(order.public())).unwrap();
+
+        assert_eq!(public["amount"], "3");
+        assert_eq!(public["amount_left"], "3");
+        assert_eq!(public["id"], format!("{:#x}", order.id));
+        assert_eq!(public["signed_data"], "0x".to_string() + &"aa".repeat(65));
+    }
 }
 
 #[cfg(test)]
 mod state_tests {
+    use chrono::{Duration, Utc};
+    use ethereum_types::U256;
     use web3::types::Address;
 
     use crate::book::Book;
+    use crate::order::{Order, OrderSide};
     use crate::OmeState;
 
     #[test]
@@ -75,4 +162,3239 @@ mod state_tests {
         state.remove_book(market);
         assert!(state.books().is_empty());
     }
+
+    #[test]
+    pub fn mass_cancel_spans_markets() {
+        let mut state = OmeState::new();
+        let trader: Address = Address::repeat_byte(0xaa);
+
+        for market in [Address::zero(), Address::repeat_byte(1)].iter() {
+            let mut book = Book::new(*market);
+            for (owner, price) in [(trader, 90), (Address::zero(), 80)].iter() {
+                let order: Order = Order::new(
+                    *owner,
+                    *market,
+                    OrderSide::Bid,
+                    U256::from(*price),
+                    U256::from(10),
+                    Utc::now() + Duration::days(1),
+                    Utc::now(),
+                    vec![],
+                );
+                book.bids.entry(order.price).or_default().push_back(order);
+            }
+            book.reindex();
+            state.add_book(book);
+        }
+
+        /* scoped to a single market */
+        let cancelled: Vec<Order> =
+            state.mass_cancel(Some(Address::zero()), Some(trader), None);
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].market, Address::zero());
+
+        /* across every market */
+        let cancelled: Vec<Order> = state.mass_cancel(None, Some(trader), None);
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].market, Address::repeat_byte(1));
+
+        for book in state.books().values() {
+            assert_eq!(book.depth(), (1, 0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use chrono::{Duration, Utc};
+    use web3::types::{Address, H256};
+
+    use crate::book::Book;
+    use crate::journal::{Journal, JournalEntry, JournalEvent};
+    use crate::order::{Order, OrderSide};
+    use crate::OmeState;
+
+    fn journal_path() -> PathBuf {
+        std::env::temp_dir().join(format!("omejournal-{:x}", H256::random()))
+    }
+
+    fn order(trader: u64, side: OrderSide, price: u64, qty: u64) -> Order {
+        Order::new(
+            Address::from_low_u64_be(trader),
+            Address::zero(),
+            side,
+            price.into(),
+            qty.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        )
+    }
+
+    #[test]
+    pub fn append_assigns_increasing_sequence_numbers() {
+        let path: PathBuf = journal_path();
+        let mut journal: Journal = Journal::open(&path, 0).unwrap();
+
+        let first: u64 = journal
+            .append(JournalEvent::AddBook {
+                market: Address::zero(),
+            })
+            .unwrap();
+        let second: u64 = journal
+            .append(JournalEvent::RemoveBook {
+                market: Address::zero(),
+            })
+            .unwrap();
+
+        assert_eq!((first, second), (1, 2));
+
+        /* reopening resumes from the last entry on disk */
+        let mut journal: Journal = Journal::open(&path, 0).unwrap();
+        assert_eq!(journal.sequence(), 2);
+        assert_eq!(
+            journal
+                .append(JournalEvent::AddBook {
+                    market: Address::zero()
+                })
+                .unwrap(),
+            3
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn torn_final_entry_is_discarded() {
+        let path: PathBuf = journal_path();
+        let mut journal: Journal = Journal::open(&path, 0).unwrap();
+        journal
+            .append(JournalEvent::AddBook {
+                market: Address::zero(),
+            })
+            .unwrap();
+        drop(journal);
+
+        /* simulate a crash midway through writing an entry */
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"sequence\":2,\"times").unwrap();
+        drop(file);
+
+        assert_eq!(Journal::entries(&path).unwrap().len(), 1);
+
+        let mut journal: Journal = Journal::open(&path, 0).unwrap();
+        journal
+            .append(JournalEvent::RemoveBook {
+                market: Address::zero(),
+            })
+            .unwrap();
+
+        let entries: Vec<JournalEntry> = Journal::entries(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].sequence, 2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn replay_reconstructs_state() {
+        let path: PathBuf = journal_path();
+        let mut journal: Journal = Journal::open(&path, 0).unwrap();
+        let mut expected: OmeState = OmeState::new();
+        let market: Address = Address::zero();
+
+        expected
+            .record(&mut journal, JournalEvent::AddBook { market })
+            .unwrap();
+        expected.add_book(Book::new(market));
+
+        let orders: Vec<Order> = vec![
+            order(1, OrderSide::Ask, 100, 10),
+            order(2, OrderSide::Ask, 100, 5),
+            order(3, OrderSide::Bid, 98, 7),
+            order(4, OrderSide::Bid, 101, 12),
+        ];
+
+        for order in orders.iter() {
+            expected
+                .record(
+                    &mut journal,
+                    JournalEvent::Submit {
+                        market,
+                        order: order.clone(),
+                    },
+                )
+                .unwrap();
+            expected
+                .book_mut(market)
+                .unwrap()
+                .replay(order.clone(), Utc::now())
+                .await
+                .unwrap();
+        }
+
+        expected
+            .record(
+                &mut journal,
+                JournalEvent::Cancel {
+                    market,
+                    order_id: orders[2].id,
+                },
+            )
+            .unwrap();
+        expected
+            .book_mut(market)
+            .unwrap()
+            .cancel(orders[2].id)
+            .unwrap();
+
+        let mut actual: OmeState = OmeState::new();
+        actual.replay(Journal::entries(&path).unwrap()).await;
+
+        assert_eq!(actual, expected);
+        assert_eq!(actual.sequence(), 6);
+
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use chrono::{Duration, Utc};
+    use serde_json::{json, Value};
+    use web3::types::{Address, H256, U256};
+
+    use crate::book::Book;
+    use crate::journal::{Journal, JournalEvent};
+    use crate::order::{Order, OrderSide};
+    use crate::registry::Termination;
+    use crate::snapshot::{self, Snapshot, SnapshotError, SNAPSHOT_VERSION};
+    use crate::OmeState;
+
+    fn books_in(path: &Path) -> usize {
+        OmeState::from_dumpfile(path).unwrap().books().len()
+    }
+
+    fn dump_path() -> PathBuf {
+        std::env::temp_dir().join(format!("omedump-{:x}", H256::random()))
+    }
+
+    #[test]
+    pub fn write_rotates_generations() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("omedump-{:x}", H256::random()));
+        fs::create_dir(&dir).unwrap();
+        let path: PathBuf = dir.join("omedump.json");
+        let mut state: OmeState = OmeState::new();
+
+        for i in 0..4 {
+            state.add_book(Book::new(Address::from_low_u64_be(i)));
+            snapshot::write(&state, &path, 2).unwrap();
+        }
+
+        /* current dump plus two previous generations, most recent first */
+        assert_eq!(books_in(&path), 4);
+        assert_eq!(books_in(&snapshot::generation_path(&path, 1)), 3);
+        assert_eq!(books_in(&snapshot::generation_path(&path, 2)), 2);
+        assert!(!snapshot::generation_path(&path, 3).exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn latest_readable_generation_is_loaded() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("omedump-{:x}", H256::random()));
+        fs::create_dir(&dir).unwrap();
+        let path: PathBuf = dir.join("omedump.json");
+        let mut state: OmeState = OmeState::new();
+
+        assert_eq!(snapshot::read_latest(&path).unwrap(), None);
+
+        for i in 0..3 {
+            state.add_book(Book::new(Address::from_low_u64_be(i)));
+            snapshot::write(&state, &path, 2).unwrap();
+        }
+
+        /* a damaged snapshot gives way to the one before it */
+        fs::write(&path, "{").unwrap();
+        assert_eq!(
+            snapshot::read_latest(&path).unwrap().unwrap().books().len(),
+            2
+        );
+
+        /* as does a missing one */
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            snapshot::read_latest(&path).unwrap().unwrap().books().len(),
+            2
+        );
+
+        /* but should none be readable, that is an error */
+        for generation in 1..3 {
+            fs::write(snapshot::generation_path(&path, generation), "{")
+                .unwrap();
+        }
+        assert!(snapshot::read_latest(&path).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn compaction_keeps_journal_tail() {
+        let path: PathBuf = std::env::temp_dir()
+            .join(format!("omejournal-{:x}", H256::random()));
+        let mut journal: Journal = Journal::open(&path, 0).unwrap();
+
+        for i in 0..5 {
+            journal
+                .append(JournalEvent::AddBook {
+                    market: Address::from_low_u64_be(i),
+                })
+                .unwrap();
+        }
+
+        journal.compact(3).unwrap();
+        journal
+            .append(JournalEvent::AddBook {
+                market: Address::zero(),
+            })
+            .unwrap();
+
+        let sequences: Vec<u64> = Journal::entries(&path)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.sequence)
+            .collect();
+        assert_eq!(sequences, vec![4, 5, 6]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn snapshot_round_trip_is_lossless() {
+        let path: PathBuf = dump_path();
+        let mut state: OmeState = OmeState::new();
+        let mut book: Book = Book::new(Address::zero());
+        book.ltp = U256::MAX;
+        book.spread = U256::from(u128::MAX) + 1;
+        state.add_book(book);
+
+        snapshot::write(&state, &path, 0).unwrap();
+
+        assert_eq!(OmeState::from_dumpfile(&path).unwrap(), state);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn snapshot_restores_order_index() {
+        let path: PathBuf = dump_path();
+        let market: Address = Address::zero();
+        let mut book: Book = Book::new(market);
+        let order: Order = Order::new(
+            Address::repeat_byte(1),
+            market,
+            OrderSide::Bid,
+            U256::from(100),
+            U256::from(10),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        );
+        book.bids
+            .entry(order.price)
+            .or_default()
+            .push_back(order.clone());
+        book.reindex();
+        let mut state: OmeState = OmeState::new();
+        state.add_book(book);
+
+        snapshot::write(&state, &path, 0).unwrap();
+
+        let restored: OmeState = OmeState::from_dumpfile(&path).unwrap();
+        let book: &Book = restored.book(market).unwrap();
+        assert_eq!(book.order(order.id), Some(&order));
+        assert_eq!(book.orders_of(order.trader), vec![&order]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn snapshot_restores_terminal_orders() {
+        let path: PathBuf = dump_path();
+        let market: Address = Address::zero();
+        let mut book: Book = Book::new(market);
+        let (filled, cancelled): (H256, H256) =
+            (H256::random(), H256::random());
+        book.terminal.record(filled, Termination::Filled);
+        book.terminal.record(cancelled, Termination::Cancelled);
+        let mut state: OmeState = OmeState::new();
+        state.add_book(book);
+
+        snapshot::write(&state, &path, 0).unwrap();
+
+        let restored: OmeState = OmeState::from_dumpfile(&path).unwrap();
+        let book: &Book = restored.book(market).unwrap();
+        assert_eq!(book.termination(filled), Some(Termination::Filled));
+        assert_eq!(book.termination(cancelled), Some(Termination::Cancelled));
+        assert!(book.is_duplicate(cancelled));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn unversioned_snapshot_is_migrated() {
+        let path: PathBuf = dump_path();
+        let market: Address = Address::zero();
+        let legacy: Value = json!({
+            "books": {
+                format!("{:?}", market): {
+                    "market": market,
+                    "bids": {},
+                    "asks": {},
+                    "LTP": 1150000000000000000u64,
+                    "depth": [0, 0],
+                    "crossed": false,
+                    "spread": 0
+                }
+            }
+        });
+        fs::write(&path, legacy.to_string()).unwrap();
+
+        let state: OmeState = OmeState::from_dumpfile(&path).unwrap();
+
+        assert_eq!(
+            state.book(market).unwrap().ltp(),
+            U256::from(1150000000000000000u64)
+        );
+        assert_eq!(state.sequence(), 0);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn unversioned_snapshot_keeps_every_digit() {
+        let path: PathBuf = dump_path();
+        let market: Address = Address::zero();
+        let legacy: String = format!(
+            r#"{{"books": {{"{:?}": {{"market": "{:?}", "bids": {{}},
+                "asks": {{}}, "LTP": 123456789012345678901234,
+                "depth": [0, 0], "crossed": false,
+                "spread": 18446744073709551616}}}}}}"#,
+            market, market
+        );
+        fs::write(&path, legacy).unwrap();
+
+        let state: OmeState = OmeState::from_dumpfile(&path).unwrap();
+        let book: &Book = state.book(market).unwrap();
+
+        assert_eq!(
+            book.ltp(),
+            U256::from_dec_str("123456789012345678901234").unwrap()
+        );
+        assert_eq!(book.spread, U256::from(u64::MAX) + 1);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn tampered_snapshot_is_rejected() {
+        let path: PathBuf = dump_path();
+        let mut snapshot: Snapshot = Snapshot::new(&OmeState::new()).unwrap();
+        snapshot.state["sequence"] = json!(42);
+        fs::write(&path, serde_json::to_string(&snapshot).unwrap()).unwrap();
+
+        assert!(matches!(
+            OmeState::from_dumpfile(&path),
+            Err(SnapshotError::ChecksumMismatch)
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn future_snapshot_version_is_rejected() {
+        let path: PathBuf = dump_path();
+        let mut snapshot: Snapshot = Snapshot::new(&OmeState::new()).unwrap();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        fs::write(&path, serde_json::to_string(&snapshot).unwrap()).unwrap();
+
+        assert!(matches!(
+            OmeState::from_dumpfile(&path),
+            Err(SnapshotError::UnsupportedVersion)
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn truncated_snapshot_is_an_error() {
+        let path: PathBuf = dump_path();
+        snapshot::write(&OmeState::new(), &path, 0).unwrap();
+        let data: String = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        assert!(OmeState::from_dumpfile(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod expiry_tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use chrono::{DateTime, Duration, Utc};
+    use tokio::sync::broadcast::Receiver;
+    use web3::types::Address;
+
+    use super::market_helpers::{journal_path, spawn_markets_of};
+    use crate::book::Book;
+    use crate::event::{self, EventSender, OrderEvent, OrderEventKind};
+    use crate::expiry;
+    use crate::market::Markets;
+    use crate::order::{Order, OrderSide, TimeInForce};
+    use crate::OmeState;
+
+    fn order(
+        market: Address,
+        side: OrderSide,
+        price: u64,
+        time_in_force: TimeInForce,
+        expiration: DateTime<Utc>,
+    ) -> Order {
+        let mut order: Order = Order::new(
+            Address::random(),
+            market,
+            side,
+            price.into(),
+            10.into(),
+            expiration,
+            Utc::now(),
+            vec![],
+        );
+        order.time_in_force = time_in_force;
+        order
+    }
+
+    #[tokio::test]
+    pub async fn sweep_removes_expired_orders_from_every_book() {
+        let now: DateTime<Utc> = Utc::now();
+        let markets: Vec<Address> =
+            vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)];
+        let mut state: OmeState = OmeState::new();
+        let mut expired: Vec<Order> = vec![];
+        let mut live: Vec<Order> = vec![];
+
+        for market in markets.iter() {
+            let mut book: Book = Book::new(*market);
+            let orders: Vec<(Order, bool)> = vec![
+                (
+                    order(
+                        *market,
+                        OrderSide::Bid,
+                        90,
+                        TimeInForce::GoodTilDate,
+                        now - Duration::seconds(1),
+                    ),
+                    true,
+                ),
+                (
+                    order(
+                        *market,
+                        OrderSide::Ask,
+                        110,
+                        TimeInForce::GoodTilDate,
+                        now + Duration::hours(1),
+                    ),
+                    false,
+                ),
+                (
+                    order(
+                        *market,
+                        OrderSide::Ask,
+                        120,
+                        TimeInForce::GoodTilCancelled,
+                        now - Duration::seconds(1),
+                    ),
+                    true,
+                ),
+            ];
+
+            /* place orders directly, bypassing the expiry check on submit */
+            for (order, is_expired) in orders {
+                let side = match order.side {
+                    OrderSide::Bid => &mut book.bids,
+                    OrderSide::Ask => &mut book.asks,
+                };
+                side.entry(order.price)
+                    .or_default()
+                    .push_back(order.clone());
+
+                if is_expired {
+                    expired.push(order);
+                } else {
+                    live.push(order);
+                }
+            }
+
+            book.reindex();
+            state.add_book(book);
+        }
+
+        let path: PathBuf = journal_path();
+        let events: EventSender = event::channel();
+        let mut subscriber: Receiver<OrderEvent> = events.subscribe();
+        let markets: Markets = spawn_markets_of(state, &path, events);
+
+        let mut actual: Vec<Order> = expiry::sweep(&markets, now).await;
+        actual.sort_by_key(|order| order.id);
+        expired.sort_by_key(|order| order.id);
+        assert_eq!(actual, expired);
+
+        /* exactly one event per expired order */
+        let mut published: Vec<OrderEvent> = vec![];
+        while let Ok(t) = subscriber.try_recv() {
+            published.push(t);
+        }
+        published.sort_by_key(|event| event.order_id);
+        assert_eq!(
+            published,
+            expired
+                .iter()
+                .map(|order| {
+                    OrderEvent::new(OrderEventKind::Expired, order, now)
+                })
+                .collect::<Vec<OrderEvent>>()
+        );
+
+        let ome_state: OmeState = markets.snapshot().await.unwrap();
+        for order in expired.iter() {
+            assert!(ome_state
+                .book(order.market)
+                .unwrap()
+                .order(order.id)
+                .is_none());
+        }
+        for order in live.iter() {
+            assert!(ome_state
+                .book(order.market)
+                .unwrap()
+                .order(order.id)
+                .is_some());
+        }
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn sweep_without_expired_orders_publishes_nothing() {
+        let market: Address = Address::zero();
+        let mut book: Book = Book::new(market);
+        book.asks.entry(110.into()).or_default().push_back(order(
+            market,
+            OrderSide::Ask,
+            110,
+            TimeInForce::GoodTilDate,
+            Utc::now() + Duration::hours(1),
+        ));
+        book.reindex();
+        let expected_book: Book = book.clone();
+
+        let mut state: OmeState = OmeState::new();
+        state.add_book(book);
+        let path: PathBuf = journal_path();
+        let events: EventSender = event::channel();
+        let mut subscriber: Receiver<OrderEvent> = events.subscribe();
+        let markets: Markets = spawn_markets_of(state, &path, events);
+
+        assert!(expiry::sweep(&markets, Utc::now()).await.is_empty());
+        assert!(subscriber.try_recv().is_err());
+        assert_eq!(
+            markets.snapshot().await.unwrap().book(market),
+            Some(&expected_book)
+        );
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod heartbeat_tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration, Utc};
+    use tokio::sync::Mutex;
+    use web3::types::Address;
+
+    use super::market_helpers::{journal_path, spawn_markets_of};
+    use crate::book::Book;
+    use crate::event;
+    use crate::heartbeat::{self, Heartbeats};
+    use crate::journal::{Journal, JournalEntry, JournalEvent};
+    use crate::market::Markets;
+    use crate::order::{Order, OrderSide};
+    use crate::OmeState;
+
+    #[test]
+    pub fn only_overdue_heartbeats_lapse() {
+        let now: DateTime<Utc> = Utc::now();
+        let overdue: Address = Address::from_low_u64_be(1);
+        let punctual: Address = Address::from_low_u64_be(2);
+        let mut heartbeats: Heartbeats = Heartbeats::new();
+
+        heartbeats.beat(overdue, Duration::seconds(5), now);
+        heartbeats.beat(punctual, Duration::seconds(5), now);
+        heartbeats.beat(
+            punctual,
+            Duration::seconds(5),
+            now + Duration::seconds(3),
+        );
+
+        let later: DateTime<Utc> = now + Duration::seconds(6);
+        assert_eq!(heartbeats.lapsed(later), vec![overdue]);
+        assert_eq!(heartbeats.deadline(overdue), None);
+        assert_eq!(
+            heartbeats.deadline(punctual),
+            Some(now + Duration::seconds(8))
+        );
+
+        /* lapsed traders must opt in again */
+        assert_eq!(heartbeats.lapsed(now + Duration::hours(1)), vec![punctual]);
+        assert!(!heartbeats.disarm(punctual));
+    }
+
+    #[tokio::test]
+    pub async fn lapsed_heartbeat_cancels_orders_in_every_book() {
+        let now: DateTime<Utc> = Utc::now();
+        let trader: Address = Address::from_low_u64_be(1);
+        let other: Address = Address::from_low_u64_be(2);
+        let mut state: OmeState = OmeState::new();
+        let markets: Vec<Address> =
+            vec![Address::zero(), Address::repeat_byte(1)];
+
+        for market in markets.iter() {
+            let mut book: Book = Book::new(*market);
+            for (owner, side, price) in [
+                (trader, OrderSide::Bid, 90),
+                (trader, OrderSide::Ask, 110),
+                (other, OrderSide::Bid, 80),
+            ]
+            .iter()
+            {
+                let order: Order = Order::new(
+                    *owner,
+                    *market,
+                    *side,
+                    (*price).into(),
+                    10.into(),
+                    now,
+                    now,
+                    vec![],
+                );
+                match side {
+                    OrderSide::Bid => &mut book.bids,
+                    OrderSide::Ask => &mut book.asks,
+                }
+                .entry(order.price)
+                .or_default()
+                .push_back(order);
+            }
+            book.reindex();
+            state.add_book(book);
+        }
+
+        let path: PathBuf = journal_path();
+        let handles: Markets = spawn_markets_of(state, &path, event::channel());
+        let heartbeats: Arc<Mutex<Heartbeats>> =
+            Arc::new(Mutex::new(Heartbeats::new()));
+        heartbeats
+            .lock()
+            .await
+            .beat(trader, Duration::seconds(5), now);
+
+        /* nothing happens before the deadline */
+        assert!(heartbeat::sweep(&heartbeats, &handles, now)
+            .await
+            .is_empty());
+
+        let cancelled: Vec<Order> =
+            heartbeat::sweep(&heartbeats, &handles, now + Duration::seconds(5))
+                .await;
+        assert_eq!(cancelled.len(), 4);
+        assert!(cancelled.iter().all(|order| order.trader == trader));
+
+        for book in handles.snapshot().await.unwrap().books().values() {
+            assert_eq!(book.depth(), (1, 0));
+        }
+
+        /* each book journals its cancellation so that it survives a restart */
+        let mut journalled: Vec<Address> = Journal::entries(&path)
+            .unwrap()
+            .into_iter()
+            .map(|entry: JournalEntry| match entry.event {
+                JournalEvent::MassCancel {
+                    market: Some(market),
+                    trader: Some(t),
+                    side: None,
+                } if t == trader => market,
+                event => panic!("Unexpected journal event {:?}", event),
+            })
+            .collect();
+        journalled.sort();
+        assert_eq!(journalled, markets);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod market_tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::mpsc;
+
+    use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+    use warp::http::StatusCode;
+    use warp::Filter;
+    use web3::types::{Address, H256};
+
+    use super::market_helpers::{journal_path, spawn_markets, submit};
+    use crate::auth::{self, Action, AuthError};
+    use crate::book::{Book, Match};
+    use crate::handler;
+    use crate::journal::{Journal, JournalEntry, JournalEvent};
+    use crate::market::{Market, MarketError, Markets, Outcome};
+    use crate::order::{Order, OrderSide, SelfTradePrevention};
+    use crate::OmeState;
+
+    fn order(market: Address, side: OrderSide, price: u64) -> Order {
+        Order::new(
+            Address::random(),
+            market,
+            side,
+            price.into(),
+            10.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn busy_market_does_not_block_others() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let busy: Address = Address::from_low_u64_be(1);
+        let idle: Address = Address::from_low_u64_be(2);
+        markets.add(busy).await.unwrap();
+        markets.add(idle).await.unwrap();
+
+        /* occupy the task of one market until told otherwise */
+        let (release, released) = mpsc::channel::<()>();
+        let busy_market: Market = markets.get(busy).await.unwrap();
+        let blocked = tokio::spawn(async move {
+            busy_market
+                .inspect(move |_book| released.recv().unwrap())
+                .await
+        });
+
+        let bid: Order = order(idle, OrderSide::Bid, 100);
+        let idle_market: Market = markets.get(idle).await.unwrap();
+        let outcome: Outcome = idle_market
+            .apply(JournalEvent::Submit {
+                market: idle,
+                order: bid.clone(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            outcome,
+            Outcome::Executed {
+                resting: Some(_),
+                ..
+            }
+        ));
+        assert_eq!(
+            idle_market.inspect(move |book| book.depth()).await,
+            Ok((1, 0))
+        );
+
+        release.send(()).unwrap();
+        blocked.await.unwrap().unwrap();
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn rejected_commands_are_not_journalled() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        assert_eq!(markets.add(market).await, Err(MarketError::MarketExists));
+
+        let ask: Order = order(market, OrderSide::Ask, 100);
+        let handle: Market = markets.get(market).await.unwrap();
+        submit(&handle, &ask).await;
+        assert_eq!(
+            handle
+                .apply(JournalEvent::Cancel {
+                    market,
+                    order_id: H256::random(),
+                })
+                .await,
+            Err(MarketError::OrderNotFound)
+        );
+
+        let entries: Vec<JournalEntry> = Journal::entries(&path).unwrap();
+        assert_eq!(
+            entries
+                .into_iter()
+                .map(|entry| entry.event)
+                .collect::<Vec<JournalEvent>>(),
+            vec![
+                JournalEvent::AddBook { market },
+                JournalEvent::Submit { market, order: ask },
+            ]
+        );
+
+        /* the journal alone reproduces the state of every market */
+        let mut replayed: OmeState = OmeState::new();
+        replayed.replay(Journal::entries(&path).unwrap()).await;
+        assert_eq!(replayed, markets.snapshot().await.unwrap());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn duplicate_orders_are_rejected() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let ask: Order = order(market, OrderSide::Ask, 100);
+        let bid: Order = order(market, OrderSide::Bid, 100);
+        for order in [&ask, &bid].iter() {
+            submit(&handle, order).await;
+        }
+
+        /* both orders were filled in full, so neither may be filled again */
+        for order in [&ask, &bid].iter() {
+            assert_eq!(
+                handle
+                    .apply(JournalEvent::Submit {
+                        market,
+                        order: (*order).clone(),
+                    })
+                    .await,
+                Err(MarketError::OrderFilled)
+            );
+        }
+        assert_eq!(
+            markets.outbox().lock().await.deliveries_in(market).len(),
+            1
+        );
+        assert_eq!(Journal::entries(&path).unwrap().len(), 3);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn replay_skips_entries_already_in_book() {
+        let ahead: Address = Address::from_low_u64_be(1);
+        let behind: Address = Address::from_low_u64_be(2);
+        let first: Order = order(ahead, OrderSide::Bid, 100);
+        let second: Order = order(behind, OrderSide::Ask, 110);
+
+        /* copied after applying entry 3, while the state is as of entry 2 */
+        let mut ahead_book: Book = Book::new(ahead);
+        ahead_book.replay(first.clone(), Utc::now()).await.unwrap();
+        let mut state: OmeState = OmeState::from_books(
+            vec![(ahead_book.clone(), 3), (Book::new(behind), 2)],
+            2,
+        );
+
+        let entries: Vec<JournalEntry> = vec![
+            JournalEntry {
+                sequence: 3,
+                timestamp: Utc::now(),
+                event: JournalEvent::Submit {
+                    market: ahead,
+                    order: first,
+                },
+            },
+            JournalEntry {
+                sequence: 4,
+                timestamp: Utc::now(),
+                event: JournalEvent::Submit {
+                    market: behind,
+                    order: second.clone(),
+                },
+            },
+        ];
+        state.replay(entries).await;
+
+        assert_eq!(state.sequence(), 4);
+        assert_eq!(state.book(ahead), Some(&ahead_book));
+        assert_eq!(state.book(behind).unwrap().depth(), (0, 1));
+        assert!(state.book(behind).unwrap().order(second.id).is_some());
+    }
+
+    #[tokio::test]
+    pub async fn amending_an_expired_order_is_replayed_alike() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let ask: Order = order(market, OrderSide::Ask, 100);
+        let bid: Order = Order::new(
+            Address::random(),
+            market,
+            OrderSide::Bid,
+            90.into(),
+            10.into(),
+            Utc::now() + Duration::seconds(1),
+            Utc::now(),
+            vec![],
+        );
+        for order in [&ask, &bid] {
+            submit(&handle, order).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+
+        /* the bid expires just before it would be amended to cross */
+        let amended: Order = Order::new(
+            bid.trader,
+            market,
+            OrderSide::Bid,
+            100.into(),
+            10.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![1],
+        );
+        assert_eq!(
+            handle
+                .apply(JournalEvent::Amend {
+                    market,
+                    order_id: bid.id,
+                    order: amended,
+                })
+                .await,
+            Err(MarketError::OrderNotFound)
+        );
+
+        let mut replayed: OmeState = OmeState::new();
+        let matches: Vec<Match> =
+            replayed.replay(Journal::entries(&path).unwrap()).await;
+        assert!(matches.is_empty());
+        assert_eq!(replayed, markets.snapshot().await.unwrap());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn book_configuration_only_changes_fields_given() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::from_low_u64_be(1);
+        let separator: H256 = H256::repeat_byte(1);
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        handle
+            .apply(JournalEvent::ConfigureBook {
+                market,
+                self_trade_prevention: Some(Some(
+                    SelfTradePrevention::CancelOldest,
+                )),
+                domain_separator: Some(Some(separator)),
+            })
+            .await
+            .unwrap();
+
+        /* resetting the mode leaves the separator be */
+        handle
+            .apply(JournalEvent::ConfigureBook {
+                market,
+                self_trade_prevention: Some(None),
+                domain_separator: None,
+            })
+            .await
+            .unwrap();
+
+        /* and the separator may not be unset over the API */
+        let route_markets: Markets = markets.clone();
+        let route = warp::path!("book" / Address)
+            .and(warp::put())
+            .and(warp::body::json())
+            .and(warp::any().map(|| None))
+            .and(warp::any().map(move || route_markets.clone()))
+            .and_then(handler::update_book_handler);
+        let response = warp::test::request()
+            .method("PUT")
+            .path(&format!("/book/{:x}", market))
+            .json(&serde_json::json!({ "domain_separator": null }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let state: OmeState = markets.snapshot().await.unwrap();
+        assert_eq!(state.books()[&market].self_trade_prevention, None);
+        assert_eq!(state.books()[&market].domain_separator, Some(separator));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn only_the_operator_sets_domain_separators() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::from_low_u64_be(1);
+        let separator: H256 = H256::repeat_byte(1);
+        markets.add(market).await.unwrap();
+
+        /* signed with private key 0x4c0883a6...3f362318 at 1623977157 */
+        let operator: Address =
+            Address::from_str("2c7536e3605d9c16a7a3d7b1898e529396a65c23")
+                .unwrap();
+        let signature: &str = "533d8733f26b247e7b80783e1f08aaf16cecbcd52553353d557a5a5336806c6110125d919847d27fccb14bb5344a64ed2d06c8cfe095748022a6953b257b3bec1c";
+        let signed: DateTime<Utc> = DateTime::from_utc(
+            NaiveDateTime::from_timestamp(1623977157, 0),
+            Utc,
+        );
+        let configure: Action = Action::Configure {
+            market,
+            domain_separator: separator,
+        };
+        assert_eq!(
+            auth::authenticate(
+                configure,
+                operator,
+                signed,
+                &hex::decode(signature).unwrap(),
+                signed
+            ),
+            Ok(())
+        );
+
+        /* the signature only sets the separator it names */
+        assert_eq!(
+            auth::authenticate(
+                Action::Configure {
+                    market,
+                    domain_separator: H256::repeat_byte(2),
+                },
+                operator,
+                signed,
+                &hex::decode(signature).unwrap(),
+                signed
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+
+        for (configured, request, status) in [
+            /* without an operator, nobody may set it */
+            (
+                None,
+                serde_json::json!({ "domain_separator": separator }),
+                StatusCode::FORBIDDEN,
+            ),
+            /* and with one, not without their signature */
+            (
+                Some(operator),
+                serde_json::json!({ "domain_separator": separator }),
+                StatusCode::UNAUTHORIZED,
+            ),
+            /* nor with a signature long since expired */
+            (
+                Some(operator),
+                serde_json::json!({
+                    "domain_separator": separator,
+                    "timestamp": 1623977157,
+                    "signature": format!("0x{}", signature),
+                }),
+                StatusCode::UNAUTHORIZED,
+            ),
+        ] {
+            let route_markets: Markets = markets.clone();
+            let route = warp::path!("book" / Address)
+                .and(warp::put())
+                .and(warp::body::json())
+                .and(warp::any().map(move || configured))
+                .and(warp::any().map(move || route_markets.clone()))
+                .and_then(handler::update_book_handler);
+            let response = warp::test::request()
+                .method("PUT")
+                .path(&format!("/book/{:x}", market))
+                .json(&request)
+                .reply(&route)
+                .await;
+            assert_eq!(response.status(), status);
+        }
+
+        let state: OmeState = markets.snapshot().await.unwrap();
+        assert_eq!(state.books()[&market].domain_separator, None);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn markets_without_a_domain_separator_refuse_orders() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::from_low_u64_be(1);
+        markets.add(market).await.unwrap();
+
+        let route_markets: Markets = markets.clone();
+        let route = warp::path!("book" / Address / "order")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(warp::any().map(move || route_markets.clone()))
+            .and_then(handler::create_order_handler);
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/book/{:x}/order", market))
+            .json(&serde_json::json!({
+                "user": Address::random(),
+                "target_tracer": market,
+                "side": "Bid",
+                "price": "100",
+                "amount": "10",
+                "expiration": (Utc::now() + Duration::days(1)).timestamp(),
+                "created": Utc::now().timestamp(),
+                "signed_data": "0x",
+            }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let state: OmeState = markets.snapshot().await.unwrap();
+        assert_eq!(state.books()[&market].depth(), (0, 0));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[test]
+    pub fn journalled_book_configurations_remain_readable() {
+        /* entries journalled before partial updates always set both fields */
+        let event: JournalEvent = serde_json::from_str(
+            r#"{"ConfigureBook":{"market":"0x0000000000000000000000000000000000000001","self_trade_prevention":null,"domain_separator":null}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event,
+            JournalEvent::ConfigureBook {
+                market: Address::from_low_u64_be(1),
+                self_trade_prevention: Some(None),
+                domain_separator: Some(None),
+            }
+        );
+
+        /* whereas fields left unchanged are left out */
+        let partial: JournalEvent = JournalEvent::ConfigureBook {
+            market: Address::from_low_u64_be(1),
+            self_trade_prevention: Some(None),
+            domain_separator: None,
+        };
+        let text: String = serde_json::to_string(&partial).unwrap();
+        assert!(!text.contains("domain_separator"));
+        assert_eq!(
+            serde_json::from_str::<JournalEvent>(&text).unwrap(),
+            partial
+        );
+    }
+}
+
+#[cfg(test)]
+mod outbox_tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration, Utc};
+    use reqwest::StatusCode;
+    use tokio::sync::{broadcast, Mutex, MutexGuard};
+    use web3::types::{Address, H160, H256, U256};
+
+    use crate::book::{Match, MatchId, Reconciliation};
+    use crate::event::{self, EventSender, Fill, OrderEvent, OrderEventKind};
+    use crate::executioner::MockExecutioner;
+    use crate::journal::{Journal, JournalEvent};
+    use crate::market::{Market, Markets, Outcome};
+    use crate::order::{Order, OrderId, OrderSide};
+    use crate::outbox::{self, Delivery, DeliveryStatus, Outbox};
+    use crate::rpc::{self, MatchResponse, RpcError};
+    use crate::OmeState;
+
+    fn outbox_path() -> PathBuf {
+        std::env::temp_dir().join(format!("omeoutbox-{:x}", H256::random()))
+    }
+
+    fn order(market: Address, side: OrderSide) -> Order {
+        Order::new(
+            Address::random(),
+            market,
+            side,
+            100.into(),
+            10.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        )
+    }
+
+    fn matched() -> Match {
+        Match {
+            maker: order(Address::zero(), OrderSide::Ask),
+            taker: order(Address::zero(), OrderSide::Bid),
+            price: 100.into(),
+            amount: 10.into(),
+            number: 1,
+        }
+    }
+
+    #[test]
+    pub fn only_accepted_matches_are_confirmed() {
+        let path: PathBuf = outbox_path();
+        let now: DateTime<Utc> = Utc::now();
+        let events: EventSender = event::channel();
+        let mut subscriber: broadcast::Receiver<OrderEvent> =
+            events.subscribe();
+
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        let accepted: Match = matched();
+        let accepted_id: MatchId =
+            outbox.enqueue(accepted.clone(), 1, now).unwrap();
+        let rejected: MatchId = outbox.enqueue(matched(), 1, now).unwrap();
+        let failed: MatchId = outbox.enqueue(matched(), 1, now).unwrap();
+        outbox
+            .record(
+                accepted_id,
+                Ok(MatchResponse::Accepted(H160::random())),
+                now,
+            )
+            .unwrap();
+        outbox
+            .record(rejected, Ok(MatchResponse::Rejected(None)), now)
+            .unwrap();
+        outbox
+            .record(failed, Err(RpcError::HttpError), now)
+            .unwrap();
+
+        let attempted: Vec<Delivery> = [accepted_id, rejected, failed]
+            .iter()
+            .map(|id| outbox.delivery(*id).unwrap().clone())
+            .collect();
+        outbox::confirm(&events, &attempted, now);
+
+        let confirmed: Vec<OrderEvent> = vec![
+            subscriber.try_recv().unwrap(),
+            subscriber.try_recv().unwrap(),
+        ];
+        assert!(subscriber.try_recv().is_err());
+        assert!(confirmed
+            .iter()
+            .all(|event| event.kind == OrderEventKind::Confirmed
+                && event.match_id == Some(accepted_id)));
+        assert_eq!(confirmed[0].order_id, accepted.maker.id);
+        assert_eq!(
+            confirmed[0].fill,
+            Some(Fill {
+                price: 100.into(),
+                quantity: 10.into(),
+                counterparty: accepted.taker.id,
+            })
+        );
+        assert_eq!(confirmed[1].order_id, accepted.taker.id);
+        assert_eq!(
+            confirmed[1].fill.map(|fill| fill.counterparty),
+            Some(accepted.maker.id)
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn only_explicit_rejections_are_final() {
+        assert!(rpc::is_rejection(StatusCode::BAD_REQUEST));
+        assert!(rpc::is_rejection(StatusCode::FORBIDDEN));
+        assert!(rpc::is_rejection(StatusCode::UNPROCESSABLE_ENTITY));
+
+        /* these say nothing of the match, so it is retried */
+        assert!(!rpc::is_rejection(StatusCode::REQUEST_TIMEOUT));
+        assert!(!rpc::is_rejection(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!rpc::is_rejection(StatusCode::NOT_FOUND));
+        assert!(!rpc::is_rejection(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!rpc::is_rejection(StatusCode::OK));
+    }
+
+    #[test]
+    pub fn backoff_doubles_up_to_limit() {
+        assert_eq!(outbox::backoff(1), Duration::seconds(1));
+        assert_eq!(outbox::backoff(2), Duration::seconds(2));
+        assert_eq!(outbox::backoff(5), Duration::seconds(16));
+        assert_eq!(outbox::backoff(10), Duration::seconds(300));
+        assert_eq!(outbox::backoff(u32::MAX), Duration::seconds(300));
+    }
+
+    #[test]
+    pub fn enqueue_is_idempotent_across_reopening() {
+        let path: PathBuf = outbox_path();
+        let now: DateTime<Utc> = Utc::now();
+        let matched: Match = matched();
+
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        let id: MatchId = outbox.enqueue(matched.clone(), 1, now).unwrap();
+        assert_eq!(id, matched.id());
+        assert_eq!(outbox.enqueue(matched.clone(), 1, now).unwrap(), id);
+        assert_eq!(outbox.pending(), 1);
+        drop(outbox);
+
+        /* as when replaying the journal re-derives the same match */
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        assert_eq!(outbox.enqueue(matched, 1, now).unwrap(), id);
+        assert_eq!(outbox.pending(), 1);
+        assert_eq!(outbox.due(now).len(), 1);
+        assert_eq!(
+            outbox.delivery(id).unwrap().status,
+            DeliveryStatus::Pending
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn failed_deliveries_back_off_until_settled() {
+        let path: PathBuf = outbox_path();
+        let now: DateTime<Utc> = Utc::now();
+
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        let delivered: MatchId = outbox.enqueue(matched(), 1, now).unwrap();
+        let rejected: MatchId = outbox.enqueue(matched(), 1, now).unwrap();
+
+        outbox
+            .record(delivered, Err(RpcError::HttpError), now)
+            .unwrap();
+        outbox
+            .record(rejected, Ok(MatchResponse::Rejected(None)), now)
+            .unwrap();
+        assert!(outbox.due(now).is_empty());
+        assert_eq!(outbox.pending(), 1);
+        assert_eq!(
+            outbox.delivery(delivered).unwrap().next_attempt,
+            now + Duration::seconds(1)
+        );
+
+        /* the delay doubles with each further failure */
+        let retry: DateTime<Utc> = now + Duration::seconds(1);
+        assert_eq!(outbox.due(retry).len(), 1);
+        outbox
+            .record(delivered, Err(RpcError::InvalidResponse), retry)
+            .unwrap();
+        assert_eq!(
+            outbox.delivery(delivered).unwrap().next_attempt,
+            retry + Duration::seconds(2)
+        );
+
+        let transaction: H160 = H160::random();
+        outbox
+            .record(delivered, Ok(MatchResponse::Accepted(transaction)), retry)
+            .unwrap();
+        drop(outbox);
+
+        /* settled deliveries survive reopening but are never attempted */
+        let outbox: Outbox = Outbox::open(&path).unwrap();
+        assert!(outbox.due(now + Duration::days(1)).is_empty());
+        assert_eq!(outbox.pending(), 0);
+        assert_eq!(
+            outbox.delivery(delivered).unwrap().status,
+            DeliveryStatus::Delivered
+        );
+        assert_eq!(outbox.delivery(delivered).unwrap().attempts, 3);
+        assert_eq!(
+            outbox.delivery(delivered).unwrap().transaction,
+            Some(transaction)
+        );
+        assert_eq!(
+            outbox.delivery(rejected).unwrap().status,
+            DeliveryStatus::Rejected
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn torn_final_entry_is_ignored() {
+        let path: PathBuf = outbox_path();
+        let now: DateTime<Utc> = Utc::now();
+
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        let id: MatchId = outbox.enqueue(matched(), 1, now).unwrap();
+        drop(outbox);
+
+        let mut data: String = fs::read_to_string(&path).unwrap();
+        data.push_str("{\"id\":");
+        fs::write(&path, data).unwrap();
+
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        assert!(outbox.delivery(id).is_some());
+        let other: MatchId = outbox.enqueue(matched(), 1, now).unwrap();
+        drop(outbox);
+
+        let outbox: Outbox = Outbox::open(&path).unwrap();
+        assert!(outbox.delivery(id).is_some());
+        assert!(outbox.delivery(other).is_some());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn deliveries_are_only_forgotten_once_compacted_away() {
+        let path: PathBuf = outbox_path();
+        let now: DateTime<Utc> = Utc::now();
+
+        /* the first made before deliveries recorded their journal entries */
+        let sequences: Vec<Option<u64>> = vec![None, Some(2)]
+            .into_iter()
+            .chain(vec![Some(1); 3])
+            .chain(vec![Some(4); outbox::RETAINED_DELIVERIES])
+            .collect();
+        let deliveries: Vec<Delivery> = sequences
+            .into_iter()
+            .map(|sequence| {
+                let matched: Match = matched();
+                Delivery {
+                    id: matched.id(),
+                    matched,
+                    status: DeliveryStatus::Delivered,
+                    attempts: 1,
+                    created: now,
+                    next_attempt: now,
+                    transaction: Some(H160::random()),
+                    last_error: None,
+                    offender: None,
+                    reconciled: false,
+                    sequence,
+                }
+            })
+            .collect();
+        let data: String = deliveries
+            .iter()
+            .map(|delivery| serde_json::to_string(delivery).unwrap() + "\n")
+            .collect();
+        fs::write(&path, data).unwrap();
+        let known = |outbox: &Outbox| -> Vec<bool> {
+            deliveries[..6]
+                .iter()
+                .map(|delivery| outbox.delivery(delivery.id).is_some())
+                .collect()
+        };
+
+        /* every journal entry may yet be replayed */
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        outbox.recover(0, 3).unwrap();
+        assert_eq!(known(&outbox), vec![true; 6]);
+
+        outbox.release(1).unwrap();
+        assert_eq!(known(&outbox), vec![true, true, false, false, false, true]);
+
+        outbox.release(2).unwrap();
+        assert_eq!(
+            known(&outbox),
+            vec![true, false, false, false, false, true]
+        );
+
+        /* older deliveries are kept as long as the latest entry */
+        outbox.release(3).unwrap();
+        assert_eq!(
+            known(&outbox),
+            vec![false, false, false, false, false, true]
+        );
+        drop(outbox);
+
+        let mut outbox: Outbox = Outbox::open(&path).unwrap();
+        outbox.recover(3, 4).unwrap();
+        assert_eq!(
+            known(&outbox),
+            vec![false, false, false, false, false, true]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn matches_are_queued_as_books_make_them() {
+        let path: PathBuf = outbox_path();
+        let journal_path: PathBuf = path.with_extension("journal");
+        let outbox: Arc<Mutex<Outbox>> =
+            Arc::new(Mutex::new(Outbox::open(&path).unwrap()));
+        let markets: Markets = Markets::new(
+            OmeState::new(),
+            Arc::new(Mutex::new(Journal::open(&journal_path, 0).unwrap())),
+            outbox.clone(),
+            event::channel(),
+            Arc::new(MockExecutioner::new()),
+        );
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let ask: Order = order(market, OrderSide::Ask);
+        let bid: Order = order(market, OrderSide::Bid);
+        handle
+            .apply(JournalEvent::Submit {
+                market,
+                order: ask.clone(),
+            })
+            .await
+            .unwrap();
+        let ids: Vec<MatchId> = match handle
+            .apply(JournalEvent::Submit {
+                market,
+                order: bid.clone(),
+            })
+            .await
+            .unwrap()
+        {
+            Outcome::Executed { matches, .. } => matches,
+            _ => unreachable!(),
+        };
+        assert_eq!(ids.len(), 1);
+
+        {
+            let outbox: MutexGuard<Outbox> = outbox.lock().await;
+            let matched: &Match = &outbox.delivery(ids[0]).unwrap().matched;
+            assert_eq!(matched.maker.id, ask.id);
+            assert_eq!(matched.taker.id, bid.id);
+            assert_eq!(matched.amount, 10.into());
+            assert_eq!(outbox.deliveries_in(market).len(), 1);
+            assert!(outbox.deliveries_in(Address::random()).is_empty());
+        }
+
+        /* replaying the journal re-derives exactly the same match */
+        let replayed: Vec<Match> = OmeState::new()
+            .replay(Journal::entries(&journal_path).unwrap())
+            .await;
+        assert_eq!(
+            replayed.iter().map(Match::id).collect::<Vec<MatchId>>(),
+            ids
+        );
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&journal_path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn rejected_matches_are_rolled_back() {
+        let path: PathBuf = outbox_path();
+        let journal_path: PathBuf = path.with_extension("journal");
+        let outbox: Arc<Mutex<Outbox>> =
+            Arc::new(Mutex::new(Outbox::open(&path).unwrap()));
+        let events: EventSender = event::channel();
+        let mut subscriber: broadcast::Receiver<OrderEvent> =
+            events.subscribe();
+        let markets: Markets = Markets::new(
+            OmeState::new(),
+            Arc::new(Mutex::new(Journal::open(&journal_path, 0).unwrap())),
+            outbox.clone(),
+            events,
+            Arc::new(MockExecutioner::new()),
+        );
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let ask: Order = order(market, OrderSide::Ask);
+        let bid: Order = Order::new(
+            Address::random(),
+            market,
+            OrderSide::Bid,
+            100.into(),
+            4.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        );
+        for order in [ask.clone(), bid.clone()] {
+            handle
+                .apply(JournalEvent::Submit { market, order })
+                .await
+                .unwrap();
+        }
+
+        /* the submissions themselves are reported as they happen */
+        let reported: Vec<(OrderEventKind, OrderId)> = (0..4)
+            .map(|_| subscriber.try_recv().unwrap())
+            .map(|event| (event.kind, event.order_id))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                (OrderEventKind::Accepted, ask.id),
+                (OrderEventKind::Accepted, bid.id),
+                (OrderEventKind::PartiallyFilled, ask.id),
+                (OrderEventKind::Filled, bid.id),
+            ]
+        );
+
+        /* nothing to undo until the executioner rejects the match */
+        assert!(outbox::reconcile(&markets).await.is_empty());
+
+        let id: MatchId = outbox.lock().await.due(Utc::now())[0].id;
+        outbox
+            .lock()
+            .await
+            .record(id, Ok(MatchResponse::Rejected(None)), Utc::now())
+            .unwrap();
+
+        let reconciliations: Vec<Reconciliation> =
+            outbox::reconcile(&markets).await;
+        assert_eq!(reconciliations.len(), 1);
+        assert_eq!(reconciliations[0].restored, vec![ask.id]);
+        assert_eq!(reconciliations[0].ltp, U256::zero());
+        let ask_id: OrderId = ask.id;
+        assert_eq!(
+            handle
+                .inspect(move |book| book.order(ask_id).map(|o| o.remaining))
+                .await,
+            Ok(Some(10.into()))
+        );
+
+        /* clients learn that neither fill stands */
+        let corrected: Vec<OrderEvent> = vec![
+            subscriber.try_recv().unwrap(),
+            subscriber.try_recv().unwrap(),
+        ];
+        assert!(corrected
+            .iter()
+            .all(|event| event.kind == OrderEventKind::Corrected
+                && event.match_id == Some(id)));
+        assert_eq!(corrected[0].order_id, ask.id);
+        assert_eq!(corrected[1].order_id, bid.id);
+
+        /* each rejection is undone once, and survives a restart */
+        assert!(outbox.lock().await.delivery(id).unwrap().reconciled);
+        assert!(outbox::reconcile(&markets).await.is_empty());
+
+        let mut replayed: OmeState = OmeState::new();
+        replayed
+            .replay(Journal::entries(&journal_path).unwrap())
+            .await;
+        assert_eq!(replayed, markets.snapshot().await.unwrap());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&journal_path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn rematches_after_a_rollback_are_delivered() {
+        let path: PathBuf = outbox_path();
+        let journal_path: PathBuf = path.with_extension("journal");
+        let outbox: Arc<Mutex<Outbox>> =
+            Arc::new(Mutex::new(Outbox::open(&path).unwrap()));
+        let markets: Markets = Markets::new(
+            OmeState::new(),
+            Arc::new(Mutex::new(Journal::open(&journal_path, 0).unwrap())),
+            outbox.clone(),
+            event::channel(),
+            Arc::new(MockExecutioner::new()),
+        );
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let ask: Order = order(market, OrderSide::Ask);
+        let bid: Order = Order::new(
+            Address::random(),
+            market,
+            OrderSide::Bid,
+            100.into(),
+            4.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        );
+        for order in [ask.clone(), bid.clone()] {
+            handle
+                .apply(JournalEvent::Submit { market, order })
+                .await
+                .unwrap();
+        }
+
+        let rejected: MatchId = outbox.lock().await.due(Utc::now())[0].id;
+        outbox
+            .lock()
+            .await
+            .record(rejected, Ok(MatchResponse::Rejected(None)), Utc::now())
+            .unwrap();
+        assert_eq!(outbox::reconcile(&markets).await.len(), 1);
+
+        /* the bid was never really filled, so it may match the ask again */
+        let ids: Vec<MatchId> = match handle
+            .apply(JournalEvent::Submit {
+                market,
+                order: bid.clone(),
+            })
+            .await
+            .unwrap()
+        {
+            Outcome::Executed { matches, .. } => matches,
+            _ => unreachable!(),
+        };
+        assert_eq!(ids.len(), 1);
+        assert_ne!(ids[0], rejected);
+
+        let due: Vec<Delivery> = outbox.lock().await.due(Utc::now());
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, ids[0]);
+        assert_eq!(due[0].matched.maker.id, ask.id);
+        assert_eq!(due[0].matched.taker.id, bid.id);
+        assert_eq!(due[0].matched.amount, 4.into());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&journal_path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn matches_are_forwarded_in_order_of_matching() {
+        let path: PathBuf = outbox_path();
+        let journal_path: PathBuf = path.with_extension("journal");
+        let executioner: Arc<MockExecutioner> =
+            Arc::new(MockExecutioner::new());
+        let markets: Markets = Markets::new(
+            OmeState::new(),
+            Arc::new(Mutex::new(Journal::open(&journal_path, 0).unwrap())),
+            Arc::new(Mutex::new(Outbox::open(&path).unwrap())),
+            event::channel(),
+            executioner.clone(),
+        );
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let near: Order = order(market, OrderSide::Ask);
+        let far: Order = Order::new(
+            Address::random(),
+            market,
+            OrderSide::Ask,
+            101.into(),
+            10.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        );
+        let bid: Order = Order::new(
+            Address::random(),
+            market,
+            OrderSide::Bid,
+            101.into(),
+            15.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        );
+        for order in [far.clone(), near.clone(), bid.clone()] {
+            handle
+                .apply(JournalEvent::Submit { market, order })
+                .await
+                .unwrap();
+        }
+
+        /* the first attempt fails, and the second match is blamed on `far` */
+        executioner.respond(Err(RpcError::HttpError));
+        executioner.respond(Ok(MatchResponse::Rejected(Some(far.id))));
+
+        let attempted: Vec<Delivery> = outbox::deliver(
+            markets.outbox(),
+            markets.executioner(),
+            Utc::now(),
+        )
+        .await;
+        let submitted: Vec<Match> = executioner.submitted();
+        assert_eq!(
+            submitted
+                .iter()
+                .map(|matched| (matched.maker.id, matched.taker.id))
+                .collect::<Vec<(OrderId, OrderId)>>(),
+            vec![(near.id, bid.id), (far.id, bid.id)]
+        );
+        assert_eq!(
+            submitted
+                .iter()
+                .map(|matched| matched.amount)
+                .collect::<Vec<U256>>(),
+            vec![10.into(), 5.into()]
+        );
+        assert_eq!(
+            attempted
+                .iter()
+                .map(|delivery| delivery.status)
+                .collect::<Vec<DeliveryStatus>>(),
+            vec![DeliveryStatus::Pending, DeliveryStatus::Rejected]
+        );
+
+        /* the blamed order is cancelled rather than restored */
+        let reconciliations: Vec<Reconciliation> =
+            outbox::reconcile(&markets).await;
+        assert_eq!(reconciliations.len(), 1);
+        assert_eq!(
+            reconciliations[0].cancelled.as_ref().map(|order| order.id),
+            Some(far.id)
+        );
+        assert_eq!(handle.inspect(|book| book.depth()).await, Ok((0, 0)));
+
+        /* retries are only made once due */
+        assert!(outbox::deliver(
+            markets.outbox(),
+            markets.executioner(),
+            Utc::now()
+        )
+        .await
+        .is_empty());
+        let retried: Vec<Delivery> = outbox::deliver(
+            markets.outbox(),
+            markets.executioner(),
+            Utc::now() + Duration::seconds(1),
+        )
+        .await;
+        assert_eq!(retried.len(), 1);
+        assert_eq!(retried[0].status, DeliveryStatus::Delivered);
+        assert_eq!(executioner.submitted().len(), 3);
+        assert_eq!(executioner.submitted()[2], submitted[0]);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&journal_path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod signature_tests {
+    use std::str::FromStr;
+
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use web3::types::{Address, H256, U256};
+
+    use crate::order::{
+        self, Order, OrderSide, OrderType, PostOnly, SelfTradePrevention,
+        TimeInForce,
+    };
+    use crate::signature::{self, SignatureError};
+
+    /* signed with private key 0x4c0883a6...3f362318 */
+    const TRADER: &str = "2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const MARKET: &str = "61f5a3d36c10d7ea1d797e70f7486eb7ad177481";
+    const SIGNATURE: &str = "977101501d2a78f87b234bf7e2f5f7732325db54cc9548fe7eb83b6b8a3bf8e07e563c6cdeaf1b3eaceb4c3ed6c944c44a95852de21b247c65f0561cb9c2a4241c";
+
+    fn timestamp(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_utc(NaiveDateTime::from_timestamp(seconds, 0), Utc)
+    }
+
+    fn domain_separator() -> H256 {
+        signature::domain_separator(
+            "Tracer Protocol",
+            "1.0",
+            U256::from(42),
+            Address::from_str(MARKET).unwrap(),
+        )
+    }
+
+    fn signed_order() -> Order {
+        Order::new(
+            Address::from_str(TRADER).unwrap(),
+            Address::from_str(MARKET).unwrap(),
+            OrderSide::Bid,
+            U256::from(1000),
+            U256::from(5),
+            timestamp(4102444800),
+            timestamp(1623977157),
+            hex::decode(SIGNATURE).unwrap(),
+        )
+    }
+
+    #[test]
+    pub fn domain_separator_matches_eip712_example() {
+        /* the "Ether Mail" example of EIP-712 itself */
+        let separator: H256 = signature::domain_separator(
+            "Ether Mail",
+            "1",
+            U256::from(1),
+            Address::from_str("CcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC")
+                .unwrap(),
+        );
+
+        assert_eq!(
+            separator,
+            H256::from_str(
+                "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    pub fn signer_of_signed_order_is_recovered() {
+        let order: Order = signed_order();
+
+        assert_eq!(
+            signature::signer(domain_separator(), &order),
+            Ok(order.trader)
+        );
+        assert_eq!(signature::verify(domain_separator(), &order), Ok(()));
+
+        /* recovery IDs of 0 and 1 are accepted too */
+        let mut unprefixed: Order = order.clone();
+        unprefixed.signed_data[64] -= 27;
+        assert_eq!(signature::verify(domain_separator(), &unprefixed), Ok(()));
+    }
+
+    #[test]
+    pub fn tampered_orders_are_rejected() {
+        let repriced: Order = Order {
+            price: U256::from(1001),
+            ..signed_order()
+        };
+        assert_eq!(
+            signature::verify(domain_separator(), &repriced),
+            Err(SignatureError::WrongSigner)
+        );
+
+        let impersonated: Order = Order {
+            trader: Address::from_low_u64_be(1),
+            ..signed_order()
+        };
+        assert_eq!(
+            signature::verify(domain_separator(), &impersonated),
+            Err(SignatureError::WrongSigner)
+        );
+
+        /* signed for another market's domain */
+        assert_eq!(
+            signature::verify(H256::zero(), &signed_order()),
+            Err(SignatureError::WrongSigner)
+        );
+    }
+
+    #[test]
+    pub fn every_order_field_is_signed_and_identifies_the_order() {
+        let tampered: Vec<Order> = vec![
+            Order {
+                order_type: OrderType::Market,
+                ..signed_order()
+            },
+            Order {
+                max_slippage: Some(0),
+                ..signed_order()
+            },
+            Order {
+                time_in_force: TimeInForce::ImmediateOrCancel,
+                ..signed_order()
+            },
+            Order {
+                post_only: Some(PostOnly::Reject),
+                ..signed_order()
+            },
+            Order {
+                stop_price: Some(U256::from(900)),
+                ..signed_order()
+            },
+            Order {
+                display_quantity: Some(U256::from(1)),
+                ..signed_order()
+            },
+            Order {
+                self_trade_prevention: Some(SelfTradePrevention::CancelBoth),
+                ..signed_order()
+            },
+        ];
+
+        for order in tampered {
+            assert_eq!(
+                signature::verify(domain_separator(), &order),
+                Err(SignatureError::WrongSigner)
+            );
+            assert_ne!(order::order_id(&order), signed_order().id);
+        }
+
+        assert_eq!(
+            signed_order().id,
+            H256::from_str(
+                "5e4819c4dd08ad126bbfd6bbb3478eda99509fb80622a1077da507b04d79bdcc"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    pub fn malformed_signatures_are_rejected() {
+        let unsigned: Order = Order {
+            signed_data: vec![],
+            ..signed_order()
+        };
+        assert_eq!(
+            signature::verify(domain_separator(), &unsigned),
+            Err(SignatureError::InvalidLength)
+        );
+
+        let mut invalid_v: Order = signed_order();
+        invalid_v.signed_data[64] = 29;
+        assert_eq!(
+            signature::verify(domain_separator(), &invalid_v),
+            Err(SignatureError::InvalidRecoveryId)
+        );
+
+        let zeroed: Order = Order {
+            signed_data: vec![0; 64].into_iter().chain(vec![27]).collect(),
+            ..signed_order()
+        };
+        assert_eq!(
+            signature::verify(domain_separator(), &zeroed),
+            Err(SignatureError::InvalidSignature)
+        );
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use web3::types::H256;
+
+    use crate::registry::{TerminalOrders, Termination, TERMINAL_ORDERS};
+
+    #[test]
+    pub fn oldest_orders_are_forgotten_first() {
+        let mut registry: TerminalOrders = TerminalOrders::new();
+        let ids: Vec<H256> = (0..=TERMINAL_ORDERS as u64)
+            .map(H256::from_low_u64_be)
+            .collect();
+
+        for id in ids.iter() {
+            registry.record(*id, Termination::Filled);
+        }
+
+        assert_eq!(registry.len(), TERMINAL_ORDERS);
+        assert_eq!(registry.get(ids[0]), None);
+        assert_eq!(registry.get(ids[1]), Some(Termination::Filled));
+        assert_eq!(
+            registry.get(ids[TERMINAL_ORDERS]),
+            Some(Termination::Filled)
+        );
+    }
+
+    #[test]
+    pub fn first_termination_is_kept() {
+        let mut registry: TerminalOrders = TerminalOrders::new();
+        let id: H256 = H256::random();
+
+        registry.record(id, Termination::Cancelled);
+        registry.record(id, Termination::Filled);
+
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.get(id), Some(Termination::Cancelled));
+        assert_eq!(registry.forget(id), Some(Termination::Cancelled));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    pub fn registry_round_trip_keeps_order() {
+        let mut registry: TerminalOrders = TerminalOrders::new();
+        let ids: Vec<H256> = (0..3).map(|_| H256::random()).collect();
+        registry.record(ids[0], Termination::Filled);
+        registry.record(ids[1], Termination::Cancelled);
+        registry.record(ids[2], Termination::Filled);
+
+        let restored: TerminalOrders =
+            serde_json::from_str(&serde_json::to_string(&registry).unwrap())
+                .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&restored).unwrap(),
+            serde_json::to_value(&registry).unwrap()
+        );
+        assert_eq!(restored.get(ids[1]), Some(Termination::Cancelled));
+    }
+}
+
+#[cfg(test)]
+mod depth_tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use chrono::{Duration, Utc};
+    use tokio::sync::broadcast;
+    use tokio::sync::broadcast::error::TryRecvError;
+    use warp::test::WsClient;
+    use warp::Filter;
+    use web3::types::{Address, U256};
+
+    use super::market_helpers::{journal_path, spawn_markets, submit};
+    use crate::book::Book;
+    use crate::depth::{Depth, DepthMessage, Level, LevelChange};
+    use crate::handler;
+    use crate::journal::JournalEvent;
+    use crate::market::{Market, Markets};
+    use crate::order::{Order, OrderSide};
+
+    fn order(side: OrderSide, price: u64, qty: u64) -> Order {
+        Order::new(
+            Address::random(),
+            Address::zero(),
+            side,
+            price.into(),
+            qty.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        )
+    }
+
+    fn changes(message: DepthMessage) -> (u64, Vec<LevelChange>) {
+        match message {
+            DepthMessage::Update {
+                sequence, changes, ..
+            } => (sequence, changes),
+            _ => panic!("Expected an update, got {:?}", message),
+        }
+    }
+
+    fn change(side: OrderSide, price: u64, quantity: u64) -> LevelChange {
+        LevelChange {
+            side,
+            price: price.into(),
+            quantity: quantity.into(),
+        }
+    }
+
+    async fn receive(client: &mut WsClient) -> DepthMessage {
+        let message: warp::ws::Message = client.recv().await.unwrap();
+        serde_json::from_str(message.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    pub fn levels_only_count_visible_quantity() {
+        let mut book: Book = Book::new(Address::zero());
+        let mut iceberg: Order = order(OrderSide::Ask, 100, 10);
+        iceberg.display_quantity = Some(2.into());
+        iceberg.displayed = 2.into();
+        let plain: Order = order(OrderSide::Ask, 100, 3);
+        book.asks
+            .entry(iceberg.price)
+            .or_default()
+            .extend(vec![iceberg, plain]);
+
+        let levels = Depth::levels(&book, OrderSide::Ask);
+        assert_eq!(
+            levels.into_iter().collect::<Vec<(U256, U256)>>(),
+            vec![(100.into(), 5.into())]
+        );
+        assert!(Depth::levels(&book, OrderSide::Bid).is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn updates_follow_snapshot_in_sequence() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let (snapshot, mut updates): (
+            DepthMessage,
+            broadcast::Receiver<DepthMessage>,
+        ) = handle.depth().await.unwrap();
+        assert!(matches!(
+            snapshot,
+            DepthMessage::Snapshot { sequence: 0, ref bids, ref asks, .. }
+                if bids.is_empty() && asks.is_empty()
+        ));
+
+        let ask: Order = order(OrderSide::Ask, 100, 10);
+        submit(&handle, &ask).await;
+        submit(&handle, &order(OrderSide::Bid, 90, 5)).await;
+        submit(&handle, &order(OrderSide::Bid, 100, 4)).await;
+        handle
+            .apply(JournalEvent::Cancel {
+                market,
+                order_id: ask.id,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            changes(updates.recv().await.unwrap()),
+            (1, vec![change(OrderSide::Ask, 100, 10)])
+        );
+        assert_eq!(
+            changes(updates.recv().await.unwrap()),
+            (2, vec![change(OrderSide::Bid, 90, 5)])
+        );
+        assert_eq!(
+            changes(updates.recv().await.unwrap()),
+            (3, vec![change(OrderSide::Ask, 100, 6)])
+        );
+        assert_eq!(
+            changes(updates.recv().await.unwrap()),
+            (4, vec![change(OrderSide::Ask, 100, 0)])
+        );
+
+        /* commands that leave the levels as they were publish nothing */
+        assert!(handle
+            .apply(JournalEvent::Cancel {
+                market,
+                order_id: ask.id,
+            })
+            .await
+            .is_err());
+        handle.expire(Utc::now()).await.unwrap();
+        assert_eq!(updates.try_recv(), Err(TryRecvError::Empty));
+
+        /* a new snapshot picks up where the updates left off */
+        let (snapshot, _updates) = handle.depth().await.unwrap();
+        assert!(matches!(
+            snapshot,
+            DepthMessage::Snapshot { sequence: 4, ref bids, ref asks, .. }
+                if bids == &vec![Level { price: 90.into(), quantity: 5.into() }]
+                    && asks.is_empty()
+        ));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn depth_is_served_over_websocket() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::repeat_byte(1);
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+        submit(&handle, &order(OrderSide::Bid, 90, 5)).await;
+
+        let depth_markets: Markets = markets.clone();
+        let route = warp::path!("book" / Address / "depth")
+            .and(warp::ws())
+            .and(warp::any().map(move || depth_markets.clone()))
+            .and_then(handler::depth_handler);
+        let mut client = warp::test::ws()
+            .path(&format!("/book/{:x}/depth", market))
+            .handshake(route)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            receive(&mut client).await,
+            DepthMessage::Snapshot { sequence: 1, ref bids, .. }
+                if bids.len() == 1
+        ));
+
+        submit(&handle, &order(OrderSide::Ask, 110, 2)).await;
+        assert_eq!(
+            changes(receive(&mut client).await),
+            (2, vec![change(OrderSide::Ask, 110, 2)])
+        );
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+    use tokio::sync::Mutex;
+    use warp::http::StatusCode;
+    use warp::Filter;
+    use web3::types::{Address, H256};
+
+    use crate::auth::{self, Action, AuthError};
+    use crate::book::Book;
+    use crate::event;
+    use crate::executioner::MockExecutioner;
+    use crate::handler;
+    use crate::heartbeat::Heartbeats;
+    use crate::journal::Journal;
+    use crate::market::Markets;
+    use crate::order::{ExternalOrder, Order, OrderSide};
+    use crate::outbox::Outbox;
+    use crate::OmeState;
+
+    /* signed with private key 0x4c0883a6...3f362318 at 1623977157 */
+    const TRADER: &str = "2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const SUBSCRIBE_SIGNATURE: &str = "11ad576bb884e2306b8ee49cab1bf6946df72246fbc41ed029b47c7a91323df802fbc22190339ad288ef396171728f2c3c3519c24f74162db093ebe02294a11b1b";
+    const CANCEL_SIGNATURE: &str = "b0b5e26580111222ad1427dc0bd3341e541a31655442527dd57039c81b7ced3b50689038c03d6cb2997d3a8bc5ac0604fb4dbab7e61e14d3c4577a97f3af64331b";
+    const HEARTBEAT_SIGNATURE: &str = "c7cecaf3dfbeec8fb41801cf227b58af1840d07a529e39f5a91f678a74b46fba00e547bbb3581373f6c1e64538a6b1766de599fd7ccf32e7a22fbecf9b17e6401b"; /* timeout of 30 */
+    const INSPECT_SIGNATURE: &str = "360b3dc621b7c6d1e1591be53df0574d245be00eca710840361412d3e65daa7a6c0dfbeb141ebb7dac71efccf9c3b6e48379874825e5e7ea905cb75b7146e2091b";
+
+    fn timestamp(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_utc(NaiveDateTime::from_timestamp(seconds, 0), Utc)
+    }
+
+    #[test]
+    pub fn only_recent_challenges_signed_by_trader_authenticate() {
+        let trader: Address = Address::from_str(TRADER).unwrap();
+        let signed: DateTime<Utc> = timestamp(1623977157);
+        let signature: Vec<u8> = hex::decode(SUBSCRIBE_SIGNATURE).unwrap();
+
+        assert_eq!(
+            auth::challenge(Action::Subscribe, trader, signed),
+            "Subscribe to order events of \
+             0x2c7536e3605d9c16a7a3d7b1898e529396a65c23\nTimestamp: 1623977157"
+        );
+        assert_eq!(
+            auth::authenticate(
+                Action::Subscribe,
+                trader,
+                signed,
+                &signature,
+                signed + Duration::seconds(30)
+            ),
+            Ok(())
+        );
+
+        /* the signature soon expires */
+        assert_eq!(
+            auth::authenticate(
+                Action::Subscribe,
+                trader,
+                signed,
+                &signature,
+                signed + Duration::seconds(61)
+            ),
+            Err(AuthError::StaleTimestamp)
+        );
+
+        /* and authenticates neither anyone else nor any other time */
+        assert_eq!(
+            auth::authenticate(
+                Action::Subscribe,
+                Address::random(),
+                signed,
+                &signature,
+                signed
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+        let later: DateTime<Utc> = signed + Duration::seconds(1);
+        assert_eq!(
+            auth::authenticate(
+                Action::Subscribe,
+                trader,
+                later,
+                &signature,
+                later
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            auth::authenticate(
+                Action::Subscribe,
+                trader,
+                signed,
+                &signature[1..],
+                signed
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    pub fn signatures_only_authenticate_the_action_signed() {
+        let trader: Address = Address::from_str(TRADER).unwrap();
+        let signed: DateTime<Utc> = timestamp(1623977157);
+        let subscribe: Vec<u8> = hex::decode(SUBSCRIBE_SIGNATURE).unwrap();
+        let cancel: Vec<u8> = hex::decode(CANCEL_SIGNATURE).unwrap();
+
+        assert_eq!(
+            auth::challenge(Action::Cancel, trader, signed),
+            "Cancel orders of \
+             0x2c7536e3605d9c16a7a3d7b1898e529396a65c23\nTimestamp: 1623977157"
+        );
+        assert_eq!(
+            auth::authenticate(Action::Cancel, trader, signed, &cancel, signed),
+            Ok(())
+        );
+
+        /* a subscription does not authorise cancellations, nor vice versa */
+        assert_eq!(
+            auth::authenticate(
+                Action::Cancel,
+                trader,
+                signed,
+                &subscribe,
+                signed
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            auth::authenticate(
+                Action::Subscribe,
+                trader,
+                signed,
+                &cancel,
+                signed
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    pub fn heartbeat_signatures_only_authenticate_their_timeout() {
+        let trader: Address = Address::from_str(TRADER).unwrap();
+        let signed: DateTime<Utc> = timestamp(1623977157);
+        let signature: Vec<u8> = hex::decode(HEARTBEAT_SIGNATURE).unwrap();
+
+        assert_eq!(
+            auth::challenge(Action::Heartbeat { timeout: 30 }, trader, signed),
+            "Heartbeat of 0x2c7536e3605d9c16a7a3d7b1898e529396a65c23 with \
+             timeout 30\nTimestamp: 1623977157"
+        );
+        assert_eq!(
+            auth::authenticate(
+                Action::Heartbeat { timeout: 30 },
+                trader,
+                signed,
+                &signature,
+                signed
+            ),
+            Ok(())
+        );
+
+        /* the heartbeat cannot be replayed to disarm the switch */
+        assert_eq!(
+            auth::authenticate(
+                Action::Heartbeat { timeout: 0 },
+                trader,
+                signed,
+                &signature,
+                signed
+            ),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[tokio::test]
+    pub async fn unauthenticated_heartbeats_are_refused() {
+        let heartbeats: Arc<Mutex<Heartbeats>> =
+            Arc::new(Mutex::new(Heartbeats::new()));
+        let route_heartbeats: Arc<Mutex<Heartbeats>> = heartbeats.clone();
+        let route = warp::path!("heartbeat")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(warp::any().map(move || route_heartbeats.clone()))
+            .and_then(handler::heartbeat_handler);
+
+        /* a valid signature, but of a challenge long since expired */
+        let response = warp::test::request()
+            .method("POST")
+            .path("/heartbeat")
+            .json(&serde_json::json!({
+                "trader": format!("0x{}", TRADER),
+                "timeout": 30,
+                "timestamp": 1623977157,
+                "signature": format!("0x{}", HEARTBEAT_SIGNATURE),
+            }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let trader: Address = Address::from_str(TRADER).unwrap();
+        assert_eq!(heartbeats.lock().await.deadline(trader), None);
+    }
+
+    #[tokio::test]
+    pub async fn mass_cancels_must_name_and_be_signed_by_a_trader() {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("omeauth-{:x}", H256::random()));
+        let trader: Address = Address::from_str(TRADER).unwrap();
+
+        let mut book: Book = Book::new(Address::zero());
+        let order: Order = Order::new(
+            trader,
+            Address::zero(),
+            OrderSide::Bid,
+            100.into(),
+            10.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        );
+        book.bids.entry(order.price).or_default().push_back(order);
+        book.reindex();
+        let mut state: OmeState = OmeState::new();
+        state.add_book(book);
+
+        let journal: Journal = Journal::open(&path, 0).unwrap();
+        let outbox: Outbox =
+            Outbox::open(&path.with_extension("outbox")).unwrap();
+        let markets: Markets = Markets::new(
+            state,
+            Arc::new(Mutex::new(journal)),
+            Arc::new(Mutex::new(outbox)),
+            event::channel(),
+            Arc::new(MockExecutioner::new()),
+        );
+        let route_markets: Markets = markets.clone();
+        let route = warp::path!("order")
+            .and(warp::delete())
+            .and(warp::query())
+            .and(warp::any().map(move || route_markets.clone()))
+            .and_then(handler::destroy_orders_handler);
+
+        /* cancelling the orders of every trader is no longer possible */
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/order")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        /* a valid signature, but of a challenge long since expired */
+        let response = warp::test::request()
+            .method("DELETE")
+            .path(&format!(
+                "/order?trader=0x{}&timestamp=1623977157&signature=0x{}",
+                TRADER, CANCEL_SIGNATURE
+            ))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        /* and the order of the trader still rests */
+        let state: OmeState = markets.snapshot().await.unwrap();
+        assert_eq!(state.books()[&Address::zero()].depth(), (1, 0));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn only_traders_are_shown_their_own_reserve() {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("omeauth-{:x}", H256::random()));
+        let trader: Address = Address::from_str(TRADER).unwrap();
+        let signed: DateTime<Utc> = timestamp(1623977157);
+        let inspect: Vec<u8> = hex::decode(INSPECT_SIGNATURE).unwrap();
+
+        assert_eq!(
+            auth::challenge(Action::Inspect, trader, signed),
+            "Inspect orders of \
+             0x2c7536e3605d9c16a7a3d7b1898e529396a65c23\nTimestamp: 1623977157"
+        );
+        assert_eq!(
+            auth::authenticate(
+                Action::Inspect,
+                trader,
+                signed,
+                &inspect,
+                signed
+            ),
+            Ok(())
+        );
+
+        let mut book: Book = Book::new(Address::zero());
+        let mut order: Order = Order::new(
+            trader,
+            Address::zero(),
+            OrderSide::Ask,
+            100.into(),
+            50.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![0xaa; 65],
+        );
+        order.display_quantity = Some(10.into());
+        order.replenish();
+        book.asks
+            .entry(order.price)
+            .or_default()
+            .push_back(order.clone());
+        book.reindex();
+        let mut state: OmeState = OmeState::new();
+        state.add_book(book);
+
+        let journal: Journal = Journal::open(&path, 0).unwrap();
+        let outbox: Outbox =
+            Outbox::open(&path.with_extension("outbox")).unwrap();
+        let markets: Markets = Markets::new(
+            state,
+            Arc::new(Mutex::new(journal)),
+            Arc::new(Mutex::new(outbox)),
+            event::channel(),
+            Arc::new(MockExecutioner::new()),
+        );
+        let route = warp::path!("book" / Address / Address)
+            .and(warp::get())
+            .and(warp::query())
+            .and(warp::any().map(move || markets.clone()))
+            .and_then(handler::market_user_orders_handler);
+
+        /* anyone may see the order, but only its visible slice */
+        let response = warp::test::request()
+            .path(&format!("/book/{:x}/{}", Address::zero(), TRADER))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let orders: Vec<ExternalOrder> =
+            serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            orders,
+            vec![ExternalOrder::from(Order {
+                quantity: 10.into(),
+                remaining: 10.into(),
+                ..order.clone()
+            })]
+        );
+
+        /* whereas claiming to be the trader takes a recent signature */
+        let response = warp::test::request()
+            .path(&format!(
+                "/book/{:x}/{}?timestamp=1623977157&signature=0x{}",
+                Address::zero(),
+                TRADER,
+                INSPECT_SIGNATURE
+            ))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = warp::test::request()
+            .path(&format!(
+                "/book/{:x}/{}?signature=0x{}",
+                Address::zero(),
+                TRADER,
+                INSPECT_SIGNATURE
+            ))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod feed_tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    use chrono::{Duration, Utc};
+    use tokio::sync::broadcast;
+    use warp::http::StatusCode;
+    use warp::test::WsClient;
+    use warp::ws::Ws;
+    use warp::Filter;
+    use web3::types::{Address, H256};
+
+    use super::market_helpers::{
+        journal_path, spawn_markets, spawn_markets_of, submit,
+    };
+    use crate::event::{self, EventSender, Fill, OrderEvent, OrderEventKind};
+    use crate::feed;
+    use crate::handler;
+    use crate::journal::JournalEvent;
+    use crate::market::{Market, Markets};
+    use crate::order::{Order, OrderSide, TimeInForce};
+    use crate::signature;
+    use crate::OmeState;
+
+    /* signed with private key 0x4c0883a6...3f362318 */
+    const TRADER: &str = "2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const SIGNATURE: &str = "11ad576bb884e2306b8ee49cab1bf6946df72246fbc41ed029b47c7a91323df802fbc22190339ad288ef396171728f2c3c3519c24f74162db093ebe02294a11b1b";
+
+    fn order(trader: Address, side: OrderSide, qty: u64) -> Order {
+        Order::new(
+            trader,
+            Address::zero(),
+            side,
+            100.into(),
+            qty.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        )
+    }
+
+    fn drain(
+        subscriber: &mut broadcast::Receiver<OrderEvent>,
+    ) -> Vec<OrderEvent> {
+        let mut events: Vec<OrderEvent> = vec![];
+        while let Ok(t) = subscriber.try_recv() {
+            events.push(t);
+        }
+        events
+    }
+
+    #[test]
+    pub fn personal_digest_matches_ethereum_clients() {
+        assert_eq!(
+            signature::personal_digest(b"Hello World"),
+            H256::from_str(
+                "a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+            )
+            .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn fills_and_cancellations_are_published() {
+        let path: PathBuf = journal_path();
+        let events: EventSender = event::channel();
+        let mut subscriber: broadcast::Receiver<OrderEvent> =
+            events.subscribe();
+        let markets: Markets = spawn_markets_of(OmeState::new(), &path, events);
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let ask: Order = order(Address::random(), OrderSide::Ask, 10);
+        let bid: Order = order(Address::random(), OrderSide::Bid, 4);
+        submit(&handle, &ask).await;
+        submit(&handle, &bid).await;
+
+        let published: Vec<OrderEvent> = drain(&mut subscriber);
+        assert_eq!(
+            published
+                .iter()
+                .map(|event| (event.kind, event.order_id, event.trader))
+                .collect::<Vec<_>>(),
+            vec![
+                (OrderEventKind::Accepted, ask.id, ask.trader),
+                (OrderEventKind::Accepted, bid.id, bid.trader),
+                (OrderEventKind::PartiallyFilled, ask.id, ask.trader),
+                (OrderEventKind::Filled, bid.id, bid.trader),
+            ]
+        );
+        assert_eq!(
+            published[2].fill,
+            Some(Fill {
+                price: 100.into(),
+                quantity: 4.into(),
+                counterparty: bid.id,
+            })
+        );
+        assert_eq!(
+            published[3].fill.map(|fill| fill.counterparty),
+            Some(ask.id)
+        );
+        assert_eq!(published[2].match_id, published[3].match_id);
+
+        /* an immediate-or-cancel remainder is cancelled straight away */
+        let ioc: Order = Order {
+            time_in_force: TimeInForce::ImmediateOrCancel,
+            ..order(Address::random(), OrderSide::Bid, 8)
+        };
+        submit(&handle, &ioc).await;
+        assert_eq!(
+            drain(&mut subscriber)
+                .iter()
+                .map(|event| (event.kind, event.order_id))
+                .collect::<Vec<_>>(),
+            vec![
+                (OrderEventKind::Accepted, ioc.id),
+                (OrderEventKind::Filled, ask.id),
+                (OrderEventKind::PartiallyFilled, ioc.id),
+                (OrderEventKind::Cancelled, ioc.id),
+            ]
+        );
+
+        /* as is an order cancelled by its trader */
+        let resting: Order = order(Address::random(), OrderSide::Ask, 3);
+        submit(&handle, &resting).await;
+        handle
+            .apply(JournalEvent::Cancel {
+                market,
+                order_id: resting.id,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            drain(&mut subscriber)
+                .iter()
+                .map(|event| (event.kind, event.order_id))
+                .collect::<Vec<_>>(),
+            vec![
+                (OrderEventKind::Accepted, resting.id),
+                (OrderEventKind::Cancelled, resting.id),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn subscribers_only_receive_their_own_events() {
+        let path: PathBuf = journal_path();
+        let events: EventSender = event::channel();
+        let markets: Markets =
+            spawn_markets_of(OmeState::new(), &path, events.clone());
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let trader: Address = Address::random();
+        let route = warp::path!("trader" / Address / "events")
+            .and(warp::ws())
+            .map(move |trader: Address, ws: Ws| {
+                let events: broadcast::Receiver<OrderEvent> =
+                    events.subscribe();
+                ws.on_upgrade(move |socket| feed::serve(socket, trader, events))
+            });
+        let mut client: WsClient = warp::test::ws()
+            .path(&format!("/trader/{:x}/events", trader))
+            .handshake(route)
+            .await
+            .unwrap();
+
+        let other: Order = order(Address::random(), OrderSide::Ask, 10);
+        let own: Order = order(trader, OrderSide::Bid, 4);
+        submit(&handle, &other).await;
+        submit(&handle, &own).await;
+
+        let mut received: Vec<(OrderEventKind, Option<Fill>)> = vec![];
+        for _ in 0..2 {
+            let message: warp::ws::Message = client.recv().await.unwrap();
+            let event: OrderEvent =
+                serde_json::from_str(message.to_str().unwrap()).unwrap();
+            assert_eq!(event.order_id, own.id);
+            received.push((event.kind, event.fill));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (OrderEventKind::Accepted, None),
+                (
+                    OrderEventKind::Filled,
+                    Some(Fill {
+                        price: 100.into(),
+                        quantity: 4.into(),
+                        counterparty: other.id,
+                    })
+                ),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn unauthenticated_subscriptions_are_refused() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let route = warp::path!("trader" / Address / "events")
+            .and(warp::query())
+            .and(warp::ws())
+            .and(warp::any().map(move || markets.clone()))
+            .and_then(handler::feed_handler);
+
+        /* a valid signature, but of a challenge long since expired */
+        let response = warp::test::request()
+            .path(&format!(
+                "/trader/{}/events?timestamp=1623977157&signature=0x{}",
+                TRADER, SIGNATURE
+            ))
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tape_tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use chrono::{DateTime, Duration, Utc};
+    use tokio::sync::broadcast;
+    use warp::http::StatusCode;
+    use warp::sse::Event;
+    use warp::Filter;
+    use web3::futures::StreamExt;
+    use web3::types::Address;
+
+    use super::market_helpers::{journal_path, spawn_markets, submit};
+    use crate::book::Match;
+    use crate::handler;
+    use crate::market::{Market, Markets};
+    use crate::order::{Order, OrderSide};
+    use crate::tape::{
+        self, Backlog, Tape, TapeError, TradeId, TradeReport, TAPE_LENGTH,
+    };
+
+    fn order(side: OrderSide, price: u64, qty: u64) -> Order {
+        Order::new(
+            Address::random(),
+            Address::zero(),
+            side,
+            price.into(),
+            qty.into(),
+            Utc::now() + Duration::days(1),
+            Utc::now(),
+            vec![],
+        )
+    }
+
+    fn matched() -> Match {
+        Match {
+            maker: order(OrderSide::Ask, 100, 10),
+            taker: order(OrderSide::Bid, 100, 10),
+            price: 100.into(),
+            amount: 10.into(),
+            number: 1,
+        }
+    }
+
+    #[test]
+    pub fn trade_ids_are_ordered_by_journal_entry_then_index() {
+        let id: TradeId = "42-3".parse().unwrap();
+        assert_eq!(
+            id,
+            TradeId {
+                sequence: 42,
+                index: 3
+            }
+        );
+        assert_eq!(id.to_string(), "42-3");
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"42-3\"");
+        assert_eq!(serde_json::from_str::<TradeId>("\"42-3\"").unwrap(), id);
+
+        assert!("41-9".parse::<TradeId>().unwrap() < id);
+        assert!(id < "42-10".parse::<TradeId>().unwrap());
+        assert!(id < "43-0".parse::<TradeId>().unwrap());
+
+        for invalid in ["", "42", "42-", "-3", "a-3", "42-3-1"].iter() {
+            assert_eq!(
+                invalid.parse::<TradeId>(),
+                Err(TapeError::InvalidTradeId)
+            );
+        }
+    }
+
+    #[test]
+    pub fn tape_only_keeps_most_recent_trades() {
+        let now: DateTime<Utc> = Utc::now();
+        let mut tape: Tape = Tape::new(0);
+        let (backlog, mut trades): (Backlog, broadcast::Receiver<TradeReport>) =
+            tape.subscribe(None);
+        assert_eq!(backlog, Backlog::default());
+
+        let matches: Vec<Match> = vec![matched(), matched()];
+        tape.record(1, &matches, now);
+
+        /* every trade is published as it is recorded */
+        let first: TradeReport = trades.try_recv().unwrap();
+        assert_eq!(
+            first,
+            TradeReport {
+                id: TradeId {
+                    sequence: 1,
+                    index: 0
+                },
+                market: Address::zero(),
+                price: 100.into(),
+                quantity: 10.into(),
+                aggressor: OrderSide::Bid,
+                maker: matches[0].maker.id,
+                taker: matches[0].taker.id,
+                timestamp: now,
+            }
+        );
+
+        assert_eq!(trades.try_recv().unwrap().id.index, 1);
+
+        /* but only the most recent are kept for resumption */
+        for sequence in 2..=(TAPE_LENGTH as u64 / 2 + 1) {
+            tape.record(sequence, &matches, now);
+        }
+        let kept: Vec<TradeReport> = tape.since(first.id);
+        assert_eq!(kept.len(), TAPE_LENGTH);
+        assert_eq!(
+            kept[0].id,
+            TradeId {
+                sequence: 2,
+                index: 0
+            }
+        );
+        let last: TradeId = kept[TAPE_LENGTH - 1].id;
+        assert!(tape.since(last).is_empty());
+        assert_eq!(
+            tape.subscribe(Some(kept[1].id)).0,
+            Backlog {
+                missed: None,
+                trades: kept[2..].to_vec(),
+            }
+        );
+
+        /* a subscriber resuming from a forgotten trade is told so */
+        assert_eq!(
+            tape.subscribe(Some(first.id)).0,
+            Backlog {
+                missed: Some(first.id),
+                trades: kept,
+            }
+        );
+    }
+
+    #[tokio::test]
+    pub async fn trades_from_before_a_restart_are_missed() {
+        /* the book reflected every journal entry up to the fifth */
+        let mut tape: Tape = Tape::new(5);
+        tape.record(7, &[matched()], Utc::now());
+
+        let before: TradeId = TradeId {
+            sequence: 5,
+            index: 0,
+        };
+        let (backlog, trades): (Backlog, broadcast::Receiver<TradeReport>) =
+            tape.subscribe(Some(before));
+        assert_eq!(backlog.missed, Some(before));
+        assert_eq!(backlog.trades.len(), 1);
+
+        /* the gap is announced before the trades kept */
+        let streamed: Vec<Event> = tape::events(backlog, trades)
+            .take(2)
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+        let (gap, kept): (String, String) =
+            (streamed[0].to_string(), streamed[1].to_string());
+        assert!(gap.contains("event:gap\n"));
+        assert!(gap.contains(r#"data:{"last_event_id":"5-0"}"#));
+        assert!(!gap.contains("id:"));
+        assert!(kept.contains("id:7-0\n"));
+
+        /* whereas trades made since the restart are not */
+        let after: TradeId = TradeId {
+            sequence: 6,
+            index: 0,
+        };
+        assert_eq!(tape.subscribe(Some(after)).0.missed, None);
+    }
+
+    #[tokio::test]
+    pub async fn trades_resume_after_last_trade_received() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::zero();
+        markets.add(market).await.unwrap();
+        let handle: Market = markets.get(market).await.unwrap();
+
+        let (backlog, trades): (Backlog, broadcast::Receiver<TradeReport>) =
+            handle.trades(None).await.unwrap();
+        assert_eq!(backlog, Backlog::default());
+
+        let first: Order = order(OrderSide::Bid, 100, 5);
+        let second: Order = order(OrderSide::Bid, 101, 5);
+        let ask: Order = order(OrderSide::Ask, 100, 8);
+        submit(&handle, &first).await;
+        submit(&handle, &second).await;
+        submit(&handle, &ask).await;
+
+        /* the ask (i.e., the fourth journal entry) trades against both bids,
+         * best price first */
+        let streamed: Vec<Event> = tape::events(backlog, trades)
+            .take(2)
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+        let (to_second, to_first): (String, String) =
+            (streamed[0].to_string(), streamed[1].to_string());
+        assert!(to_second.contains("event:trade\n"));
+        assert!(to_second.contains("id:4-0\n"));
+        assert!(to_first.contains("id:4-1\n"));
+
+        let report: TradeReport = serde_json::from_str(
+            to_second
+                .lines()
+                .find_map(|line| line.strip_prefix("data:"))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(report.price, 101.into());
+        assert_eq!(report.quantity, 5.into());
+        assert_eq!(report.aggressor, OrderSide::Ask);
+        assert_eq!((report.maker, report.taker), (second.id, ask.id));
+
+        /* a subscriber that missed the second trade picks up from there */
+        let (backlog, _trades): (Backlog, broadcast::Receiver<TradeReport>) =
+            handle.trades(Some(report.id)).await.unwrap();
+        assert_eq!(backlog.missed, None);
+        assert_eq!(backlog.trades.len(), 1);
+        assert_eq!(backlog.trades[0].maker, first.id);
+        assert_eq!(backlog.trades[0].quantity, 3.into());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn invalid_subscriptions_are_refused() {
+        let path: PathBuf = journal_path();
+        let markets: Markets = spawn_markets(&path);
+        let market: Address = Address::repeat_byte(1);
+        markets.add(market).await.unwrap();
+        let route = warp::path!("book" / Address / "trades" / "stream")
+            .and(warp::get())
+            .and(warp::sse::last_event_id::<String>())
+            .and(warp::any().map(move || markets.clone()))
+            .and_then(handler::trades_handler);
+
+        let response = warp::test::request()
+            .path(&format!("/book/{:x}/trades/stream", market))
+            .header("last-event-id", "yesterday")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = warp::test::request()
+            .path(&format!("/book/{:x}/trades/stream", Address::zero()))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("outbox")).unwrap();
+    }
 }
@@ -0,0 +1,188 @@
+//! Contains logic and type definitions for stop orders awaiting activation
+//!
+//! Stop orders rest in a per-market trigger book, keyed by their stop price,
+//! until the last traded price of the market reaches it. At that point they
+//! are submitted to the order book proper as ordinary (market or limit)
+//! orders.
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::order::{ExternalOrder, Order, OrderId, OrderSide};
+
+/// Represents the stop orders of a particular Tracer market
+#[derive(Clone, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+pub struct TriggerBook {
+    pub bids: BTreeMap<U256, VecDeque<Order>>, /* buy stops, by stop price */
+    pub asks: BTreeMap<U256, VecDeque<Order>>, /* sell stops, by stop price */
+}
+
+impl TriggerBook {
+    /// Constructor for the `TriggerBook` type
+    pub fn new() -> Self {
+        Self {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    /// Returns whether `order` is activated by a last traded price of `ltp`
+    ///
+    /// Buy stops activate once the market trades at or above their stop
+    /// price, sell stops once it trades at or below it. A market that has
+    /// never traded activates nothing.
+    pub fn is_triggered(order: &Order, ltp: U256) -> bool {
+        match order.stop_price {
+            Some(stop) if !ltp.is_zero() => match order.side {
+                OrderSide::Bid => ltp >= stop,
+                OrderSide::Ask => ltp <= stop,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns an iterator over every stop order in the trigger book
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.bids.values().chain(self.asks.values()).flatten()
+    }
+
+    /// Returns a reference to the stop order matching the provided order ID
+    pub fn order(&self, id: OrderId) -> Option<&Order> {
+        self.orders().find(|order| order.id == id)
+    }
+
+    /// Stores `order` until it is triggered
+    ///
+    /// Orders without a stop price are ignored.
+    pub fn add_order(&mut self, order: Order) {
+        let stop: U256 = match order.stop_price {
+            Some(t) => t,
+            None => return,
+        };
+
+        info!("Adding stop {}...", order);
+
+        match order.side {
+            OrderSide::Bid => self.bids.entry(stop),
+            OrderSide::Ask => self.asks.entry(stop),
+        }
+        .or_insert_with(VecDeque::new)
+        .push_back(order);
+    }
+
+    /// Removes the stop order matching the provided order ID, returning it
+    pub fn cancel(&mut self, id: OrderId) -> Option<Order> {
+        let mut cancelled: Option<Order> = None;
+
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            if let Some(index) = orders.iter().position(|order| order.id == id)
+            {
+                cancelled = orders.remove(index);
+                break;
+            }
+        }
+
+        if let Some(order) = &cancelled {
+            info!("Cancelled stop {}", order);
+            self.prune();
+        }
+
+        cancelled
+    }
+
+    /// Removes every stop order satisfying `predicate`, returning them
+    pub fn cancel_where<P>(&mut self, predicate: P) -> Vec<Order>
+    where
+        P: Fn(&Order) -> bool,
+    {
+        let mut cancelled: Vec<Order> = vec![];
+
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            cancelled.extend(orders.iter().filter(|o| predicate(o)).cloned());
+            orders.retain(|order| !predicate(order));
+        }
+
+        if !cancelled.is_empty() {
+            self.prune();
+        }
+
+        cancelled
+    }
+
+    /// Removes and returns the next stop order activated by a last traded
+    /// price of `ltp`, if any
+    ///
+    /// Buy stops are activated before sell stops. Within a side, stops
+    /// nearest the price at which the market traded before reaching `ltp`
+    /// (i.e., the lowest buy stop and the highest sell stop) are activated
+    /// first, with stops sharing a stop price activated in order of arrival.
+    pub fn next(&mut self, ltp: U256) -> Option<Order> {
+        if ltp.is_zero() {
+            return None;
+        }
+
+        let (side, stop): (OrderSide, U256) =
+            match (self.bids.first_key_value(), self.asks.last_key_value()) {
+                (Some((stop, _)), _) if ltp >= *stop => (OrderSide::Bid, *stop),
+                (_, Some((stop, _))) if ltp <= *stop => (OrderSide::Ask, *stop),
+                _ => return None,
+            };
+
+        let levels: &mut BTreeMap<U256, VecDeque<Order>> = match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+        let order: Option<Order> = levels.get_mut(&stop)?.pop_front();
+        self.prune();
+
+        /* once activated, the order is an ordinary order */
+        order.map(|order| Order {
+            stop_price: None,
+            ..order
+        })
+    }
+
+    /// Removes every stop order that has expired as of `now`
+    ///
+    /// Returns the orders removed.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<Order> {
+        let mut expired: Vec<Order> = vec![];
+
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            expired.extend(
+                orders.iter().filter(|order| order.is_expired(now)).cloned(),
+            );
+            orders.retain(|order| !order.is_expired(now));
+        }
+
+        if !expired.is_empty() {
+            self.prune();
+        }
+
+        expired
+    }
+
+    fn prune(&mut self) {
+        self.bids.retain(|_stop, orders| !orders.is_empty());
+        self.asks.retain(|_stop, orders| !orders.is_empty());
+    }
+}
+
+/// Represents a stop order as presented to API consumers
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ExternalStopOrder {
+    #[serde(flatten)]
+    pub order: ExternalOrder,
+    pub stop_price: String,
+}
+
+impl From<Order> for ExternalStopOrder {
+    fn from(value: Order) -> Self {
+        Self {
+            stop_price: value.stop_price.unwrap_or_default().to_string(),
+            order: value.into(),
+        }
+    }
+}
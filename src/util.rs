@@ -1,28 +1,20 @@
-use std::fs;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
 
 use ethereum_types::U256;
 use serde::de::{Error, Unexpected};
 use serde::{Deserialize, Deserializer, Serializer};
-
-use crate::state::OmeState;
+use tokio::signal::unix::{signal, SignalKind};
 
 /// Helper to convert from hexadecimal strings to decimal strings
 ///
 /// This is necessary to override serde's defaults for the underlying field
-/// types we're using.
+/// types we're using. The full 256-bit value is preserved, so that values
+/// written by this function are always readable by `from_hex_de`.
 pub fn from_hex_se<S>(x: &U256, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    /* try to convert to an unsigned 128-bit integer, otherwise strip high bits */
-    let casted_val: u128 = match *x {
-        x if x <= U256::from(u128::MAX) => x.as_u128(),
-        _ => x.low_u128(),
-    };
-
-    serializer.serialize_u128(casted_val)
+    serializer.serialize_str(&x.to_string())
 }
 
 /// Helper to convert from hexadecimal strings to decimal strings
@@ -42,10 +34,70 @@ where
     })
 }
 
+/// Helper to convert from optional hexadecimal strings to optional decimal
+/// strings
+///
+/// See `from_hex_se`.
+pub fn from_hex_opt_se<S>(
+    x: &Option<U256>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match x {
+        Some(t) => from_hex_se(t, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Helper to convert from optional hexadecimal strings to optional decimal
+/// strings
+///
+/// See `from_hex_de`.
+pub fn from_hex_opt_de<'de, D>(
+    deserializer: D,
+) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    s.map(|t| {
+        U256::from_dec_str(&t).map_err(|_e| {
+            D::Error::invalid_type(
+                Unexpected::Other("non-decimal string"),
+                &"decimal string",
+            )
+        })
+    })
+    .transpose()
+}
+
+/// Helper to tell a field set to `null` apart from a field left out
+///
+/// Along with `#[serde(default)]`, a field left out deserialises to `None`,
+/// whereas a field set to `null` deserialises to `Some(None)`.
+pub fn nullable_de<'de, D, T>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
 pub fn is_existing_state(path: &Path) -> bool {
     path.exists()
 }
 
-pub fn dump_state(state: Arc<Mutex<OmeState>>, path: &Path) {
-    fs::write(path, serde_json::to_string(&state).unwrap()).unwrap()
+/// Resolves once the process receives either SIGINT or SIGTERM
+pub async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate())
+        .expect("Failed to install SIGTERM handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+    }
 }